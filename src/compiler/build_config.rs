@@ -1,18 +1,53 @@
+use std::path::PathBuf;
+
+pub enum Emit {
+    Ast,
+    Cfg
+}
+
 pub struct BuildConfig {
-    configs: Vec<String>,
-    line_length: u16,
-    line_count: u16,
+    pub configs: Vec<String>,
+    pub line_length: u16,
+    pub line_count: u16,
+    pub max_unroll: u16,
+    pub source_map: Option<PathBuf>,
+    pub emit: Emit,
+    pub lib_paths: Vec<PathBuf>,
 }
 
 impl BuildConfig {
     pub fn from_matches(matches: &clap::ArgMatches<'_>) -> BuildConfig {
+        let line_count = matches.value_of("line_count").map(|s| s.parse().expect("Cannot parse u16 from line_count")).unwrap_or(20);
+
         BuildConfig {
             configs: matches.values_of("config")
                 .map(|a| a.map(|s| s.to_string()).collect())
                 .unwrap_or(Vec::new()),
 
             line_length: matches.value_of("line_length").map(|s| s.parse().expect("Cannot parse u16 from line_length")).unwrap_or(70),
-            line_count: matches.value_of("line_count").map(|s| s.parse().expect("Cannot parse u16 from line_count")).unwrap_or(20),
+            line_count: line_count,
+            max_unroll: matches.value_of("max_unroll").map(|s| s.parse().expect("Cannot parse u16 from max_unroll")).unwrap_or(line_count),
+
+            source_map: matches.value_of("source_map").map(PathBuf::from),
+
+            emit: match matches.value_of("emit") {
+                Some("cfg") => Emit::Cfg,
+                _ => Emit::Ast
+            },
+
+            // Directories given on the command line are searched before those in `YC_PATH`
+            lib_paths: matches.values_of("lib_path")
+                .map(|a| a.map(PathBuf::from).collect())
+                .unwrap_or(Vec::new())
+                .into_iter()
+                .chain(std::env::var_os("YC_PATH").map(|p| std::env::split_paths(&p).collect::<Vec<_>>()).unwrap_or(Vec::new()))
+                .collect(),
         }
     }
+
+    // The default configuration, without any command line arguments
+    #[cfg(test)]
+    pub fn for_tests() -> BuildConfig {
+        BuildConfig { configs: vec![], line_length: 70, line_count: 20, max_unroll: 20, source_map: None, emit: Emit::Ast, lib_paths: vec![] }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+use std::collections::{ HashMap, HashSet };
+
+use crate::error::{ CompilerError };
+use crate::grammar::ast::{ CallableDefinition, StructDefinition, InnerStatement, Expression, CallType };
+use crate::grammar::visit::{ visit_stmt_mut, visit_stmt_exprs_mut };
+
+// Find the names of every callable called from the body of a callable. Method calls are followed if the struct type of the receiver can
+// be found from the parameters and fields declared in the body.
+fn called_names(callable: &CallableDefinition, structs: &HashMap<String, StructDefinition>) -> Vec<String> {
+    let mut fields: HashMap<String, String> = callable.parameters
+        .iter()
+        .map(|p| (p.field.name.clone(), p.field.typename.typename.clone()))
+        .collect();
+
+    let mut names = Vec::new();
+    for stmt in callable.statements.iter() {
+        let mut stmt = stmt.clone();
+
+        visit_stmt_mut(&mut stmt, &mut |s| match s {
+            InnerStatement::DeclareAssign(f, _) |
+            InnerStatement::DeclareConst(f, _) => { fields.insert(f.name.clone(), f.typename.typename.clone()); },
+            InnerStatement::Call(name, _) => names.push(name.clone()),
+            InnerStatement::MethodCall(path, method, _) => names.extend(method_name(path, method, &fields, structs)),
+            _ => {}
+        });
+
+        visit_stmt_exprs_mut(&mut stmt, &mut |e| match e {
+            Expression::Call(name, _) => names.push(name.clone()),
+            Expression::MethodCall(path, method, _) => names.extend(method_name(path, method, &fields, structs)),
+            _ => {}
+        });
+    }
+
+    return names;
+}
+
+fn method_name(path: &Vec<String>, method: &str, fields: &HashMap<String, String>, structs: &HashMap<String, StructDefinition>) -> Option<String> {
+    let mut typename = match fields.get(&path[0]) {
+        Some(t) => t.clone(),
+        None => return Some(format!("{}.{}", path.join("."), method))
+    };
+
+    for name in path[1..].iter() {
+        typename = structs.get(&typename)?.fields.iter().find(|f| f.name == *name)?.typename.typename.clone();
+    }
+
+    return Some(format!("{}.{}", typename, method));
+}
+
+// The maximum recursion depth of a `proc` marked `[max_depth(n)]`
+pub fn max_depth(callable: &CallableDefinition) -> Option<usize> {
+    if let CallType::Macro = callable.call_type {
+        return None;
+    }
+
+    return callable.attributes
+        .iter()
+        .filter(|a| a.name == "max_depth")
+        .find_map(|a| match a.parameters.as_slice() {
+            [ Expression::ConstNumber(n) ] => Some(n.float_value() as usize),
+            _ => None
+        });
+}
+
+// Check that there are no cycles in the calls between callables. Macros are copied into every place they are called so they can never
+// call themselves, recursion is only allowed if every callable in the cycle is a `proc` with an explicit `max_depth`.
+pub fn check_recursion(callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>) -> Result<(), CompilerError> {
+
+    // Overloads share a name, so the graph is built over names
+    let mut graph: HashMap<&String, Vec<String>> = HashMap::new();
+    for (name, overloads) in callables.iter() {
+        let mut called: Vec<_> = overloads.iter().flat_map(|c| called_names(c, structs)).filter(|n| callables.contains_key(n)).collect();
+        called.sort();
+        called.dedup();
+        graph.insert(name, called);
+    }
+
+    let mut names: Vec<_> = callables.keys().collect();
+    names.sort();
+
+    let mut done = HashSet::new();
+    for name in names.into_iter() {
+        let mut stack = Vec::new();
+        visit(name, &graph, callables, &mut stack, &mut done)?;
+    }
+
+    return Ok(());
+
+    fn visit<'a>(name: &'a String, graph: &'a HashMap<&String, Vec<String>>, callables: &HashMap<String, Vec<CallableDefinition>>, stack: &mut Vec<&'a String>, done: &mut HashSet<&'a String>) -> Result<(), CompilerError> {
+        if let Some(start) = stack.iter().position(|n| *n == name) {
+            let cycle: Vec<_> = stack[start..].iter().map(|n| n.to_string()).chain(std::iter::once(name.clone())).collect();
+            let allowed = stack[start..].iter().all(|n| callables[*n].iter().all(|c| max_depth(c).is_some()));
+            if allowed {
+                return Ok(());
+            }
+            return Err(CompilerError::RecursiveCall(cycle));
+        }
+
+        if done.contains(name) {
+            return Ok(());
+        }
+
+        stack.push(name);
+        for called in graph[name].iter() {
+            visit(called, graph, callables, stack, done)?;
+        }
+        stack.pop();
+        done.insert(name);
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::grammar::parser::y_parser;
+    use super::super::overloads::{ group_overloads };
+    use super::*;
+
+    fn check(code: &str) -> Result<(), CompilerError> {
+        let program = y_parser::program(code).ok().unwrap();
+        let structs = program.structs.iter().map(|s| (s.name.clone(), s.clone())).collect();
+        return check_recursion(&group_overloads(program.callables).ok().unwrap(), &structs);
+    }
+
+    #[test]
+    fn macro_cycles_are_rejected() {
+        let code = "
+            def macro a(x: number) { b(x); }
+            def macro b(x: number) { var y: number = c(x); }
+            def macro c(x: number) -> number { a(x); return x; }
+            def macro d() { a(1); }
+        ";
+        match check(code) {
+            Err(CompilerError::RecursiveCall(cycle)) => assert_eq!(vec![ "a", "b", "c", "a" ], cycle),
+            _ => panic!("Expected recursive call")
+        }
+
+        let code = "
+            type struct s { n: number }
+            impl s { def macro m(self) { self.m(); } }
+        ";
+        match check(code) {
+            Err(CompilerError::RecursiveCall(cycle)) => assert_eq!(vec![ "s.m", "s.m" ], cycle),
+            _ => panic!("Expected recursive method call")
+        }
+    }
+
+    #[test]
+    fn procs_with_depth_limit_may_recurse() {
+        assert!(check("[max_depth(4)] def proc a(x: number) { a(x - 1); }").is_ok());
+        assert!(check("def macro b() { } def macro a() { b(); b(); }").is_ok());
+
+        match check("def proc a(x: number) { a(x - 1); }") {
+            Err(CompilerError::RecursiveCall(cycle)) => assert_eq!(vec![ "a", "a" ], cycle),
+            _ => panic!("Expected recursive call")
+        }
+    }
+}
\ No newline at end of file
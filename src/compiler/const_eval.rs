@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use yolol_number::prelude::*;
+
+use crate::error::{ CompilerError };
+use crate::grammar::ast::{ InnerStatement, Expression, CallableDefinition };
+use crate::yolol::value::{ Value };
+use super::fields::{ canonicalise_field_path };
+use super::overloads::{ resolve_overload };
+use super::typecheck::{ Type };
+
+// Check if a callable is marked `[const]`, which allows it to be evaluated at compile time
+pub fn is_const(callable: &CallableDefinition) -> bool {
+    return callable.attributes.iter().any(|a| a.name == "const" && a.parameters.len() == 0);
+}
+
+// Check if an expression is a constant value
+pub fn is_constant(expr: &Expression) -> bool {
+    match expr {
+        Expression::ConstNumber(_) | Expression::ConstString(_) => true,
+        _ => false
+    }
+}
+
+// Run the body of a const macro with constant arguments, returning the value it returns. Values are either `ConstNumber` or `ConstString`.
+// The body may read constants declared outside the macro. Macros cannot be recursive, so evaluation always finishes.
+pub fn evaluate_call(callable: &CallableDefinition, args: Vec<Expression>, callables: &HashMap<String, Vec<CallableDefinition>>, consts: &HashMap<String, Expression>) -> Result<Expression, CompilerError> {
+    let mut eval = Evaluator {
+        callable: callable,
+        callables: callables,
+        consts: consts,
+        fields: callable.parameters.iter().map(|p| p.field.name.clone()).zip(args.into_iter()).collect(),
+    };
+
+    return match eval.stmts(&callable.statements)? {
+        Some(value) => Ok(value),
+        None => Err(fail(callable, "it did not return a value".to_string()))
+    };
+}
+
+fn fail(callable: &CallableDefinition, reason: String) -> CompilerError {
+    return CompilerError::ConstEvaluationFailed(callable.name.clone(), reason);
+}
+
+struct Evaluator<'a> {
+    callable: &'a CallableDefinition,
+    callables: &'a HashMap<String, Vec<CallableDefinition>>,
+    consts: &'a HashMap<String, Expression>,
+    fields: HashMap<String, Expression>,
+}
+
+impl<'a> Evaluator<'a> {
+
+    // Run statements until a `return`, returning the value
+    fn stmts(&mut self, stmts: &[InnerStatement]) -> Result<Option<Expression>, CompilerError> {
+        for stmt in stmts.iter() {
+            if let Some(value) = self.stmt(stmt)? {
+                return Ok(Some(value));
+            }
+        }
+        return Ok(None);
+    }
+
+    fn stmt(&mut self, stmt: &InnerStatement) -> Result<Option<Expression>, CompilerError> {
+        match stmt {
+            InnerStatement::DeclareAssign(field, value) |
+            InnerStatement::DeclareConst(field, value) => {
+                let value = self.expr(value)?;
+                self.fields.insert(field.name.clone(), value);
+            },
+
+            InnerStatement::Assign(path, value) => {
+                let value = self.expr(value)?;
+                self.write(path, value)?;
+            },
+
+            InnerStatement::ExpressionWrapper(value) => { self.expr(value)?; },
+
+            InnerStatement::If(condition, pass, fail) => {
+                let condition = self.expr(condition)?;
+                return self.stmts(if self.truthy(&condition)? { pass } else { fail });
+            },
+
+            InnerStatement::Match(value, arms, default) => {
+                let value = self.expr(value)?;
+                for (pattern, body) in arms.iter() {
+                    let pattern = self.expr(pattern)?;
+                    if self.compare(&value, &pattern)?.is_eq() {
+                        return self.stmts(body);
+                    }
+                }
+                return self.stmts(default);
+            },
+
+            InnerStatement::Return(value) => return Ok(Some(self.expr(value)?)),
+
+            other => return Err(self.fail(format!("`{:?}` cannot be evaluated at compile time", other)))
+        }
+
+        return Ok(None);
+    }
+
+    fn expr(&mut self, expr: &Expression) -> Result<Expression, CompilerError> {
+        let binary = |s: &mut Self, a: &Expression, b: &Expression| -> Result<(Expression, Expression), CompilerError> {
+            let a = s.expr(a)?;
+            return Ok((a, s.expr(b)?));
+        };
+
+        Ok(match expr {
+            Expression::ConstNumber(_) | Expression::ConstString(_) => expr.clone(),
+            Expression::FieldAccess(path) => self.read(path)?,
+            Expression::Bracket(x) => self.expr(x)?,
+
+            Expression::Negate(x) => { let x = self.number(x)?; num(-x) },
+            Expression::Not(x) => { let x = self.expr(x)?; boolean(!self.truthy(&x)?) },
+
+            Expression::Add(a, b) => { let (a, b) = binary(self, a, b)?; constant(self.value(&a)?.add(self.value(&b)?)) },
+            Expression::Subtract(a, b) => { let (a, b) = binary(self, a, b)?; constant(self.value(&a)?.subtract(self.value(&b)?)) },
+
+            Expression::Multiply(a, b) => { let (a, b) = (self.number(a)?, self.number(b)?); num(a * b) },
+            Expression::Exponent(a, b) => { let (a, b) = (self.number(a)?, self.number(b)?); num(a.pow(b)) },
+            Expression::Divide(a, b) | Expression::Modulus(a, b) => {
+                let (x, y) = (self.number(a)?, self.number(b)?);
+                if y == YololNumber::zero() {
+                    return Err(self.fail("division by zero".to_string()));
+                }
+                match expr {
+                    Expression::Divide(_, _) => num(x / y),
+                    _ => num(x % y)
+                }
+            },
+
+            Expression::And(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.truthy(&a)? && self.truthy(&b)?) },
+            Expression::Or(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.truthy(&a)? || self.truthy(&b)?) },
+
+            Expression::Equals(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.compare(&a, &b)?.is_eq()) },
+            Expression::NotEquals(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.compare(&a, &b)?.is_ne()) },
+            Expression::GreaterThan(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.compare(&a, &b)?.is_gt()) },
+            Expression::GreaterThanOrEq(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.compare(&a, &b)?.is_ge()) },
+            Expression::LessThan(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.compare(&a, &b)?.is_lt()) },
+            Expression::LessThanOrEq(a, b) => { let (a, b) = binary(self, a, b)?; boolean(self.compare(&a, &b)?.is_le()) },
+
+            Expression::Ternary(c, a, b) => {
+                let c = self.expr(c)?;
+                if self.truthy(&c)? { self.expr(a)? } else { self.expr(b)? }
+            },
+
+            Expression::PreIncrement(path) => self.step(path, 1, true)?,
+            Expression::PreDecrement(path) => self.step(path, -1, true)?,
+            Expression::PostIncrement(path) => self.step(path, 1, false)?,
+            Expression::PostDecrement(path) => self.step(path, -1, false)?,
+
+            Expression::Call(name, args) => {
+                let args = args.iter().map(|a| self.expr(a)).collect::<Result<Vec<_>, _>>()?;
+                self.call(name, args)?
+            },
+
+            other => return Err(self.fail(format!("`{:?}` cannot be evaluated at compile time", other)))
+        })
+    }
+
+    // Call a maths function or another const macro
+    fn call(&mut self, name: &str, args: Vec<Expression>) -> Result<Expression, CompilerError> {
+        let maths: Option<fn(YololNumber) -> YololNumber> = match name {
+            "abs" => Some(|n| n.abs()),
+            "sqrt" => Some(|n| n.sqrt()),
+            "sin" => Some(|n| n.sin()),
+            "cos" => Some(|n| n.cos()),
+            "tan" => Some(|n| n.tan()),
+            "asin" => Some(|n| n.asin()),
+            "acos" => Some(|n| n.acos()),
+            "atan" => Some(|n| n.atan()),
+            _ => None
+        };
+
+        if let Some(f) = maths {
+            return match args.as_slice() {
+                [ Expression::ConstNumber(n) ] => Ok(num(f(*n))),
+                _ => Err(self.fail(format!("`{}` must be called with one number", name)))
+            };
+        }
+
+        let types: HashMap<String, Type> = HashMap::new();
+        let (callable, args, _) = resolve_overload(name, &args, &types, self.callables)?;
+        if !is_const(callable) {
+            return Err(self.fail(format!("`{}` is not a const macro", name)));
+        }
+
+        return evaluate_call(callable, args, self.callables, self.consts);
+    }
+
+    // Read a field declared in the macro, or a constant declared outside it
+    fn read(&self, path: &Vec<String>) -> Result<Expression, CompilerError> {
+        let name = canonicalise_field_path(path);
+        return match self.fields.get(&name).or_else(|| self.consts.get(&name)) {
+            Some(value) => Ok(value.clone()),
+            None => Err(self.fail(format!("`{}` is not known at compile time", path.join("."))))
+        };
+    }
+
+    fn write(&mut self, path: &Vec<String>, value: Expression) -> Result<(), CompilerError> {
+        let name = canonicalise_field_path(path);
+        if !self.fields.contains_key(&name) {
+            return Err(self.fail(format!("`{}` is not known at compile time", path.join("."))));
+        }
+        self.fields.insert(name, value);
+        return Ok(());
+    }
+
+    fn step(&mut self, path: &Vec<String>, amount: i32, pre: bool) -> Result<Expression, CompilerError> {
+        let old = match self.read(path)? {
+            Expression::ConstNumber(n) => n,
+            _ => return Err(self.fail("only numbers can be incremented at compile time".to_string()))
+        };
+        let new = old + YololNumber::from_value(amount);
+        self.write(path, num(new))?;
+        return Ok(num(if pre { new } else { old }));
+    }
+
+    fn number(&mut self, expr: &Expression) -> Result<YololNumber, CompilerError> {
+        return match self.expr(expr)? {
+            Expression::ConstNumber(n) => Ok(n),
+            _ => Err(self.fail("expected a number".to_string()))
+        };
+    }
+
+    fn truthy(&self, v: &Expression) -> Result<bool, CompilerError> {
+        return self.value(v)?.truthy().ok_or_else(|| self.fail("strings cannot be used as conditions".to_string()));
+    }
+
+    fn compare(&self, a: &Expression, b: &Expression) -> Result<std::cmp::Ordering, CompilerError> {
+        return Ok(self.value(a)?.compare(&self.value(b)?));
+    }
+
+    fn value(&self, expr: &Expression) -> Result<Value, CompilerError> {
+        return match expr {
+            Expression::ConstNumber(n) => Ok(Value::Num(*n)),
+            Expression::ConstString(s) => Ok(Value::Str(s.clone())),
+            other => Err(self.fail(format!("`{:?}` is not a constant value", other)))
+        };
+    }
+
+    fn fail(&self, reason: String) -> CompilerError {
+        return fail(self.callable, reason);
+    }
+}
+
+fn num(n: YololNumber) -> Expression {
+    return Expression::ConstNumber(n);
+}
+
+fn boolean(b: bool) -> Expression {
+    return num(if b { YololNumber::one() } else { YololNumber::zero() });
+}
+
+fn constant(value: Value) -> Expression {
+    return match value {
+        Value::Num(n) => Expression::ConstNumber(n),
+        Value::Str(s) => Expression::ConstString(s),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::grammar::parser::y_parser;
+    use super::super::overloads::{ group_overloads };
+    use super::*;
+
+    fn call(code: &str, args: Vec<Expression>) -> Result<Expression, CompilerError> {
+        let callables = group_overloads(y_parser::program(code).ok().unwrap().callables).ok().unwrap();
+        return evaluate_call(&callables["f"][0], args, &callables, &HashMap::new());
+    }
+
+    #[test]
+    fn evaluate_const_macro() {
+        let code = "
+            [const] def macro square(x: number) -> number { return x * x; }
+            [const] def macro f(n: number) -> string {
+                var total: number = 0;
+                if (n > 2) { total = square(n); } else { total = -1; };
+                return \"v\" + total++ + total;
+            }
+        ";
+        let value = call(code, vec![ num(YololNumber::from_value(3)) ]).ok().unwrap();
+        assert_eq!("ConstString(\"v910\")", format!("{:?}", value));
+    }
+
+    #[test]
+    fn externals_are_not_constant() {
+        match call("[const] def macro f() -> number { return :a; }", vec![]) {
+            Err(CompilerError::ConstEvaluationFailed(name, _)) => assert_eq!("f", name),
+            _ => panic!("Expected evaluation failure")
+        }
+    }
+
+    #[test]
+    fn read_constants_declared_outside() {
+        let callables = group_overloads(y_parser::program("[const] def macro f() -> number { return 2 * pi; }").ok().unwrap().callables).ok().unwrap();
+        let mut consts = HashMap::new();
+        consts.insert("pi".to_string(), num(YololNumber::from_value(3)));
+
+        match evaluate_call(&callables["f"][0], vec![], &callables, &consts).ok().unwrap() {
+            Expression::ConstNumber(n) => assert_eq!(YololNumber::from_value(6), n),
+            other => panic!("Unexpected value {:?}", other)
+        }
+    }
+}
\ No newline at end of file
@@ -1,3 +1,13 @@
 pub fn canonicalise_field_path(path: &Vec<String>) -> String {
     return path.join("_");
+}
+
+const GOTO_LABEL_PREFIX: &str = "goto_layout_label_";
+
+pub fn goto_label_field(label: &str) -> String {
+    return format!("{}{}", GOTO_LABEL_PREFIX, label);
+}
+
+pub fn is_goto_label_field(name: &str) -> bool {
+    return name.starts_with(GOTO_LABEL_PREFIX);
 }
\ No newline at end of file
@@ -0,0 +1,89 @@
+use crate::yolol::ast::{ Expression };
+use crate::compiler::typecheck::{ Type };
+
+// Builds the Yolol expression for a call to a built in maths function
+pub type Intrinsic = fn(Box<Expression>) -> Expression;
+
+// Find the built in function with the given name, these take a single number and return a number
+pub fn find_intrinsic(name: &str) -> Option<Intrinsic> {
+    match name {
+        "abs" => Some(Expression::Abs),
+        "sqrt" => Some(Expression::Sqrt),
+        "sin" => Some(Expression::Sine),
+        "cos" => Some(Expression::Cosine),
+        "tan" => Some(Expression::Tangent),
+        "asin" => Some(Expression::ASin),
+        "acos" => Some(Expression::ACos),
+        "atan" => Some(Expression::ATan),
+        _ => None
+    }
+}
+
+// Built in string functions, each is lowered to the shortest known Yolol sequence for that operation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StringHelper {
+    // `pop_back(field)` removes the last character from a string field and returns it: `s-(--s)`
+    PopBack,
+
+    // `last_char(s)` copies the string into a temporary field and pops the last character from that
+    LastChar,
+
+    // `contains(s, x)` checks if removing `x` from `s` changes it: `s-x!=s`
+    Contains,
+
+    // `len(s)` counts how many times a copy of the string can be decremented before it is empty
+    Length,
+
+    // `parse_number(s)` pops base 10 digits off the end of a copy of the string, accumulating the value
+    ParseNumber,
+}
+
+pub fn find_string_helper(name: &str) -> Option<StringHelper> {
+    match name {
+        "pop_back" => Some(StringHelper::PopBack),
+        "last_char" => Some(StringHelper::LastChar),
+        "contains" => Some(StringHelper::Contains),
+        "len" => Some(StringHelper::Length),
+        "parse_number" => Some(StringHelper::ParseNumber),
+        _ => None
+    }
+}
+
+impl StringHelper {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StringHelper::PopBack => "pop_back",
+            StringHelper::LastChar => "last_char",
+            StringHelper::Contains => "contains",
+            StringHelper::Length => "len",
+            StringHelper::ParseNumber => "parse_number",
+        }
+    }
+
+    // Every parameter is a string
+    pub fn parameter_count(&self) -> usize {
+        match self {
+            StringHelper::Contains => 2,
+            _ => 1
+        }
+    }
+
+    pub fn return_type(&self) -> Type {
+        match self {
+            StringHelper::PopBack => Type::Str,
+            StringHelper::LastChar => Type::Str,
+            StringHelper::Contains => Type::Bool,
+            StringHelper::Length => Type::Num,
+            StringHelper::ParseNumber => Type::Num,
+        }
+    }
+
+    // Helpers which loop are lowered into `while` loops when loops are lowered, so they can only be used where a loop can be placed
+    pub fn needs_loop(&self) -> bool {
+        match self {
+            StringHelper::Length => true,
+            StringHelper::ParseNumber => true,
+            _ => false
+        }
+    }
+}
\ No newline at end of file
@@ -1,10 +1,14 @@
 mod typecheck;
 mod calls;
-mod fields;
+mod overloads;
+mod const_eval;
+mod call_graph;
+mod intrinsics;
+pub(crate) mod fields;
 mod build_config;
-mod stages;
+pub(crate) mod stages;
 
 pub use typecheck::{ Type };
 pub use calls::{ CallType };
 pub use fields::{ canonicalise_field_path };
-pub use build_config::BuildConfig;
\ No newline at end of file
+pub use build_config::{ BuildConfig, Emit };
\ No newline at end of file
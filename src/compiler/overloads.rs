@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use crate::error::{ CompilerError };
+use crate::grammar::ast::{ CallableDefinition, Expression };
+use super::typecheck::{ Type, infer_expr_type, type_check_assignment };
+
+// Group callables by name, every callable with the same name must have a different signature
+pub fn group_overloads(callables: Vec<CallableDefinition>) -> Result<HashMap<String, Vec<CallableDefinition>>, CompilerError> {
+    let mut result: HashMap<String, Vec<CallableDefinition>> = HashMap::new();
+
+    for callable in callables.into_iter() {
+        let overloads = result.entry(callable.name.clone()).or_default();
+        if overloads.iter().any(|c| parameter_types(c) == parameter_types(&callable)) {
+            return Err(CompilerError::DuplicateCallableDefinition(signature(&callable)));
+        }
+        overloads.push(callable);
+    }
+
+    return Ok(result);
+}
+
+// Pick the overload to call with the given arguments, returning the arguments (and their types) in parameter order. Parameters with
+// exactly the type of the argument are preferred over generic parameters, which are preferred over parameters the argument can be converted to.
+pub fn resolve_overload<'a>(name: &str, args: &[Expression], types: &HashMap<String, Type>, callables: &'a HashMap<String, Vec<CallableDefinition>>) -> Result<(&'a CallableDefinition, Vec<Expression>, Vec<Type>), CompilerError> {
+    let overloads = match callables.get(name) {
+        Some(o) => o,
+        None => return Err(CompilerError::CallableNotFound(name.to_string()))
+    };
+
+    let bind = |callable: &'a CallableDefinition| -> Result<(&'a CallableDefinition, Vec<Expression>, Vec<Type>), CompilerError> {
+        let args = bind_arguments(callable, args)?;
+        let arg_types = args.iter().map(|a| infer_expr_type(a, types)).collect::<Result<Vec<_>, _>>()?;
+        return Ok((callable, args, arg_types));
+    };
+
+    // With no choice to make, leave reporting any errors to the caller
+    if overloads.len() == 1 {
+        return bind(&overloads[0]);
+    }
+
+    let mut best = Vec::new();
+    let mut best_score = 0;
+    for candidate in overloads.iter().filter_map(|c| bind(c).ok()) {
+        let score = match score(candidate.0, &candidate.2) {
+            Some(s) => s,
+            None => continue
+        };
+
+        if best.len() == 0 || score > best_score {
+            best = vec![ candidate ];
+            best_score = score;
+        } else if score == best_score {
+            best.push(candidate);
+        }
+    }
+
+    return match best.len() {
+        0 => Err(CompilerError::NoMatchingOverload(name.to_string(), overloads.iter().map(signature).collect())),
+        1 => Ok(best.remove(0)),
+        _ => Err(CompilerError::AmbiguousCall(name.to_string(), best.iter().map(|(c, _, _)| signature(c)).collect())),
+    };
+}
+
+// Put the arguments of a call into parameter order. Named arguments (`name: value`) are passed to the parameter with that name, other
+// arguments to the parameter in the same position. Parameters with no argument use their default value.
+pub fn bind_arguments(callable: &CallableDefinition, args: &[Expression]) -> Result<Vec<Expression>, CompilerError> {
+    let count_error = || CompilerError::IncorrectCallParameterCount(callable.name.clone(), callable.parameters.len(), args.len());
+    if args.len() > callable.parameters.len() {
+        return Err(count_error());
+    }
+
+    let mut bound: Vec<Option<Expression>> = vec![ None; callable.parameters.len() ];
+    for (index, arg) in args.iter().enumerate() {
+        let (index, value) = match arg {
+            Expression::NamedArgument(n, value) => match callable.parameters.iter().position(|p| p.field.name == *n) {
+                Some(i) => (i, value.as_ref()),
+                None => return Err(CompilerError::UnknownNamedArgument(callable.name.clone(), n.clone()))
+            },
+            value => (index, value)
+        };
+
+        if bound[index].is_some() {
+            return Err(CompilerError::DuplicateArgument(callable.name.clone(), callable.parameters[index].field.name.clone()));
+        }
+        bound[index] = Some(value.clone());
+    }
+
+    return bound
+        .into_iter()
+        .zip(callable.parameters.iter())
+        .map(|(arg, param)| arg.or(param.default.clone()).ok_or_else(count_error))
+        .collect();
+}
+
+// The types of the parameters of a callable, with type parameters replaced by their position so `a<T>(x: T)` and `a<U>(y: U)` are the same
+fn parameter_types(callable: &CallableDefinition) -> Vec<String> {
+    return callable.parameters
+        .iter()
+        .map(|p| match callable.type_parameters.iter().position(|t| *t == p.field.typename.typename) {
+            Some(i) => format!("<{}>", i),
+            None => p.field.typename.to_type().to_typename().typename,
+        })
+        .collect();
+}
+
+// How well a callable matches the arguments, or None if it cannot be called with them
+fn score(callable: &CallableDefinition, arg_types: &[Type]) -> Option<usize> {
+    if callable.parameters.len() != arg_types.len() {
+        return None;
+    }
+
+    let mut generics: HashMap<&String, &Type> = HashMap::new();
+    let mut score = 0;
+    for (param, arg) in callable.parameters.iter().zip(arg_types.iter()) {
+        let typename = &param.field.typename.typename;
+        if callable.type_parameters.contains(typename) {
+            let bound = *generics.entry(typename).or_insert(arg);
+            type_check_assignment(bound, arg).ok()?;
+            score += 1;
+        } else {
+            let param_type = param.field.typename.to_type();
+            type_check_assignment(&param_type, arg).ok()?;
+            if param_type.to_typename() == arg.canonicalise().to_typename() {
+                score += 2;
+            }
+        }
+    }
+
+    return Some(score);
+}
+
+// A readable description of a callable, e.g. `max(number, number)`
+fn signature(callable: &CallableDefinition) -> String {
+    let params: Vec<_> = callable.parameters.iter().map(|p| p.field.typename.typename.clone()).collect();
+    return format!("{}({})", callable.name, params.join(", "));
+}
+
+#[cfg(test)]
+mod tests {
+
+    use yolol_number::prelude::*;
+
+    use crate::grammar::parser::y_parser;
+    use crate::error::{ CompilerError };
+    use super::*;
+
+    fn overloads(code: &str) -> Result<HashMap<String, Vec<CallableDefinition>>, CompilerError> {
+        return group_overloads(y_parser::program(code).ok().unwrap().callables);
+    }
+
+    fn resolve(callables: &HashMap<String, Vec<CallableDefinition>>, args: &[Expression]) -> Result<String, CompilerError> {
+        let mut types = HashMap::new();
+        types.insert("a".to_string(), Type::Any);
+        return resolve_overload("show", args, &types, callables).map(|(c, _, _)| signature(c));
+    }
+
+    fn num(n: i32) -> Expression {
+        return Expression::ConstNumber(YololNumber::from_value(n));
+    }
+
+    fn string() -> Expression {
+        return Expression::ConstString("s".to_string());
+    }
+
+    const CODE: &str = "
+        def macro show(a: number) -> string { return \"n\"; }
+        def macro show(a: string) -> string { return \"s\"; }
+        def macro show<T>(a: T, b: T) -> string { return \"t\"; }
+        def macro show(a: any, b: number) -> string { return \"a\"; }
+    ";
+
+    #[test]
+    fn resolve_by_count_and_type() {
+        let callables = overloads(CODE).ok().unwrap();
+
+        assert_eq!("show(number)", resolve(&callables, &[ num(1) ]).ok().unwrap());
+        assert_eq!("show(string)", resolve(&callables, &[ string() ]).ok().unwrap());
+        assert_eq!("show(T, T)", resolve(&callables, &[ string(), string() ]).ok().unwrap());
+        assert_eq!("show(any, number)", resolve(&callables, &[ Expression::FieldAccess(vec![ "a".to_string() ]), num(1) ]).ok().unwrap());
+    }
+
+    #[test]
+    fn ambiguous_and_unmatched_calls() {
+        let callables = overloads(CODE).ok().unwrap();
+
+        match resolve(&callables, &[ num(1), num(2), num(3) ]) {
+            Err(CompilerError::NoMatchingOverload(_, candidates)) => assert_eq!(4, candidates.len()),
+            _ => panic!("Expected no matching overload")
+        }
+
+        // `show<T>(T, T)` and `show(any, number)` are equally good matches
+        match resolve(&callables, &[ num(1), num(2) ]) {
+            Err(CompilerError::AmbiguousCall(_, candidates)) => assert_eq!(vec![ "show(T, T)", "show(any, number)" ], candidates),
+            _ => panic!("Expected ambiguous call")
+        }
+    }
+
+    #[test]
+    fn duplicate_signatures() {
+        match overloads("def macro a<T>(x: T) { } def macro a<U>(y: U) { }") {
+            Err(CompilerError::DuplicateCallableDefinition(s)) => assert_eq!("a(U)", s),
+            _ => panic!("Expected duplicate definition")
+        }
+    }
+
+    #[test]
+    fn named_and_default_arguments() {
+        let callables = overloads("def macro f(a: number, b: number = 2, c: number = 3) { }").ok().unwrap();
+        let f = &callables["f"][0];
+        let named = |n: &str, x: i32| Expression::NamedArgument(n.to_string(), Box::new(num(x)));
+
+        let bound = bind_arguments(f, &[ num(1), named("c", 4) ]).ok().unwrap();
+        assert_eq!(format!("{:?}", vec![ num(1), num(2), num(4) ]), format!("{:?}", bound));
+
+        match bind_arguments(f, &[ named("b", 1) ]) {
+            Err(CompilerError::IncorrectCallParameterCount(_, 3, 1)) => {},
+            _ => panic!("Expected missing argument")
+        }
+        match bind_arguments(f, &[ num(1), named("a", 1) ]) {
+            Err(CompilerError::DuplicateArgument(_, a)) => assert_eq!("a", a),
+            _ => panic!("Expected duplicate argument")
+        }
+        match bind_arguments(f, &[ named("d", 1) ]) {
+            Err(CompilerError::UnknownNamedArgument(_, d)) => assert_eq!("d", d),
+            _ => panic!("Expected unknown argument")
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,142 @@
+use crate::error::{ CompilerError };
+use crate::compiler::{ BuildConfig };
+use crate::yolol::ast::{ Statement, StatementList, Expression };
+use crate::yolol::visit::{ visit_stmt_identifiers_mut };
+use super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+use super::super::fields::{ is_goto_label_field, goto_label_field };
+
+// Estimate how many characters these statements will take up once placed onto a line. Goto labels will be replaced with line numbers, which are at most 2 characters
+pub fn estimate_line_length(stmts: &Vec<Statement>) -> usize {
+    let mut stmts = stmts.clone();
+    for stmt in stmts.iter_mut() {
+        visit_stmt_identifiers_mut(stmt, &mut |id| if !id.external && is_goto_label_field(&id.name) {
+            id.name = "99".to_string();
+        });
+    }
+    return StatementList { statements: stmts }.to_string().len();
+}
+
+// Check if a statement is a goto to the given label
+fn is_goto(stmt: &Statement, label: &str) -> bool {
+    match stmt {
+        Statement::Goto(Expression::VariableAccess(id)) => !id.external && id.name == goto_label_field(label),
+        Statement::GotoLabel(l) => l == label,
+        _ => false
+    }
+}
+
+fn invert_condition(condition: Expression) -> Expression {
+    match condition {
+        Expression::Not(x) => match *x {
+            Expression::Bracket(y) => *y,
+            y => y
+        },
+        other => Expression::Not(Box::new(Expression::Bracket(Box::new(other))))
+    }
+}
+
+impl YololStatementBlocks {
+
+    // Place loops which are small enough onto a single line, so each iteration loops with `goto` to the same line
+    pub fn compact_loops(self, config: &BuildConfig) -> Result<YololStatementBlocks, CompilerError> {
+
+        let line_length = config.line_length as usize;
+
+        let next_labels: Vec<Option<String>> = self.blocks
+            .iter()
+            .skip(1)
+            .map(|b| match b {
+                YololBlock::Statements(label, _) => label.clone(),
+                YololBlock::Line(label, _) => label.clone(),
+            })
+            .chain(std::iter::once(None))
+            .collect();
+
+        let blocks = self.blocks
+            .into_iter()
+            .zip(next_labels)
+            .map(|(b, next)| handle_block(b, next, line_length))
+            .collect();
+
+        return Ok(YololStatementBlocks {
+            blocks: blocks,
+            types: self.types,
+            consts: self.consts
+        });
+
+        fn handle_block(b: YololBlock, next: Option<String>, line_length: usize) -> YololBlock {
+            let (label, stmts) = match b {
+                YololBlock::Statements(Some(label), stmts) => (label, stmts),
+                other => return other
+            };
+
+            // Only blocks which end by jumping back to their own start are loops
+            if !stmts.last().map(|s| is_goto(s, &label)).unwrap_or(false) {
+                return YololBlock::Statements(Some(label), stmts);
+            }
+
+            // A `while` loop starts by leaving the loop when the condition is false. When the loop exit is the next block this
+            // can be written as a single `if`, running off the end of the line leaves the loop.
+            let candidate = match (stmts.first(), &next) {
+                (Some(Statement::If(condition, pass, fail)), Some(next)) if fail.statements.len() == 0 && pass.statements.len() == 1 && is_goto(&pass.statements[0], next) => {
+                    vec![ Statement::If(
+                        invert_condition(condition.clone()),
+                        Box::new(StatementList { statements: stmts[1..].to_vec() }),
+                        Box::new(StatementList { statements: vec![] })
+                    ) ]
+                },
+                _ => stmts.clone()
+            };
+
+            if estimate_line_length(&candidate) <= line_length {
+                return YololBlock::Line(Some(label), candidate);
+            } else {
+                return YololBlock::Statements(Some(label), stmts);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::compiler::{ BuildConfig };
+    use crate::yolol::ast::{ Statement, StatementList, Expression, Identifier };
+    use super::super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+    use super::super::super::fields::{ goto_label_field };
+
+    fn goto(label: &str) -> Statement {
+        Statement::Goto(Expression::VariableAccess(Identifier { name: goto_label_field(label), external: false }))
+    }
+
+    #[test]
+    fn while_loop_onto_line() {
+        let x = Identifier { name: "x".to_string(), external: true };
+        let condition = Expression::GreaterThan(Box::new(Expression::VariableAccess(x.clone())), Box::new(Expression::ConstantNumber(YololNumber::zero())));
+
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Statements(Some("start".to_string()), vec![
+                    Statement::If(Expression::Not(Box::new(Expression::Bracket(Box::new(condition)))), Box::new(StatementList { statements: vec![ goto("end") ] }), Box::new(StatementList { statements: vec![] })),
+                    Statement::ExpressionWrapper(Expression::PostDecrement(x)),
+                    goto("start")
+                ]),
+                YololBlock::Statements(Some("end".to_string()), vec![]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let blocks = blocks.compact_loops(&BuildConfig::for_tests()).ok().unwrap();
+
+        // The exit condition is inverted and wraps the loop body, leaving the loop by falling off the end of the line
+        match &blocks.blocks[0] {
+            YololBlock::Line(_, stmts) => assert_eq!("if :x>0 then :x-- goto goto_layout_label_start end", StatementList { statements: stmts.clone() }.to_string()),
+            _ => panic!("Expected loop to be placed onto a line")
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,348 @@
+use std::collections::{ HashMap, HashSet };
+
+use yolol_number::prelude::*;
+
+use crate::error::{ CompilerError };
+use crate::yolol::ast::{ Statement, Expression, Op };
+use crate::yolol::visit::{ visit_stmt_exprs };
+use super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+use super::super::fields::{ is_goto_label_field, goto_label_field };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdgeKind {
+    // Execution continues into the next block
+    Fallthrough,
+
+    // Execution jumps to the target block with a `goto`
+    Goto,
+
+    // Execution runs off the end of the last line and wraps around to the first line
+    LineWrap,
+
+    // A runtime error skips the rest of the line and execution continues on the next line
+    Error
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub label: Option<String>,
+
+    // Index of the `YololBlock` these statements came from
+    pub source: usize,
+
+    // Set if the statements of the source block must all be placed onto a single line
+    pub line: bool,
+
+    pub statements: Vec<Statement>
+}
+
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>
+}
+
+// Check if control may leave the current block part way through this statement
+pub fn contains_goto(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Goto(_) => true,
+        Statement::GotoLabel(_) => true,
+        Statement::If(_, pass, fail) => pass.statements.iter().chain(fail.statements.iter()).any(contains_goto),
+        _ => false
+    }
+}
+
+// Check if control always leaves the current block at this statement
+pub fn is_unconditional_goto(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::Goto(_) => true,
+        Statement::GotoLabel(_) => true,
+        _ => false
+    }
+}
+
+// Check if executing this statement may cause a runtime error, which skips the rest of the line. Dividing by anything other than a
+// non-zero constant may fail and decrementing a field may fail if it holds an empty string.
+pub fn can_fail(stmt: &Statement) -> bool {
+    let mut fail = match stmt {
+        Statement::CompoundAssignment(_, Op::Divide, value) |
+        Statement::CompoundAssignment(_, Op::Modulo, value) => !is_non_zero_constant(value),
+        _ => false
+    };
+
+    visit_stmt_exprs(stmt, &mut |e| match e {
+        Expression::Divide(_, y) |
+        Expression::Modulus(_, y) if !is_non_zero_constant(y) => fail = true,
+        Expression::PostDecrement(_) |
+        Expression::PreDecrement(_) => fail = true,
+        _ => {}
+    });
+
+    return fail;
+
+    fn is_non_zero_constant(expr: &Expression) -> bool {
+        match expr {
+            Expression::ConstantNumber(n) => *n != YololNumber::zero(),
+            _ => false
+        }
+    }
+}
+
+// Find the indices of all the blocks a statement may jump to. A computed goto which counts from a label (e.g. a jump table,
+// `goto label + offset`) may land on any line at or after that label, one which does not use any label may land on any line.
+fn goto_targets(stmt: &Statement, labels: &HashMap<String, usize>) -> Vec<usize> {
+    let mut targets = Vec::new();
+    match stmt {
+        Statement::GotoLabel(label) => targets.extend(labels.get(&goto_label_field(label))),
+        Statement::Goto(expr) => {
+            let mut fields = Vec::new();
+            visit_stmt_exprs(stmt, &mut |e| if let Expression::VariableAccess(id) = e {
+                if !id.external && is_goto_label_field(&id.name) {
+                    fields.push(id.name.clone());
+                }
+            });
+
+            match (expr, fields.as_slice()) {
+                (_, []) => targets.extend(labels.values()),
+                (Expression::VariableAccess(_), _) => targets.extend(fields.iter().filter_map(|f| labels.get(f))),
+                (_, [ start ]) => match labels.get(start) {
+                    Some(start) => targets.extend(labels.values().filter(|i| *i >= start)),
+                    None => {}
+                },
+                _ => targets.extend(fields.iter().filter_map(|f| labels.get(f)))
+            }
+            targets.sort();
+        },
+        Statement::If(_, pass, fail) => pass.statements.iter().chain(fail.statements.iter()).for_each(|s| targets.extend(goto_targets(s, labels))),
+        _ => {}
+    }
+    return targets;
+}
+
+impl YololStatementBlocks {
+
+    pub fn build_control_flow_graph(&self) -> Result<ControlFlowGraph, CompilerError> {
+
+        // Split blocks into basic blocks, control can only enter at the start and leave at the end
+        let mut blocks = Vec::new();
+        for (index, block) in self.blocks.iter().enumerate() {
+            let (label, stmts, line) = match block {
+                YololBlock::Statements(label, stmts) => (label, stmts, false),
+                YololBlock::Line(label, stmts) => (label, stmts, true),
+            };
+
+            let mut current = BasicBlock { label: label.clone(), source: index, line: line, statements: Vec::new() };
+            for stmt in stmts.iter() {
+                current.statements.push(stmt.clone());
+                if contains_goto(stmt) {
+                    blocks.push(current);
+                    current = BasicBlock { label: None, source: index, line: line, statements: Vec::new() };
+                }
+            }
+
+            if current.statements.len() > 0 || current.label.is_some() {
+                blocks.push(current);
+            }
+        }
+
+        let labels: HashMap<String, usize> = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| b.label.as_ref().map(|l| (goto_label_field(l), i)))
+            .collect();
+
+        let mut edges = Vec::new();
+        for (index, block) in blocks.iter().enumerate() {
+            for stmt in block.statements.iter() {
+                for to in goto_targets(stmt, &labels) {
+                    edges.push(Edge { from: index, to: to, kind: EdgeKind::Goto });
+                }
+            }
+
+            if !block.statements.last().map(is_unconditional_goto).unwrap_or(false) {
+                if index + 1 < blocks.len() {
+                    edges.push(Edge { from: index, to: index + 1, kind: EdgeKind::Fallthrough });
+                } else {
+                    edges.push(Edge { from: index, to: 0, kind: EdgeKind::LineWrap });
+                }
+            }
+
+            // A runtime error skips the rest of the line. Blocks which are not lines have not been placed yet, so the next line may start with
+            // the next block.
+            if block.statements.iter().any(can_fail) {
+                let next = (index + 1..blocks.len()).find(|i| !block.line || blocks[*i].source != block.source).unwrap_or(0);
+                edges.push(Edge { from: index, to: next, kind: EdgeKind::Error });
+            }
+        }
+
+        return Ok(ControlFlowGraph {
+            blocks: blocks,
+            edges: edges
+        });
+    }
+}
+
+impl ControlFlowGraph {
+
+    // Indices of the `YololBlock`s which have at least one basic block reachable from the start of the program
+    pub fn reachable_sources(&self) -> HashSet<usize> {
+        let mut reached = HashSet::new();
+        let mut pending = if self.blocks.len() > 0 { vec![ 0 ] } else { vec![] };
+        while let Some(index) = pending.pop() {
+            if reached.insert(index) {
+                pending.extend(self.edges.iter().filter(|e| e.from == index).map(|e| e.to));
+            }
+        }
+
+        return reached.into_iter().map(|i| self.blocks[i].source).collect();
+    }
+
+    pub fn to_graphviz(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("digraph cfg {\n");
+        out.push_str("    node [shape=box, fontname=\"Courier\"];\n");
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut text = match &block.label {
+                Some(label) => format!("@{}\\l", label),
+                None => String::new()
+            };
+            for stmt in block.statements.iter() {
+                text.push_str(&stmt.to_string().replace("\\", "\\\\").replace("\"", "\\\""));
+                text.push_str("\\l");
+            }
+
+            out.push_str(&format!("    b{} [label=\"{}\"];\n", index, text));
+        }
+
+        // Group together basic blocks which must be placed onto the same line
+        let mut lines: Vec<(usize, Vec<usize>)> = Vec::new();
+        for (index, block) in self.blocks.iter().enumerate().filter(|(_, b)| b.line) {
+            match lines.last_mut() {
+                Some((source, members)) if *source == block.source => members.push(index),
+                _ => lines.push((block.source, vec![ index ]))
+            }
+        }
+        for (source, members) in lines.iter() {
+            let nodes = members.iter().map(|m| format!("b{};", m)).collect::<Vec<_>>().join(" ");
+            out.push_str(&format!("    subgraph cluster_line_{} {{ style=dashed; {} }}\n", source, nodes));
+        }
+
+        for edge in self.edges.iter() {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Goto => " [style=dashed, label=\"goto\"]",
+                EdgeKind::LineWrap => " [style=dotted, label=\"wrap\"]",
+                EdgeKind::Error => " [style=dotted, label=\"error\"]",
+            };
+            out.push_str(&format!("    b{} -> b{}{};\n", edge.from, edge.to, style));
+        }
+
+        out.push_str("}\n");
+        return out;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::yolol::ast::{ Statement, StatementList, Expression, Identifier };
+    use super::super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+    use super::super::super::fields::{ goto_label_field };
+    use super::*;
+
+    #[test]
+    fn conditional_goto_edges() {
+        let goto_loop = Statement::Goto(Expression::VariableAccess(Identifier { name: goto_label_field("loop"), external: false }));
+        let condition = Expression::VariableAccess(Identifier { name: "a".to_string(), external: true });
+        let assign = Statement::Assignment(Identifier { name: "b".to_string(), external: false }, Expression::ConstantNumber(YololNumber::one()));
+
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Statements(None, vec![ assign.clone() ]),
+                YololBlock::Line(Some("loop".to_string()), vec![
+                    Statement::If(condition, Box::new(StatementList { statements: vec![ goto_loop ] }), Box::new(StatementList { statements: vec![] })),
+                    assign
+                ]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let cfg = blocks.build_control_flow_graph().ok().unwrap();
+
+        // The line is split after the `if`, which can jump back to the start of the line
+        assert_eq!(3, cfg.blocks.len());
+        let edges: Vec<(usize, usize, EdgeKind)> = cfg.edges.iter().map(|e| (e.from, e.to, e.kind.clone())).collect();
+        assert_eq!(vec![
+            (0, 1, EdgeKind::Fallthrough),
+            (1, 1, EdgeKind::Goto),
+            (1, 2, EdgeKind::Fallthrough),
+            (2, 0, EdgeKind::LineWrap),
+        ], edges);
+    }
+
+    #[test]
+    fn error_edges_skip_to_next_line() {
+        let external = |name: &str| Expression::VariableAccess(Identifier { name: name.to_string(), external: true });
+        let divide = Statement::Assignment(Identifier { name: "b".to_string(), external: false }, Expression::Divide(Box::new(external("a")), Box::new(external("c"))));
+        let goto_end = Statement::GotoLabel("end".to_string());
+
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Line(None, vec![ divide, goto_end.clone(), goto_end ]),
+                YololBlock::Statements(None, vec![]),
+                YololBlock::Line(Some("end".to_string()), vec![]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let cfg = blocks.build_control_flow_graph().ok().unwrap();
+
+        // The division may fail, which skips the rest of the first line including the second goto
+        let edges: Vec<(usize, usize, EdgeKind)> = cfg.edges.iter().map(|e| (e.from, e.to, e.kind.clone())).collect();
+        assert_eq!(vec![
+            (0, 2, EdgeKind::Goto),
+            (0, 2, EdgeKind::Error),
+            (1, 2, EdgeKind::Goto),
+            (2, 0, EdgeKind::LineWrap),
+        ], edges);
+    }
+
+    #[test]
+    fn computed_goto_edges_to_every_label() {
+        let goto = Statement::Goto(Expression::VariableAccess(Identifier { name: "a".to_string(), external: true }));
+
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Statements(None, vec![ goto ]),
+                YololBlock::Statements(Some("first".to_string()), vec![ Statement::GotoLabel("first".to_string()) ]),
+                YololBlock::Statements(Some("second".to_string()), vec![]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let cfg = blocks.build_control_flow_graph().ok().unwrap();
+        assert_eq!(3, cfg.reachable_sources().len());
+
+        let edges: Vec<(usize, usize, EdgeKind)> = cfg.edges.iter().filter(|e| e.from == 0).map(|e| (e.from, e.to, e.kind.clone())).collect();
+        assert_eq!(vec![
+            (0, 1, EdgeKind::Goto),
+            (0, 2, EdgeKind::Goto),
+        ], edges);
+    }
+}
\ No newline at end of file
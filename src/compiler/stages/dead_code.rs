@@ -0,0 +1,370 @@
+use std::collections::HashSet;
+
+use crate::error::{ CompilerError };
+use crate::yolol::ast::{ Statement, StatementList, Expression, Identifier };
+use crate::yolol::visit::{ visit_expr, visit_stmt_exprs };
+use super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+use super::control_flow::{ contains_goto, is_unconditional_goto, can_fail };
+
+// Get the field written by a statement which does nothing except store a value
+fn stored_field(stmt: &Statement) -> Option<&Identifier> {
+    match stmt {
+        Statement::Assignment(id, _) => Some(id),
+        Statement::CompoundAssignment(id, _, _) => Some(id),
+        Statement::ExpressionWrapper(Expression::PostIncrement(id)) => Some(id),
+        Statement::ExpressionWrapper(Expression::PostDecrement(id)) => Some(id),
+        Statement::ExpressionWrapper(Expression::PreIncrement(id)) => Some(id),
+        Statement::ExpressionWrapper(Expression::PreDecrement(id)) => Some(id),
+        _ => None
+    }
+}
+
+// Check if evaluating this statement modifies any field other than the one it stores into, or may fail and skip the rest of the line
+fn has_side_effects(stmt: &Statement) -> bool {
+    if can_fail(stmt) {
+        return true;
+    }
+
+    let value = match stmt {
+        Statement::Assignment(_, value) => value,
+        Statement::CompoundAssignment(_, _, value) => value,
+        Statement::ExpressionWrapper(_) => return false,
+        _ => return true
+    };
+
+    return expr_has_side_effects(value);
+}
+
+fn expr_has_side_effects(expr: &Expression) -> bool {
+    let mut side_effects = false;
+    visit_expr(expr, &mut |e| match e {
+        Expression::PostIncrement(_) |
+        Expression::PostDecrement(_) |
+        Expression::PreIncrement(_) |
+        Expression::PreDecrement(_) => side_effects = true,
+        _ => {}
+    });
+    return side_effects;
+}
+
+// Collect the names of all internal fields whose value is observed by a statement
+fn observe_stmt(stmt: &Statement, observed: &mut HashSet<String>) {
+    match stmt {
+        Statement::ExpressionWrapper(Expression::PostIncrement(_)) |
+        Statement::ExpressionWrapper(Expression::PostDecrement(_)) |
+        Statement::ExpressionWrapper(Expression::PreIncrement(_)) |
+        Statement::ExpressionWrapper(Expression::PreDecrement(_)) => {},
+
+        // Modifying a field with a compound assignment does not make it live
+        Statement::CompoundAssignment(_, _, value) => observe_expr(value, observed),
+
+        Statement::If(condition, pass, fail) => {
+            observe_expr(condition, observed);
+            pass.statements.iter().for_each(|s| observe_stmt(s, observed));
+            fail.statements.iter().for_each(|s| observe_stmt(s, observed));
+        },
+
+        other => visit_stmt_exprs(other, &mut |e| observe_root(e, observed)),
+    }
+}
+
+fn observe_expr(expr: &Expression, observed: &mut HashSet<String>) {
+    visit_expr(expr, &mut |e| observe_root(e, observed));
+}
+
+fn observe_root(expr: &Expression, observed: &mut HashSet<String>) {
+    match expr {
+        Expression::VariableAccess(id) |
+        Expression::PostIncrement(id) |
+        Expression::PostDecrement(id) |
+        Expression::PreIncrement(id) |
+        Expression::PreDecrement(id) if !id.external => { observed.insert(id.name.clone()); },
+        _ => {}
+    }
+}
+
+// Check if a statement reads the value of the given internal field
+fn reads_field(stmt: &Statement, name: &str) -> bool {
+    if let Statement::CompoundAssignment(id, _, _) = stmt {
+        if !id.external && id.name == name {
+            return true;
+        }
+    }
+
+    let mut observed = HashSet::new();
+    visit_stmt_exprs(stmt, &mut |e| observe_root(e, &mut observed));
+    return observed.contains(name);
+}
+
+impl YololStatementBlocks {
+
+    pub fn eliminate_dead_code(self) -> Result<YololStatementBlocks, CompilerError> {
+
+        let reachable = remove_unreachable_blocks(self)?;
+        let mut blocks = reachable.blocks;
+
+        // Removing one dead store may make the fields it read dead too, so repeat until nothing changes
+        loop {
+            let mut observed = HashSet::new();
+            for stmts in blocks.iter().map(block_stmts) {
+                stmts.iter().for_each(|s| observe_stmt(s, &mut observed));
+            }
+
+            let before: usize = blocks.iter().map(|b| count_stmts(block_stmts(b))).sum();
+            blocks = blocks
+                .into_iter()
+                .map(|b| match b {
+                    YololBlock::Statements(label, stmts) => YololBlock::Statements(label, handle_stmts(stmts, &observed)),
+                    YololBlock::Line(label, stmts) => YololBlock::Line(label, handle_stmts(stmts, &observed)),
+                })
+                .collect();
+            let after: usize = blocks.iter().map(|b| count_stmts(block_stmts(b))).sum();
+
+            if before == after {
+                break;
+            }
+        }
+
+        // Drop the types of fields which no longer exist (e.g. unused struct leaf fields)
+        let mut used = HashSet::new();
+        for stmts in blocks.iter().map(block_stmts) {
+            for stmt in stmts.iter() {
+                observe_stmt(stmt, &mut used);
+                if let Some(id) = stored_field(stmt) {
+                    used.insert(id.name.clone());
+                }
+            }
+        }
+        let types = reachable.types
+            .into_iter()
+            .filter(|(k, _)| used.contains(k))
+            .collect();
+
+        return Ok(YololStatementBlocks {
+            blocks: blocks,
+            types: types,
+            consts: reachable.consts
+        });
+
+        fn block_stmts(b: &YololBlock) -> &Vec<Statement> {
+            match b {
+                YololBlock::Statements(_, stmts) => stmts,
+                YololBlock::Line(_, stmts) => stmts,
+            }
+        }
+
+        fn count_stmts(stmts: &Vec<Statement>) -> usize {
+            stmts.iter().map(|s| match s {
+                Statement::If(_, pass, fail) => 1 + count_stmts(&pass.statements) + count_stmts(&fail.statements),
+                _ => 1
+            }).sum()
+        }
+
+        // Remove blocks which cannot be reached in the control flow graph, and statements after an unconditional goto in the blocks which remain
+        fn remove_unreachable_blocks(blocks: YololStatementBlocks) -> Result<YololStatementBlocks, CompilerError> {
+            let reachable = blocks.build_control_flow_graph()?.reachable_sources();
+
+            return Ok(YololStatementBlocks {
+                blocks: blocks.blocks
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| reachable.contains(i))
+                    .map(|(_, b)| match b {
+                        YololBlock::Statements(label, stmts) => YololBlock::Statements(label, remove_unreachable_stmts(stmts)),
+                        YololBlock::Line(label, stmts) => YololBlock::Line(label, remove_unreachable_stmts(stmts)),
+                    })
+                    .collect(),
+                types: blocks.types,
+                consts: blocks.consts
+            });
+        }
+
+        // Statements after an unconditional goto can never execute
+        fn remove_unreachable_stmts(stmts: Vec<Statement>) -> Vec<Statement> {
+            let mut result = Vec::new();
+            for stmt in stmts.into_iter() {
+                let stmt = match stmt {
+                    Statement::If(condition, pass, fail) => Statement::If(
+                        condition,
+                        Box::new(StatementList { statements: remove_unreachable_stmts(pass.statements) }),
+                        Box::new(StatementList { statements: remove_unreachable_stmts(fail.statements) })
+                    ),
+                    other => other
+                };
+
+                let end = is_unconditional_goto(&stmt);
+                result.push(stmt);
+                if end {
+                    break;
+                }
+            }
+            return result;
+        }
+
+        fn handle_stmts(stmts: Vec<Statement>, observed: &HashSet<String>) -> Vec<Statement> {
+            let stmts: Vec<Statement> = stmts
+                .into_iter()
+                .filter_map(|s| match s {
+                    Statement::If(condition, pass, fail) => {
+                        let pass = handle_stmts(pass.statements, observed);
+                        let fail = handle_stmts(fail.statements, observed);
+
+                        // An `if` with nothing in either branch does nothing, unless evaluating the condition does something
+                        if pass.len() == 0 && fail.len() == 0 && !expr_has_side_effects(&condition) {
+                            None
+                        } else {
+                            Some(Statement::If(
+                                condition,
+                                Box::new(StatementList { statements: pass }),
+                                Box::new(StatementList { statements: fail })
+                            ))
+                        }
+                    },
+
+                    // Remove stores to fields which are never read
+                    s => match stored_field(&s) {
+                        Some(id) if !id.external && !observed.contains(&id.name) && !has_side_effects(&s) => None,
+                        _ => Some(s)
+                    }
+                })
+                .collect();
+
+            // Remove stores which are always overwritten before they are read
+            let overwritten: Vec<bool> = (0..stmts.len()).map(|i| is_overwritten(&stmts[i], &stmts[i + 1..])).collect();
+            return stmts
+                .into_iter()
+                .zip(overwritten)
+                .filter(|(_, o)| !o)
+                .map(|(s, _)| s)
+                .collect();
+        }
+
+        fn is_overwritten(stmt: &Statement, after: &[Statement]) -> bool {
+            let id = match stmt {
+                Statement::Assignment(id, _) if !id.external && !has_side_effects(stmt) => id,
+                _ => return false
+            };
+
+            // If a statement fails before the field is overwritten the rest of the line is skipped, so the earlier value is kept
+            for next in after.iter() {
+                if reads_field(next, &id.name) || contains_goto(next) || can_fail(next) {
+                    return false;
+                }
+
+                if let Statement::Assignment(other, _) = next {
+                    if !other.external && other.name == id.name {
+                        return true;
+                    }
+                }
+            }
+
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::yolol::ast::{ Statement, Expression, Identifier };
+    use super::super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+    use super::super::super::fields::{ goto_label_field };
+
+    fn id(name: &str, external: bool) -> Identifier {
+        Identifier { name: name.to_string(), external: external }
+    }
+
+    fn assign(name: &str, value: Expression) -> Statement {
+        Statement::Assignment(id(name, false), value)
+    }
+
+    fn num(n: i32) -> Expression {
+        Expression::ConstantNumber(YololNumber::from_value(n))
+    }
+
+    #[test]
+    fn remove_dead_stores() {
+        let blocks = YololStatementBlocks {
+            blocks: vec![ YololBlock::Statements(None, vec![
+                assign("a", num(1)),
+                assign("b", num(2)),
+                assign("c", Expression::VariableAccess(id("a", false))),
+                assign("b", num(3)),
+                Statement::Assignment(id("out", true), Expression::VariableAccess(id("b", false))),
+                Statement::Goto(num(1)),
+                assign("b", num(4)),
+            ]) ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let stmts = match blocks.eliminate_dead_code().ok().unwrap().blocks.remove(0) {
+            YololBlock::Statements(_, stmts) => stmts,
+            _ => panic!("Unexpected block")
+        };
+
+        // `c` is never read, so `a` is never read either. The first write to `b` is overwritten and the last is unreachable
+        assert_eq!(3, stmts.len());
+        match &stmts[0] {
+            Statement::Assignment(i, Expression::ConstantNumber(n)) => { assert_eq!("b", i.name); assert_eq!(YololNumber::from_value(3), *n) },
+            _ => panic!("Expected `b = 3`")
+        }
+    }
+
+    #[test]
+    fn remove_blocks_only_reached_from_dead_code() {
+        let goto = |l: &str| Statement::Goto(Expression::VariableAccess(id(&goto_label_field(l), false)));
+        let out = |n: i32| Statement::Assignment(id("out", true), num(n));
+
+        // `dead` can never run, so `orphan` is unreachable even though there is a goto to it
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Statements(None, vec![ goto("end") ]),
+                YololBlock::Statements(Some("dead".to_string()), vec![ out(1), goto("orphan") ]),
+                YololBlock::Statements(Some("orphan".to_string()), vec![ out(2), goto("end") ]),
+                YololBlock::Statements(Some("end".to_string()), vec![ out(3) ]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let labels: Vec<_> = blocks.eliminate_dead_code().ok().unwrap().blocks.into_iter().map(|b| match b {
+            YololBlock::Statements(label, _) => label,
+            YololBlock::Line(label, _) => label,
+        }).collect();
+        assert_eq!(vec![ None, Some("end".to_string()) ], labels);
+    }
+
+    #[test]
+    fn keep_code_after_statements_which_can_fail() {
+        let div = |name: &str| Expression::Divide(Box::new(num(1)), Box::new(Expression::VariableAccess(id(name, true))));
+
+        // Dividing by `:x` may fail, which skips the `goto` and the overwrite of `b`
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Statements(None, vec![
+                    assign("b", num(1)),
+                    assign("b", div("x")),
+                    Statement::Assignment(id("out", true), Expression::VariableAccess(id("b", false))),
+                    Statement::Goto(num(1)),
+                ]),
+                YololBlock::Statements(None, vec![
+                    Statement::Assignment(id("out", true), num(2)),
+                ]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let mut blocks = blocks.eliminate_dead_code().ok().unwrap().blocks;
+        assert_eq!(2, blocks.len());
+        match blocks.remove(0) {
+            YololBlock::Statements(_, stmts) => assert_eq!(4, stmts.len()),
+            _ => panic!("Unexpected block")
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use yolol_number::prelude::*;
+
+use crate::error::{ CompilerError };
+use crate::yolol::ast::{ Statement, StatementList, Expression };
+use crate::yolol::value::{ Value };
+use super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+
+impl YololStatementBlocks {
+
+    pub fn fold_constants(self) -> Result<YololStatementBlocks, CompilerError> {
+
+        let consts = self.consts;
+
+        let result: Result<_, _> = self.blocks
+            .iter()
+            .map(|x| handle_block(x, &consts))
+            .collect();
+
+        let folded: Result<_, _> = consts
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), handle_expr(v, &consts, &mut vec![ k.clone() ])?)))
+            .collect();
+
+        return Ok(YololStatementBlocks {
+            blocks: result?,
+            types: self.types,
+            consts: folded?
+        });
+
+        fn handle_block(b: &YololBlock, consts: &HashMap<String, Expression>) -> Result<YololBlock, CompilerError> {
+            match b {
+                YololBlock::Statements(label, stmts) => Ok(YololBlock::Statements(label.clone(), handle_stmts(stmts, consts)?)),
+                YololBlock::Line(label, stmts) => Ok(YololBlock::Line(label.clone(), handle_stmts(stmts, consts)?)),
+            }
+        }
+
+        fn handle_stmts(stmts: &Vec<Statement>, consts: &HashMap<String, Expression>) -> Result<Vec<Statement>, CompilerError> {
+            stmts
+                .iter()
+                .map(|x| handle_stmt(x, consts))
+                .collect()
+        }
+
+        fn handle_stmt(stmt: &Statement, consts: &HashMap<String, Expression>) -> Result<Statement, CompilerError> {
+            Ok(match stmt {
+                Statement::Assignment(id, value) => Statement::Assignment(id.clone(), handle_expr(value, consts, &mut Vec::new())?),
+                Statement::CompoundAssignment(id, op, value) => Statement::CompoundAssignment(id.clone(), op.clone(), handle_expr(value, consts, &mut Vec::new())?),
+                Statement::ExpressionWrapper(expr) => Statement::ExpressionWrapper(handle_expr(expr, consts, &mut Vec::new())?),
+                Statement::Goto(expr) => Statement::Goto(handle_expr(expr, consts, &mut Vec::new())?),
+                Statement::If(condition, pass, fail) => Statement::If(
+                    handle_expr(condition, consts, &mut Vec::new())?,
+                    Box::new(StatementList { statements: handle_stmts(&pass.statements, consts)? }),
+                    Box::new(StatementList { statements: handle_stmts(&fail.statements, consts)? })
+                ),
+
+                Statement::Empty() => Statement::Empty(),
+                Statement::GotoLabel(label) => Statement::GotoLabel(label.clone()),
+            })
+        }
+
+        fn handle_unary<F>(x: &Expression, consts: &HashMap<String, Expression>, expanding: &mut Vec<String>, build: fn(Box<Expression>) -> Expression, fold: F) -> Result<Expression, CompilerError>
+            where F: FnOnce(YololNumber) -> YololNumber
+        {
+            let x = handle_expr(x, consts, expanding)?;
+            return Ok(match x {
+                Expression::ConstantNumber(n) => Expression::ConstantNumber(fold(n)),
+                x => build(Box::new(x)),
+            });
+        }
+
+        fn handle_binary<F>(x: &Expression, y: &Expression, consts: &HashMap<String, Expression>, expanding: &mut Vec<String>, build: fn(Box<Expression>, Box<Expression>) -> Expression, fold: F) -> Result<Expression, CompilerError>
+            where F: FnOnce(Value, Value) -> Option<Value>
+        {
+            let x = handle_expr(x, consts, expanding)?;
+            let y = handle_expr(y, consts, expanding)?;
+            return Ok(fold_binary(x, y, build, fold));
+        }
+
+        // Combine two already folded expressions, folding them into a single constant if both are constant
+        fn fold_binary<F>(x: Expression, y: Expression, build: fn(Box<Expression>, Box<Expression>) -> Expression, fold: F) -> Expression
+            where F: FnOnce(Value, Value) -> Option<Value>
+        {
+            if let (Some(a), Some(b)) = (Value::from_expr(&x), Value::from_expr(&y)) {
+                if let Some(r) = fold(a, b) {
+                    return r.to_expr();
+                }
+            }
+
+            return build(Box::new(x), Box::new(y));
+        }
+
+        // Divide or modulus, it is an error if the divisor folds to zero
+        fn handle_division(expr: &Expression, x: &Expression, y: &Expression, consts: &HashMap<String, Expression>, expanding: &mut Vec<String>, build: fn(Box<Expression>, Box<Expression>) -> Expression, f: fn(YololNumber, YololNumber) -> YololNumber) -> Result<Expression, CompilerError> {
+            let x = handle_expr(x, consts, expanding)?;
+            let y = handle_expr(y, consts, expanding)?;
+
+            if let Expression::ConstantNumber(n) = &y {
+                if *n == YololNumber::zero() {
+                    return Err(CompilerError::ConstantDivisionByZero(expr.clone()));
+                }
+            }
+
+            return Ok(fold_binary(x, y, build, numeric(f)));
+        }
+
+        fn numeric(f: fn(YololNumber, YololNumber) -> YololNumber) -> impl FnOnce(Value, Value) -> Option<Value> {
+            move |a, b| match (a, b) {
+                (Value::Num(a), Value::Num(b)) => Some(Value::Num(f(a, b))),
+                _ => None
+            }
+        }
+
+        // Strings cannot be used as conditions, so they're left for the error to happen at runtime
+        fn logical(f: fn(bool, bool) -> bool) -> impl FnOnce(Value, Value) -> Option<Value> {
+            move |a, b| Some(Value::from_bool(f(a.truthy()?, b.truthy()?)))
+        }
+
+        fn compare(f: fn(std::cmp::Ordering) -> bool) -> impl FnOnce(Value, Value) -> Option<Value> {
+            move |a, b| Some(Value::from_bool(f(a.compare(&b))))
+        }
+
+        // Fold an expression. `expanding` holds the names of the constants whose values are being substituted, a constant which refers to
+        // itself (directly or through other constants) would never finish.
+        fn handle_expr(expr: &Expression, consts: &HashMap<String, Expression>, expanding: &mut Vec<String>) -> Result<Expression, CompilerError> {
+            Ok(match expr {
+                Expression::ConstantNumber(_) => expr.clone(),
+                Expression::ConstantString(_) => expr.clone(),
+
+                // Substitute the value of constant fields inline
+                Expression::VariableAccess(id) => {
+                    match consts.get(&id.name) {
+                        Some(value) if !id.external => {
+                            if let Some(start) = expanding.iter().position(|n| *n == id.name) {
+                                return Err(CompilerError::RecursiveConstant(expanding[start..].iter().cloned().chain(std::iter::once(id.name.clone())).collect()));
+                            }
+
+                            expanding.push(id.name.clone());
+                            let value = handle_expr(value, consts, expanding)?;
+                            expanding.pop();
+
+                            match value {
+                                v @ Expression::ConstantNumber(_) => v,
+                                v @ Expression::ConstantString(_) => v,
+                                v @ Expression::VariableAccess(_) => v,
+                                v @ Expression::Bracket(_) => v,
+                                v => Expression::Bracket(Box::new(v))
+                            }
+                        },
+                        _ => expr.clone()
+                    }
+                },
+
+                Expression::Bracket(x) => {
+                    match handle_expr(x, consts, expanding)? {
+                        v @ Expression::ConstantNumber(_) => v,
+                        v @ Expression::ConstantString(_) => v,
+                        v => Expression::Bracket(Box::new(v))
+                    }
+                },
+
+                Expression::ACos(x) => handle_unary(x, consts, expanding, Expression::ACos, |n| n.acos())?,
+                Expression::ASin(x) => handle_unary(x, consts, expanding, Expression::ASin, |n| n.asin())?,
+                Expression::ATan(x) => handle_unary(x, consts, expanding, Expression::ATan, |n| n.atan())?,
+                Expression::Sqrt(x) => handle_unary(x, consts, expanding, Expression::Sqrt, |n| n.sqrt())?,
+                Expression::Cosine(x) => handle_unary(x, consts, expanding, Expression::Cosine, |n| n.cos())?,
+                Expression::Sine(x) => handle_unary(x, consts, expanding, Expression::Sine, |n| n.sin())?,
+                Expression::Tangent(x) => handle_unary(x, consts, expanding, Expression::Tangent, |n| n.tan())?,
+                Expression::Abs(x) => handle_unary(x, consts, expanding, Expression::Abs, |n| n.abs())?,
+                Expression::Negate(x) => handle_unary(x, consts, expanding, Expression::Negate, |n| -n)?,
+                Expression::Not(x) => handle_unary(x, consts, expanding, Expression::Not, |n| !n)?,
+
+                // Increments modify a field, so they can never be folded away
+                Expression::PostDecrement(_) => expr.clone(),
+                Expression::PostIncrement(_) => expr.clone(),
+                Expression::PreDecrement(_) => expr.clone(),
+                Expression::PreIncrement(_) => expr.clone(),
+
+                Expression::Add(x, y) => handle_binary(x, y, consts, expanding, Expression::Add, |a, b| Some(a.add(b)))?,
+                Expression::Subtract(x, y) => handle_binary(x, y, consts, expanding, Expression::Subtract, |a, b| Some(a.subtract(b)))?,
+
+                Expression::Multiply(x, y) => handle_binary(x, y, consts, expanding, Expression::Multiply, numeric(|a, b| a * b))?,
+                Expression::Exponent(x, y) => handle_binary(x, y, consts, expanding, Expression::Exponent, numeric(|a, b| a.pow(b)))?,
+                Expression::Divide(x, y) => handle_division(expr, x, y, consts, expanding, Expression::Divide, |a, b| a / b)?,
+                Expression::Modulus(x, y) => handle_division(expr, x, y, consts, expanding, Expression::Modulus, |a, b| a % b)?,
+
+                Expression::And(x, y) => handle_binary(x, y, consts, expanding, Expression::And, logical(|a, b| a && b))?,
+                Expression::Or(x, y) => handle_binary(x, y, consts, expanding, Expression::Or, logical(|a, b| a || b))?,
+
+                Expression::Equal(x, y) => handle_binary(x, y, consts, expanding, Expression::Equal, compare(|o| o.is_eq()))?,
+                Expression::NotEqual(x, y) => handle_binary(x, y, consts, expanding, Expression::NotEqual, compare(|o| o.is_ne()))?,
+                Expression::GreaterThan(x, y) => handle_binary(x, y, consts, expanding, Expression::GreaterThan, compare(|o| o.is_gt()))?,
+                Expression::GreaterThanOrEq(x, y) => handle_binary(x, y, consts, expanding, Expression::GreaterThanOrEq, compare(|o| o.is_ge()))?,
+                Expression::LessThan(x, y) => handle_binary(x, y, consts, expanding, Expression::LessThan, compare(|o| o.is_lt()))?,
+                Expression::LessThanOrEq(x, y) => handle_binary(x, y, consts, expanding, Expression::LessThanOrEq, compare(|o| o.is_le()))?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::error::CompilerError;
+    use crate::yolol::ast::{ Statement, Expression, Identifier };
+    use super::super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+
+    fn num(n: i64) -> Box<Expression> {
+        Box::new(Expression::ConstantNumber(YololNumber::from_value(n)))
+    }
+
+    fn var(name: &str) -> Box<Expression> {
+        Box::new(Expression::VariableAccess(Identifier { name: name.to_string(), external: false }))
+    }
+
+    fn fold(expr: Expression, consts: Vec<(&str, Expression)>) -> Result<Expression, CompilerError> {
+        let blocks = YololStatementBlocks {
+            blocks: vec![ YololBlock::Statements(None, vec![ Statement::ExpressionWrapper(expr) ]) ],
+            types: HashMap::new(),
+            consts: consts.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+        };
+
+        let mut folded = blocks.fold_constants()?;
+        match folded.blocks.remove(0) {
+            YololBlock::Statements(_, mut stmts) => match stmts.remove(0) {
+                Statement::ExpressionWrapper(e) => Ok(e),
+                _ => panic!("Unexpected statement")
+            },
+            _ => panic!("Unexpected block")
+        }
+    }
+
+    #[test]
+    fn fold_numbers_and_consts() {
+        let pi = ("pi", Expression::ConstantNumber("3.141".parse().unwrap()));
+        let tau = ("tau", Expression::Multiply(num(2), var("pi")));
+
+        match fold(Expression::Add(num(1), var("tau")), vec![pi, tau]) {
+            Ok(Expression::ConstantNumber(n)) => assert_eq!("7.282".parse::<YololNumber>().unwrap(), n),
+            _ => panic!("Expected a constant number")
+        }
+    }
+
+    #[test]
+    fn fold_strings() {
+        let abcabc = Box::new(Expression::ConstantString("abcabc".to_string()));
+        let bc = Box::new(Expression::ConstantString("bc".to_string()));
+
+        match fold(Expression::Add(Box::new(Expression::Subtract(abcabc, bc)), num(1)), vec![]) {
+            Ok(Expression::ConstantString(s)) => assert_eq!("abca1", s),
+            _ => panic!("Expected a constant string")
+        }
+    }
+
+    #[test]
+    fn fold_string_number_comparison() {
+        let ten = Box::new(Expression::ConstantString("10".to_string()));
+
+        match fold(Expression::Equal(ten, num(10)), vec![]) {
+            Ok(Expression::ConstantNumber(n)) => assert_eq!(YololNumber::one(), n),
+            _ => panic!("Expected a constant number")
+        }
+    }
+
+    #[test]
+    fn fold_divide_by_zero() {
+        match fold(Expression::Divide(var("a"), Box::new(Expression::Subtract(num(2), num(2)))), vec![]) {
+            Err(CompilerError::ConstantDivisionByZero(_)) => {},
+            _ => panic!("Expected division by zero error")
+        }
+    }
+    #[test]
+    fn fold_recursive_consts() {
+        let a = ("a", Expression::Add(var("b"), num(1)));
+        let b = ("b", Expression::Multiply(var("a"), num(2)));
+
+        match fold(Expression::Add(var("a"), num(1)), vec![a, b]) {
+            Err(CompilerError::RecursiveConstant(chain)) => assert_eq!(vec![ "a", "b", "a" ], chain),
+            _ => panic!("Expected recursive constant error")
+        }
+    }
+}
\ No newline at end of file
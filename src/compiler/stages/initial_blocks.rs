@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 
-use crate::grammar::ast::{ Program, Main, InnerStatement, OuterStatement, CallableDefinition, StructDefinition };
+use crate::grammar::ast::{ Program, Main, InnerStatement, OuterStatement, CallableDefinition, StructDefinition, Constant, FieldDefinition, TypeName, Attribute, Expression };
 use crate::error::{ CompilerError };
 use super::super::build_config::BuildConfig;
+use super::loops::{ lower_loops, lower_matches };
+use super::super::fields::{ canonicalise_field_path };
+use super::super::overloads::{ group_overloads };
 
 #[derive(Debug)]
 pub enum Block {
@@ -17,28 +20,51 @@ pub enum Block {
 pub struct InitialStatementBlocks {
     pub blocks: Vec<Block>,
 
-    pub callables: HashMap<String, CallableDefinition>,
+    // Every callable with a given name, overloads are resolved when they are called
+    pub callables: HashMap<String, Vec<CallableDefinition>>,
     pub structs: HashMap<String, StructDefinition>,
 }
 
+// Check if every config named by a `cfg("name")` attribute is enabled in the build config
+fn is_enabled(attribute: &Attribute, config: &BuildConfig) -> bool {
+    return attribute.parameters.iter().all(|p| match p {
+        Expression::ConstString(name) => config.configs.contains(name),
+        _ => false
+    });
+}
+
 impl Program {
     pub fn build_blocks(self, config: &BuildConfig) -> Result<InitialStatementBlocks, CompilerError> {
 
-        fn extract_main(main: Main) -> Vec<Block> {
+        fn extract_main(main: Main, constants: Vec<Constant>, config: &BuildConfig) -> Result<Vec<Block>, CompilerError> {
+            let statements = lower_loops(main.statements, &constants, config)?;
+
             let mut result: Vec<Block> = Vec::new();
-            let mut current: Vec<OuterStatement> = Vec::new();
+
+            // Top level constants are declared at the very start of the program, before any other statements
+            let mut current: Vec<OuterStatement> = constants
+                .into_iter()
+                .map(|c| OuterStatement::Inner(InnerStatement::DeclareConst(c.field, c.value)))
+                .collect();
             let mut current_name = None;
     
-            for stmt in main.statements.into_iter() {
+            fn push(result: &mut Vec<Block>, name: Option<String>, stmts: Vec<OuterStatement>) {
+                // An empty block with no label can never do anything, leave it out so labels are next to each other
+                if name.is_some() || stmts.len() > 0 || result.len() == 0 {
+                    result.push(Block::Statements(name, stmts));
+                }
+            }
+
+            for stmt in statements.into_iter() {
                 match stmt {
                     OuterStatement::Line(inner, label) => {
-                        result.push(Block::Statements(current_name.clone(), current));
+                        push(&mut result, current_name.clone(), current);
                         current = Vec::new();
                         current_name = None;
                         result.push(Block::Line(label, inner));
                     },
                     OuterStatement::Label(name) => {
-                        result.push(Block::Statements(current_name.clone(), current));
+                        push(&mut result, current_name.clone(), current);
                         current_name = Some(name.clone());
                         current = Vec::new();
                     }
@@ -46,18 +72,40 @@ impl Program {
                 }
             }
     
-            result.push(Block::Statements(None, current));
+            push(&mut result, current_name, current);
     
-            return result;
+            return Ok(result);
         }
-    
-        fn extract_calls(callables: Vec<CallableDefinition>) -> HashMap<String, CallableDefinition> {
-            return callables.iter().map(|c| (c.name.clone(), c.clone())).collect();
+
+        // Enum items are constants of the enum base type, accessed as `enum_name.item_name`
+        let mut constants = self.constants;
+        for e in self.enums.iter() {
+            for item in e.items.iter() {
+                constants.push(Constant {
+                    field: FieldDefinition {
+                        name: canonicalise_field_path(&vec![ e.name.clone(), item.name.clone() ]),
+                        typename: TypeName { typename: e.base.clone() }
+                    },
+                    value: item.value.clone(),
+                    public: e.public
+                });
+            }
         }
 
+        // Callables are inlined after loops in main are lowered, so `match` must be lowered in their bodies now. Callables with a
+        // `cfg` attribute are left out unless every config they name is enabled.
+        let callables = self.callables
+            .into_iter()
+            .filter(|c| c.attributes.iter().filter(|a| a.name == "cfg").all(|a| is_enabled(a, config)))
+            .map(|mut c| {
+                c.statements = lower_matches(c.statements, &constants, config)?;
+                return Ok(c);
+            })
+            .collect::<Result<Vec<_>, CompilerError>>()?;
+
         return Ok(InitialStatementBlocks {
-            blocks: extract_main(self.main.ok_or(CompilerError::NoMainBlock)?),
-            callables: self.callables.iter().map(|c| (c.name.clone(), c.clone())).collect(),
+            blocks: extract_main(self.main.ok_or(CompilerError::NoMainBlock)?, constants, config)?,
+            callables: group_overloads(callables)?,
             structs: self.structs.iter().map(|c| (c.name.clone(), c.clone())).collect(),
         });
     }
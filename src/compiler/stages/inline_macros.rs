@@ -1,42 +1,56 @@
 use std::collections::HashMap;
 
 use uuid::Uuid;
+use yolol_number::prelude::*;
 
 use crate::error::{ CompilerError };
-use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression, CallableDefinition, FieldDefinition, CallType, ParameterDefinition };
+use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression, CallableDefinition, StructDefinition, FieldDefinition, CallType, TypeName };
+use crate::grammar::visit::{ visit_expr_mut, visit_stmt_mut, visit_stmt_exprs_mut };
+use crate::compiler::intrinsics::{ find_intrinsic, find_string_helper };
 use super::initial_blocks::{ InitialStatementBlocks, Block };
-use super::super::typecheck::{ infer_expr_type, Type, type_check_assignment };
+use super::string_loops::{ check_no_string_loops };
+use super::super::typecheck::{ Type, type_check_assignment };
 use super::super::build_config::BuildConfig;
+use super::super::overloads::{ resolve_overload };
+use super::super::const_eval::{ is_const, is_constant, evaluate_call };
+use super::super::fields::{ canonicalise_field_path };
+use super::super::call_graph::{ check_recursion, max_depth };
 
 impl InitialStatementBlocks {
     pub fn inline_macros(self, config: &BuildConfig) -> Result<InitialStatementBlocks, CompilerError> {
 
         let blocks = self.blocks;
         let callables = self.callables;
+        let structs = self.structs;
         let mut types = HashMap::new();
+        let mut consts = HashMap::new();
+        let mut stack = Vec::new();
+
+        // Inlining a macro which calls itself would never finish
+        check_recursion(&callables, &structs)?;
 
         let result: Result<_, _> = blocks
             .into_iter()
-            .map(|x| handle_block(x, &callables, &mut types, config))
+            .map(|x| handle_block(x, &callables, &structs, &mut types, &mut consts, config, &mut stack))
             .collect();
 
         return Ok(InitialStatementBlocks {
             blocks: result?,
             callables: callables,
-            structs: self.structs,
+            structs: structs,
         });
 
-        fn handle_block(b: Block, callables: &HashMap<String, CallableDefinition>, types: &mut HashMap<String, Type>, config: &BuildConfig) -> Result<Block, CompilerError> {
+        fn handle_block(b: Block, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>) -> Result<Block, CompilerError> {
             match b {
-                Block::Statements(label, stmts) => Ok(Block::Statements(label, handle_outer_stmts(stmts, callables, types, config)?)),
-                Block::Line(label, stmts) => Ok(Block::Line(label, handle_inner_stmts(stmts, callables, types, config)?)),
+                Block::Statements(label, stmts) => Ok(Block::Statements(label, handle_outer_stmts(stmts, callables, structs, types, consts, config, stack)?)),
+                Block::Line(label, stmts) => Ok(Block::Line(label, handle_inner_stmts(stmts, callables, structs, types, consts, config, stack)?)),
             }
         }
 
-        fn handle_outer_stmts(stmts: Vec::<OuterStatement>, callables: &HashMap<String, CallableDefinition>, types: &mut HashMap<String, Type>, config: &BuildConfig) -> Result<Vec<OuterStatement>, CompilerError> {
+        fn handle_outer_stmts(stmts: Vec::<OuterStatement>, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>) -> Result<Vec<OuterStatement>, CompilerError> {
             Ok(stmts
                 .into_iter()
-                .map(|x| handle_outer_stmt(x, callables, types, config))
+                .map(|x| handle_outer_stmt(x, callables, structs, types, consts, config, stack))
                 .collect::<Result<Vec<_>, CompilerError>>()?
                 .into_iter()
                 .flatten()
@@ -44,22 +58,27 @@ impl InitialStatementBlocks {
             )
         }
 
-        fn handle_outer_stmt(outer: OuterStatement, callables: &HashMap<String, CallableDefinition>, types: &mut HashMap<String, Type>, config: &BuildConfig) -> Result<Vec<OuterStatement>, CompilerError> {
+        fn handle_outer_stmt(outer: OuterStatement, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>) -> Result<Vec<OuterStatement>, CompilerError> {
             match outer {
-                OuterStatement::Inner(inner) => Ok(handle_inner_stmt(inner, callables, types, config)?.into_iter().map(|x| OuterStatement::Inner(x)).collect()),
+                OuterStatement::Inner(inner) => Ok(handle_inner_stmt(inner, callables, structs, types, consts, config, stack)?.into_iter().map(|x| OuterStatement::Inner(x)).collect()),
 
                 // There should be no `Label` statements here, they've been promoted into named blocks by the initial_blocks pass
                 OuterStatement::Label(name) => panic!("Encountered label `{}` as an outer statement (1b566df9-0d58-4bd8-aade-89a2e5a4397b)", name),
 
                 // There should be no `Line` statements here, they've been separated into Line blocks by the initial_blocks pass
                 OuterStatement::Line(_, name) => panic!("Encountered line `{:?}` as an outer statement (bf0389c9-db37-4019-99e9-62747d842146)", name),
+
+                // There should be no loops here, they've been lowered into labels and gotos by the initial_blocks pass
+                OuterStatement::Loop(_) => panic!("Encountered loop as an outer statement (1c786a07-d932-4a64-81c0-64d419f0e4d8)"),
+                OuterStatement::While(_, _) => panic!("Encountered while loop as an outer statement (b8e8829f-b3ae-4e7b-8b64-a0937e965ece)"),
+                OuterStatement::For(_, _, _, _) => panic!("Encountered for loop as an outer statement (4c1658ba-852f-4865-be9f-9368fe0426ef)"),
             }
         }
 
-        fn handle_inner_stmts(stmts: Vec::<InnerStatement>, callables: &HashMap<String, CallableDefinition>, types: &mut HashMap<String, Type>, config: &BuildConfig) -> Result<Vec<InnerStatement>, CompilerError> {
+        fn handle_inner_stmts(stmts: Vec::<InnerStatement>, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>) -> Result<Vec<InnerStatement>, CompilerError> {
             Ok(stmts
                 .into_iter()
-                .map(|x| handle_inner_stmt(x, callables, types, config))
+                .map(|x| handle_inner_stmt(x, callables, structs, types, consts, config, stack))
                 .collect::<Result<Vec<_>, CompilerError>>()?
                 .into_iter()
                 .flatten()
@@ -67,9 +86,41 @@ impl InitialStatementBlocks {
             )
         }
 
-        fn handle_inner_stmt(inner: InnerStatement, callables: &HashMap<String, CallableDefinition>, types: &mut HashMap<String, Type>, config: &BuildConfig) -> Result<Vec<InnerStatement>, CompilerError> {
+        fn handle_inner_stmt(inner: InnerStatement, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>) -> Result<Vec<InnerStatement>, CompilerError> {
+
+            let inner = match inner {
+                InnerStatement::MethodCall(path, method, args) => {
+                    let (name, args) = method_call(&path, &method, args, types, structs)?;
+                    InnerStatement::Call(name, args)
+                },
+                a => a
+            };
+
+            // Calls inside expressions are inlined before this statement, with the result stored in a field
+            let (mut result, inner) = hoist_calls(inner, callables, structs, types, consts, config, stack)?;
+
             match inner {
-                InnerStatement::Call(ref name, ref args) => handle_call_stmt(name, args, callables, None, types, config),
+                InnerStatement::Call(name, args) => {
+                    if callables.contains_key(&name) {
+                        // A recursive `proc` which has reached its depth limit is not called
+                        if let Some((stmts, _)) = inline_call(&name, &args, callables, types, stack)? {
+                            stack.push(name);
+                            let stmts = handle_inner_stmts(stmts, callables, structs, types, consts, config, stack);
+                            stack.pop();
+                            result.append(&mut stmts?);
+                        }
+                    } else if find_intrinsic(&name).is_some() || find_string_helper(&name).is_some() {
+                        result.push(InnerStatement::ExpressionWrapper(Expression::Call(name, args)));
+                    } else {
+                        return Err(CompilerError::CallableNotFound(name));
+                    }
+                },
+
+                InnerStatement::If(condition, pass, fail) => {
+                    let pass = handle_inner_stmts(pass, callables, structs, types, consts, config, stack)?;
+                    let fail = handle_inner_stmts(fail, callables, structs, types, consts, config, stack)?;
+                    result.push(InnerStatement::If(condition, pass, fail));
+                },
 
                 InnerStatement::DeclareAssign(field, value) => {
                     if types.contains_key(&field.name) {
@@ -77,8 +128,8 @@ impl InitialStatementBlocks {
                     }
 
                     types.insert(field.name.clone(), field.typename.to_type());
-    
-                    return Ok(vec![InnerStatement::DeclareAssign(field, value)]);
+
+                    result.push(InnerStatement::DeclareAssign(field, value));
                 }
 
                 InnerStatement::DeclareConst(field, value) => {
@@ -87,77 +138,607 @@ impl InitialStatementBlocks {
                     }
 
                     types.insert(field.name.clone(), field.typename.to_type());
+                    if is_constant(&value) {
+                        consts.insert(field.name.clone(), value.clone());
+                    }
 
-                    return Ok(vec![InnerStatement::DeclareConst(field, value)]);
+                    result.push(InnerStatement::DeclareConst(field, value));
                 },
 
-                //todo: check expressions for embedded calls
-                a => Ok(vec![a])
+                a => result.push(a)
             }
+
+            return Ok(result);
         }
 
-        fn handle_call_stmt(name: &String, args: &Vec<Expression>, callables: &HashMap<String, CallableDefinition>, return_field: Option<Vec<String>>, types: &HashMap<String, Type>, config: &BuildConfig) -> Result<Vec<InnerStatement>, CompilerError> {
+        // Inline every call to a macro inside the expressions of this statement, returning the (already handled) statements which calculate
+        // the results. Only the condition of an `if` is always evaluated, calls in the branches are inlined into the branches.
+        fn hoist_calls(mut stmt: InnerStatement, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>) -> Result<(Vec<InnerStatement>, InnerStatement), CompilerError> {
+            let mut hoisted = Vec::new();
+            let mut error = None;
+
+            match &mut stmt {
+                InnerStatement::If(condition, _, _) => hoist_tree(condition, callables, structs, types, consts, config, stack, &mut hoisted, &mut error),
+                other => visit_stmt_exprs_mut(other, &mut |e| hoist_node(e, callables, structs, types, consts, config, stack, &mut hoisted, &mut error)),
+            }
+
+            return match error {
+                Some(e) => Err(e),
+                None => Ok((hoisted, stmt))
+            };
+        }
+
+        fn hoist_tree(expr: &mut Expression, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>, hoisted: &mut Vec<InnerStatement>, error: &mut Option<CompilerError>) {
+            visit_expr_mut(expr, &mut |e| hoist_node(e, callables, structs, types, consts, config, stack, hoisted, error));
+        }
+
+        // Inline a call, the arguments are inlined first so their types are known when the call is inlined
+        fn hoist_node(expr: &mut Expression, callables: &HashMap<String, Vec<CallableDefinition>>, structs: &HashMap<String, StructDefinition>, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, Expression>, config: &BuildConfig, stack: &mut Vec<String>, hoisted: &mut Vec<InnerStatement>, error: &mut Option<CompilerError>) {
+            if let (Expression::MethodCall(path, method, args), None) = (&*expr, &*error) {
+                match method_call(path, method, args.clone(), types, structs) {
+                    Ok((name, args)) => *expr = Expression::Call(name, args),
+                    Err(err) => *error = Some(err),
+                }
+            }
+
+            let (name, args) = match expr {
+                Expression::Call(name, args) if error.is_none() && callables.contains_key(name) => (name.clone(), args),
+                Expression::Call(name, _) if error.is_none() && find_intrinsic(name).is_none() && find_string_helper(name).is_none() => {
+                    *error = Some(CompilerError::CallableNotFound(name.clone()));
+                    return;
+                },
+                _ => return
+            };
+
+            args.iter_mut().for_each(|a| hoist_tree(a, callables, structs, types, consts, config, stack, hoisted, error));
+            if error.is_some() {
+                return;
+            }
+
+            // Const macros called with constant arguments are evaluated at compile time
+            match const_call(&name, args, callables, types, consts) {
+                Ok(Some(value)) => { *expr = value; return; },
+                Ok(None) => {},
+                Err(err) => { *error = Some(err); return; }
+            }
+
+            let inlined = inline_call(&name, args, callables, types, stack).and_then(|inlined| {
+                let (stmts, value) = inlined.ok_or_else(|| CompilerError::RecursionTooDeep(name.clone(), stack.iter().filter(|n| **n == name).count()))?;
+                let value = value.ok_or(CompilerError::NoReturnValue(name.clone()))?;
+
+                stack.push(name.clone());
+                let stmts = handle_inner_stmts(stmts, callables, structs, types, consts, config, stack);
+                stack.pop();
+                hoisted.append(&mut stmts?);
+                Ok(value)
+            });
+
+            match inlined {
+                Ok(value) => *expr = value,
+                Err(err) => *error = Some(err),
+            }
+        }
+
+        // Evaluate a call to a const macro if every argument is a constant (or a field declared `const` with a constant value)
+        fn const_call(name: &String, args: &Vec<Expression>, callables: &HashMap<String, Vec<CallableDefinition>>, types: &HashMap<String, Type>, consts: &HashMap<String, Expression>) -> Result<Option<Expression>, CompilerError> {
+            let (callable, args, _) = resolve_overload(name, args, types, callables)?;
+            if !is_const(callable) {
+                return Ok(None);
+            }
+
+            let args: Vec<_> = args.into_iter().map(|a| match &a {
+                Expression::FieldAccess(path) => consts.get(&canonicalise_field_path(path)).cloned().unwrap_or(a),
+                _ => a
+            }).collect();
+
+            if !args.iter().all(is_constant) {
+                return Ok(None);
+            }
+
+            return Ok(Some(evaluate_call(callable, args, callables, consts)?));
+        }
 
-            let callable = match callables.get(name) {
-                Some(c) => c,
-                None => return Err(CompilerError::CallableNotFound(name.clone()))
+        // Find the callable for a method call, the struct at the path is passed as the first (`self`) argument. If the path is not a field
+        // the method is called without `self`, e.g. `pid.new()`.
+        fn method_call(path: &Vec<String>, method: &str, mut args: Vec<Expression>, types: &HashMap<String, Type>, structs: &HashMap<String, StructDefinition>) -> Result<(String, Vec<Expression>), CompilerError> {
+            let mut typename = match types.get(&path[0]) {
+                Some(t) => t.to_typename().typename,
+                None => return Ok((format!("{}.{}", path.join("."), method), args)),
             };
 
-            if callable.return_type.is_some() {
-                return Err(CompilerError::CompilerStageNotImplemented("return values from calls are not implemented".to_string()));
+            for name in path[1..].iter() {
+                typename = match structs.get(&typename).and_then(|s| s.fields.iter().find(|f| f.name == *name)) {
+                    Some(f) => f.typename.typename.clone(),
+                    None => return Err(CompilerError::FieldTypeNotKnown(path.clone()))
+                };
             }
 
-            if callable.attributes.len() != 0 {
+            args.insert(0, Expression::FieldAccess(path.clone()));
+            return Ok((format!("{}.{}", typename, method), args));
+        }
+
+        // Create a copy of the body of a macro for a single call. Parameters are replaced with the arguments and fields declared inside the
+        // macro are renamed, so every call has its own copy. Returns the statements and the field holding the return value (if there is one).
+        // A `proc` with a `max_depth` is copied in the same way, `stack` holds the callables being inlined and once the `proc` is already
+        // being inlined `max_depth` times nothing is returned.
+        fn inline_call(name: &String, args: &Vec<Expression>, callables: &HashMap<String, Vec<CallableDefinition>>, types: &HashMap<String, Type>, stack: &Vec<String>) -> Result<Option<(Vec<InnerStatement>, Option<Expression>)>, CompilerError> {
+
+            // Pick which overload to call from the types of the arguments
+            let (callable, args, arg_types) = resolve_overload(name, args, types, callables)?;
+
+            if callable.attributes.iter().any(|a| a.name != "const" && a.name != "max_depth" && a.name != "cfg") {
                 return Err(CompilerError::CompilerStageNotImplemented("Call attributes are not implemented".to_string()));
             }
 
             if let CallType::Proc = callable.call_type {
-                return Err(CompilerError::CompilerStageNotImplemented("`proc` calls are not implemented".to_string()));
+                match max_depth(callable) {
+                    None => return Err(CompilerError::CompilerStageNotImplemented("`proc` calls are not implemented".to_string())),
+                    Some(depth) if stack.iter().filter(|n| *n == name).count() >= depth => return Ok(None),
+                    Some(_) => {}
+                }
             }
 
-            // Check that the correct numbers of parameters were passed
-            if callable.parameters.len() != args.len() {
-                return Err(CompilerError::IncorrectCallParameterCount(name.clone(), callable.parameters.len(), args.len()));
+            // Loops cannot be placed inside the body of a macro
+            check_no_string_loops(&callable.statements)?;
+
+            // Find the concrete type of every type parameter from the first argument passed to a parameter of that type, then make a copy of the
+            // macro with the type parameters replaced
+            let mut generics = HashMap::new();
+            for (param, t) in callable.parameters.iter().zip(arg_types.iter()) {
+                if callable.type_parameters.contains(&param.field.typename.typename) {
+                    generics.entry(param.field.typename.typename.clone()).or_insert(t.to_typename());
+                }
             }
+            if let Some(t) = callable.type_parameters.iter().find(|t| !generics.contains_key(*t)) {
+                return Err(CompilerError::TypeParameterNotInferred(name.clone(), t.clone()));
+            }
+            let callable = monomorphise(callable, &generics);
 
             let mut result: Vec<InnerStatement> = Vec::new();
-            let mut bindings: HashMap<&ParameterDefinition, Expression> = HashMap::new();
-            
+            let mut bindings: HashMap<String, Expression> = HashMap::new();
+            let prefix = format!("_{}_{}", name.replace(":", "_"), Uuid::new_v4().to_simple());
+
             // Build a list of bindings, every time a parameter is accessed inside the macro body the binding value will be used instead
-            for (param, arg) in callable.parameters.iter().zip(args) {
-                match arg {
-                    Expression::ExternalFieldAccess(name) => { bindings.insert(param, Expression::ExternalFieldAccess(name.clone())); }, //todo: type check externals?
-                    Expression::FieldAccess(name) => { bindings.insert(param, Expression::FieldAccess(name.clone())); },
-                    Expression::ConstNumber(num) => { bindings.insert(param, Expression::ConstNumber(num.clone())); },
-                    Expression::ConstString(string) => { bindings.insert(param, Expression::ConstString(string.clone())); },
-                    other => {
-                        // Assign expression value to a temp, pass temp into macro
-                        let n = format!("_tmp_{}", Uuid::new_v4().to_simple());
-                        let t = infer_expr_type(other, types)?;
-                        let d = OuterStatement::Inner(InnerStatement::DeclareAssign(
-                            FieldDefinition { name: n.clone(), typename: infer_expr_type(other, types)?.to_typename() },
-                            other.clone()
-                        ));
-                        bindings.insert(param, Expression::FieldAccess(vec![n]));
-                    }
+            for ((param, arg), arg_type) in callable.parameters.iter().zip(args).zip(arg_types) {
+
+                // Check that every argument has a compatible type with the parameter it's bound to. Externals have no known type,
+                // so they can be passed to any parameter.
+                let external = match arg { Expression::ExternalFieldAccess(_) => true, _ => false };
+                if !external {
+                    type_check_assignment(&param.field.typename.to_type(), &arg_type)?;
+                }
+
+                let binding = match arg {
+                    Expression::ExternalFieldAccess(_) |
+                    Expression::FieldAccess(_) if !param.copy => Some(arg.clone()),
+                    Expression::ConstNumber(_) |
+                    Expression::ConstString(_) if !param.copy && !writes_field(&callable.statements, &param.field.name) => Some(arg.clone()),
+                    _ => None
                 };
+
+                // Assign expression value to a temp, pass temp into macro
+                let binding = binding.unwrap_or_else(|| {
+                    let n = format!("{}_{}", prefix, param.field.name);
+                    let typename = if external { arg_type.to_typename() } else { param.field.typename.clone() };
+                    result.push(InnerStatement::DeclareAssign(FieldDefinition { name: n.clone(), typename: typename }, arg.clone()));
+                    Expression::FieldAccess(vec![ n ])
+                });
+                bindings.insert(param.field.name.clone(), binding);
+            }
+
+            // Rename every field declared inside the macro body
+            let mut locals = HashMap::new();
+            for stmt in callable.statements.iter() {
+                visit_stmt_mut(&mut stmt.clone(), &mut |s| match s {
+                    InnerStatement::DeclareAssign(f, _) |
+                    InnerStatement::DeclareConst(f, _) => { locals.insert(f.name.clone(), format!("{}_{}", prefix, f.name)); },
+                    _ => {}
+                });
+            }
+
+            let mut body = callable.statements.clone();
+            for stmt in body.iter_mut() {
+                rewrite_stmt(stmt, &bindings, &locals);
+            }
+
+            // A macro which returns a value must end with a `return`, which is stored into a field
+            let (value, typename) = match &callable.return_type {
+                None => (None, None),
+                Some(return_type) => match body.last() {
+                    Some(InnerStatement::Return(_)) => (Some(format!("{}_return", prefix)), Some(TypeName { typename: return_type.clone() })),
+                    _ => return Err(CompilerError::MissingReturn(name.clone()))
+                }
+            };
+
+            body = match (&value, typename) {
+                (Some(field), Some(typename)) => {
+                    // A `return` before the end sets a flag, the statements after the `if` containing it only run if the flag is not set
+                    let flag = format!("{}_returned", prefix);
+                    let early = body[..body.len() - 1].iter().any(contains_return);
+                    if early {
+                        result.push(InnerStatement::DeclareAssign(FieldDefinition { name: flag.clone(), typename: TypeName { typename: "number".to_string() } }, Expression::ConstNumber(YololNumber::zero())));
+                    }
+
+                    let mut declared = false;
+                    lower_returns(body, field, &typename, if early { Some(&flag) } else { None }, &mut declared)
+                },
+
+                // A macro without a return type cannot return a value
+                _ => match body.iter().any(contains_return) {
+                    true => return Err(CompilerError::MisplacedReturn(name.clone())),
+                    false => body
+                }
+            };
+
+            result.append(&mut body);
+            return Ok(Some((result, value.map(|v| Expression::FieldAccess(vec![ v ])))));
+        }
+
+        fn contains_return(stmt: &InnerStatement) -> bool {
+            let mut found = false;
+            visit_stmt_mut(&mut stmt.clone(), &mut |s| if let InnerStatement::Return(_) = s { found = true });
+            return found;
+        }
+
+        // Replace every `return` with an assignment to the return field. The first one converted declares the field, like the branches of
+        // a conditional expression. Statements after a `return` can never run and are dropped.
+        fn lower_returns(stmts: Vec<InnerStatement>, field: &String, typename: &TypeName, flag: Option<&String>, declared: &mut bool) -> Vec<InnerStatement> {
+            let mut result = Vec::new();
+            let mut stmts = stmts.into_iter();
+            while let Some(stmt) = stmts.next() {
+                match stmt {
+                    InnerStatement::Return(value) => {
+                        result.push(match *declared {
+                            true => InnerStatement::Assign(vec![ field.clone() ], value),
+                            false => InnerStatement::DeclareAssign(FieldDefinition { name: field.clone(), typename: typename.clone() }, value),
+                        });
+                        *declared = true;
+
+                        if let Some(flag) = flag {
+                            result.push(InnerStatement::Assign(vec![ flag.clone() ], Expression::ConstNumber(YololNumber::one())));
+                        }
+                        break;
+                    },
+
+                    InnerStatement::If(condition, pass, fail) if pass.iter().chain(fail.iter()).any(contains_return) => {
+                        let pass = lower_returns(pass, field, typename, flag, declared);
+                        let fail = lower_returns(fail, field, typename, flag, declared);
+                        result.push(InnerStatement::If(condition, pass, fail));
+
+                        let rest = lower_returns(stmts.collect(), field, typename, flag, declared);
+                        if let (Some(flag), false) = (flag, rest.is_empty()) {
+                            result.push(InnerStatement::If(Expression::Not(Box::new(Expression::FieldAccess(vec![ flag.clone() ]))), rest, vec![]));
+                        }
+                        break;
+                    },
+
+                    other => result.push(other)
+                }
+            }
+            return result;
+        }
+
+        // Replace every type parameter with the concrete type
+        fn monomorphise(callable: &CallableDefinition, generics: &HashMap<String, TypeName>) -> CallableDefinition {
+            let substitute = |t: &mut TypeName| {
+                if let Some(concrete) = generics.get(&t.typename) {
+                    *t = concrete.clone();
+                } else if let Some((element, length)) = t.array() {
+                    if let Some(concrete) = generics.get(&element.typename) {
+                        t.typename = format!("{}[{}]", concrete.typename, length);
+                    }
+                }
+            };
+
+            let mut callable = callable.clone();
+            callable.type_parameters.clear();
+            callable.parameters.iter_mut().for_each(|p| substitute(&mut p.field.typename));
+            callable.return_type = callable.return_type.map(|r| {
+                let mut t = TypeName { typename: r };
+                substitute(&mut t);
+                t.typename
+            });
+
+            for stmt in callable.statements.iter_mut() {
+                visit_stmt_mut(stmt, &mut |s| match s {
+                    InnerStatement::DeclareAssign(f, _) |
+                    InnerStatement::DeclareConst(f, _) => substitute(&mut f.typename),
+                    _ => {}
+                });
+                visit_stmt_exprs_mut(stmt, &mut |e| if let Expression::Is(_, t) = e {
+                    substitute(t);
+                });
             }
 
-            // Check that every binding has a compatible type with the parameter it's bound to
-            for (param, expr) in bindings.iter() {
-                type_check_assignment(&param.field.typename.to_type(), &infer_expr_type(&expr, &types)?)?;
+            return callable;
+        }
+
+        // Check if the macro body modifies a field
+        fn writes_field(stmts: &Vec<InnerStatement>, name: &str) -> bool {
+            let mut writes = false;
+            for stmt in stmts.iter() {
+                let mut stmt = stmt.clone();
+                visit_stmt_mut(&mut stmt, &mut |s| match s {
+                    InnerStatement::Assign(path, _) |
+                    InnerStatement::AssignIndex(path, _, _) => writes |= path[0] == name,
+                    _ => {}
+                });
+                visit_stmt_exprs_mut(&mut stmt, &mut |e| match e {
+                    Expression::PostIncrement(path) |
+                    Expression::PostDecrement(path) |
+                    Expression::PreIncrement(path) |
+                    Expression::PreDecrement(path) => writes |= path[0] == name,
+                    _ => {}
+                });
             }
+            return writes;
+        }
 
-            // Rewrite the macro AST to replace field accesses. If they're an access to a parameter, use the expression from the `bindings` map
-            // otherwise mangle the name to a temporary which is only valid inside this call body.
-            // todo: ^
-            let mut rewritten = callable.clone();
-            panic!("rewrite");
+        // Replace parameters with their bindings and rename locals in a statement from a macro body
+        fn rewrite_stmt(stmt: &mut InnerStatement, bindings: &HashMap<String, Expression>, locals: &HashMap<String, String>) {
+            visit_stmt_mut(stmt, &mut |s| match s {
+                InnerStatement::DeclareAssign(f, _) |
+                InnerStatement::DeclareConst(f, _) => f.name = locals[&f.name].clone(),
 
-            // Now emit the rewritten AST
-            result.append(&mut rewritten.statements);
+                InnerStatement::Assign(path, value) => match rewrite_path(path, bindings, locals) {
+                    Err(external) => *s = InnerStatement::ExternalAssign(external, value.clone()),
+                    Ok(p) => *path = p,
+                },
+
+                InnerStatement::AssignIndex(path, _, _) |
+                InnerStatement::MethodCall(path, _, _) => if let Ok(p) = rewrite_path(path, bindings, locals) {
+                    *path = p;
+                },
 
+                _ => {}
+            });
+
+            visit_stmt_exprs_mut(stmt, &mut |e| match e {
+                Expression::FieldAccess(path) => match bindings.get(&path[0]) {
+                    Some(binding) if path.len() == 1 => *e = binding.clone(),
+                    _ => if let Ok(p) = rewrite_path(path, bindings, locals) { *path = p },
+                },
+
+                Expression::Index(path, _) |
+                Expression::MethodCall(path, _, _) => if let Ok(p) = rewrite_path(path, bindings, locals) {
+                    *path = p;
+                },
+
+                Expression::PostIncrement(path) => match rewrite_path(path, bindings, locals) { Ok(p) => *path = p, Err(x) => *e = Expression::ExternalPostIncrement(x) },
+                Expression::PostDecrement(path) => match rewrite_path(path, bindings, locals) { Ok(p) => *path = p, Err(x) => *e = Expression::ExternalPostDecrement(x) },
+                Expression::PreIncrement(path) => match rewrite_path(path, bindings, locals) { Ok(p) => *path = p, Err(x) => *e = Expression::ExternalPreIncrement(x) },
+                Expression::PreDecrement(path) => match rewrite_path(path, bindings, locals) { Ok(p) => *path = p, Err(x) => *e = Expression::ExternalPreDecrement(x) },
+
+                _ => {}
+            });
+        }
+
+        // Get the new path for a field used inside a macro body, or the name of the external field if the path is a parameter bound to an external field
+        fn rewrite_path(path: &Vec<String>, bindings: &HashMap<String, Expression>, locals: &HashMap<String, String>) -> Result<Vec<String>, String> {
+            let mut result = match (bindings.get(&path[0]), locals.get(&path[0])) {
+                (Some(Expression::FieldAccess(p)), _) => p.clone(),
+                (Some(Expression::ExternalFieldAccess(x)), _) => return Err(x.clone()),
+                (_, Some(local)) => vec![ local.clone() ],
+                _ => vec![ path[0].clone() ],
+            };
+            result.extend(path[1..].iter().cloned());
             return Ok(result);
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use crate::compiler::{ BuildConfig };
+    use crate::grammar::parser::y_parser;
+    use crate::error::{ CompilerError };
+    use crate::grammar::ast::{ InnerStatement, OuterStatement };
+    use crate::yolol::interpret::{ compile_and_run, Value };
+    use super::super::initial_blocks::{ Block };
+
+    fn run_program(code: &str) -> Result<Value, CompilerError> {
+        return Ok(compile_and_run(y_parser::program(code).ok().unwrap(), HashMap::new(), 100)?.remove(":out").unwrap());
+    }
+
+    const GENERIC: &str = "
+        def macro larger<T>(a: T, b: T) -> T {
+            var r: T = b;
+            if (a > b) { r = a; };
+            return r;
+        }
+    ";
+
+    #[test]
+    fn generic_macro_for_each_type() {
+        let code = format!("{} main {{ var n: number = larger(3, 7) + larger(9, 2); :out = larger(\"b\", \"a\") + n; }}", GENERIC);
+        assert_eq!(Value::from("b16"), run_program(&code).ok().unwrap());
+    }
+
+    #[test]
+    fn generic_arguments_must_match() {
+        let code = format!("{} main {{ :out = larger(1, \"a\"); }}", GENERIC);
+        match run_program(&code) {
+            Err(CompilerError::TypeCheckFailed(_, _)) => {},
+            _ => panic!("Expected type check failure")
+        }
+    }
+
+    #[test]
+    fn macro_in_expression() {
+        let code = "
+            def macro square(x: number) -> number { return x * x; }
+            main { :out = square(3) + square(square(2)); }
+        ";
+        assert_eq!(Value::from(25), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn calls_have_separate_locals() {
+        let code = "
+            def macro twice(x: number) -> number { var t: number = x * 2; return t; }
+            main { var a: number = twice(1); var b: number = twice(a); :out = a * 10 + b; }
+        ";
+        assert_eq!(Value::from(24), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn early_return() {
+        let code = "
+            def macro sign(x: number) -> number {
+                if (x < 0) { return -1; };
+                if (x == 0) { return 0; } else { :calls += 1; };
+                return 1;
+            }
+            main { :calls = 0; :out = sign(:in) * 10 + :calls; }
+        ";
+        let run = |input: i32| {
+            let state = vec![ (":in".to_string(), Value::from(input)) ].into_iter().collect();
+            return compile_and_run(y_parser::program(code).ok().unwrap(), state, 100).ok().unwrap().remove(":out").unwrap();
+        };
+
+        assert_eq!(Value::from(-10), run(-3));
+        assert_eq!(Value::from(0), run(0));
+        assert_eq!(Value::from(11), run(4));
+    }
+
+    #[test]
+    fn return_in_macro_without_return_type() {
+        let code = "
+            def macro f(x: number) { return x; }
+            main { f(1); }
+        ";
+        match run_program(code) {
+            Err(CompilerError::MisplacedReturn(name)) => assert_eq!("f", name),
+            _ => panic!("Expected misplaced return")
+        }
+    }
+
+    #[test]
+    fn recursive_proc_within_depth_limit() {
+        let code = |n: i32| format!("
+            [max_depth(3)] def proc count_down(n: number) {{ if (n > 0) {{ :out += n; count_down(n - 1); }}; }}
+            main {{ :out = 0; count_down({}); }}
+        ", n);
+
+        // Only three levels are inlined, so deeper calls are left out
+        assert_eq!(Value::from(6), run_program(&code(3)).ok().unwrap());
+        assert_eq!(Value::from(12), run_program(&code(5)).ok().unwrap());
+    }
+
+    #[test]
+    fn recursive_proc_in_expression_beyond_depth_limit() {
+        let code = "
+            [max_depth(2)] def proc sum(n: number) -> number { var r: number = n; if (n > 0) { r += sum(n - 1); }; return r; }
+            main { :out = sum(3); }
+        ";
+        match run_program(code) {
+            Err(CompilerError::RecursionTooDeep(name, 2)) => assert_eq!("sum", name),
+            _ => panic!("Expected recursion too deep")
+        }
+    }
+
+    #[test]
+    fn macro_writes_to_field_argument() {
+        let code = "
+            def macro double(x: number) { x *= 2; }
+            main { var a: number = 5; double(a); double(a); :out = a; }
+        ";
+        assert_eq!(Value::from(20), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn external_arguments() {
+        let code = "
+            def macro f(a: number) { :out = a; }
+            main { f(:in); }
+        ";
+        let state = vec![ (":in".to_string(), Value::from(3)) ].into_iter().collect();
+        assert_eq!(Value::from(3), compile_and_run(y_parser::program(code).ok().unwrap(), state, 100).ok().unwrap().remove(":out").unwrap());
+
+        let code = "
+            def macro f(copy a: number) { :in = 5; :out = a; }
+            main { f(:in); }
+        ";
+        let state = vec![ (":in".to_string(), Value::from(3)) ].into_iter().collect();
+        assert_eq!(Value::from(3), compile_and_run(y_parser::program(code).ok().unwrap(), state, 100).ok().unwrap().remove(":out").unwrap());
+    }
+
+    #[test]
+    fn match_in_macro() {
+        let code = "
+            def macro pick(copy v: number) { match v { 1 => { :out = 1; }, _ => { :out = 2; }, }; }
+            main { var q: number = 1; pick(q); }
+        ";
+        assert_eq!(Value::from(1), run_program(code).ok().unwrap());
+
+        let code = "
+            def macro pick(copy v: number) { match v { 1 => { :out = 1; }, _ => { :out = 2; }, }; }
+            main { var q: number = 1; pick(q); pick(q + 1); }
+        ";
+        assert_eq!(Value::from(2), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn overloads_picked_by_argument_types() {
+        let code = "
+            def macro describe(x: number) -> string { return \"n\"; }
+            def macro describe(x: string) -> string { return \"s\"; }
+            def macro describe(x: number, y: number) -> string { return \"nn\"; }
+            main { :out = describe(1) + describe(\"a\") + describe(1, 2); }
+        ";
+        assert_eq!(Value::from("nsnn"), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn method_binds_self_by_reference() {
+        let code = "
+            type struct counter { n: number }
+            impl counter { def macro add(self, x: number) { self.n = self.n + x; } }
+            main { var c: counter = { n: 0 }; c.add(2); }
+        ";
+        let config = BuildConfig::for_tests();
+        let blocks = y_parser::program(code).ok().unwrap()
+            .build_blocks(&config).ok().unwrap()
+            .inline_macros(&config).ok().unwrap();
+
+        match &blocks.blocks[0] {
+            Block::Statements(_, stmts) => match &stmts[1] {
+                OuterStatement::Inner(InnerStatement::Assign(path, _)) => assert_eq!(vec![ "c", "n" ], *path),
+                s => panic!("Unexpected statement {:?}", s)
+            },
+            b => panic!("Unexpected block {:?}", b)
+        }
+    }
+
+    #[test]
+    fn method_with_external_arguments() {
+        let code = "
+            impl number { def macro count_positive(self, x: number) { if (x > 0) { self = self + 1; }; } }
+            main { var c: number = 0; c.count_positive(:a); c.count_positive(:b); :out = c; }
+        ";
+        let run = |a: i32, b: i32| {
+            let state = vec![ (":a".to_string(), Value::from(a)), (":b".to_string(), Value::from(b)) ].into_iter().collect();
+            return compile_and_run(y_parser::program(code).ok().unwrap(), state, 100).ok().unwrap().remove(":out").unwrap();
+        };
+        assert_eq!(Value::from(2), run(1, 2));
+        assert_eq!(Value::from(1), run(0, 2));
+        assert_eq!(Value::from(0), run(0, -1));
+    }
+
+    #[test]
+    fn callables_are_left_out_without_their_config() {
+        let code = "
+            [cfg(\"test\")] def macro a() -> number { return 1; }
+            [cfg(\"other\")] def macro b() -> number { return 2; }
+        ";
+        assert_eq!(Value::from(1), run_program(&format!("{} main {{ :out = a(); }}", code)).ok().unwrap());
+
+        match run_program(&format!("{} main {{ :out = b(); }}", code)) {
+            Err(CompilerError::CallableNotFound(name)) => assert_eq!("b", name),
+            _ => panic!("Expected `b` to be left out")
+        }
+    }
 }
\ No newline at end of file
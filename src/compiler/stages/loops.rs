@@ -0,0 +1,709 @@
+use std::collections::{ HashMap, HashSet };
+
+use yolol_number::prelude::*;
+
+use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression, Constant, FieldDefinition, TypeName };
+use crate::grammar::visit::{ visit_expr_mut, visit_stmt_mut, visit_stmt_exprs_mut };
+use crate::error::{ CompilerError };
+use super::super::build_config::BuildConfig;
+use super::super::fields::{ canonicalise_field_path };
+use super::string_loops::{ hoist_string_loops, check_no_string_loops };
+
+// Where `continue` and `break` jump to inside the innermost loop
+struct LoopTargets {
+    next: String,
+    exit: String
+}
+
+struct Lowering {
+    counter: usize,
+    matches: usize,
+    string_loops: usize,
+    max_unroll: usize,
+    line_length: usize,
+    line_count: usize,
+    constants: HashMap<String, YololNumber>
+}
+
+// Lower all loops into labels and gotos. Loops with constant bounds may be unrolled instead.
+// `match` statements are lowered into `if` chains, or jump tables if the chain would be too long for one line. String helpers
+// which need a loop are lowered into `while` loops.
+pub fn lower_loops(stmts: Vec<OuterStatement>, constants: &Vec<Constant>, config: &BuildConfig) -> Result<Vec<OuterStatement>, CompilerError> {
+    return Lowering::new(constants, config).lower(stmts, None);
+}
+
+// Lower `match` statements in the body of a callable into `if` chains. Callables cannot contain labels, so jump tables are never used.
+pub fn lower_matches(stmts: Vec<InnerStatement>, constants: &Vec<Constant>, config: &BuildConfig) -> Result<Vec<InnerStatement>, CompilerError> {
+    return Lowering::new(constants, config).lower_inner(stmts, None);
+}
+
+// Get the value of an expression which is a compile time constant number
+pub fn constant_number(expr: &Expression, constants: &HashMap<String, YololNumber>) -> Option<YololNumber> {
+    match expr {
+        Expression::ConstNumber(n) => Some(*n),
+        Expression::Negate(x) => constant_number(x, constants).map(|n| -n),
+        Expression::Bracket(x) => constant_number(x, constants),
+        Expression::FieldAccess(path) => constants.get(&canonicalise_field_path(path)).cloned(),
+        _ => None
+    }
+}
+
+// Collect every inner statement (including those nested in `if` branches). Only descends into nested loops if `nested` is set.
+fn inner_stmts(stmts: &[OuterStatement], nested: bool) -> Vec<&InnerStatement> {
+    fn add<'a>(stmt: &'a InnerStatement, result: &mut Vec<&'a InnerStatement>) {
+        result.push(stmt);
+        match stmt {
+            InnerStatement::If(_, pass, fail) => pass.iter().chain(fail.iter()).for_each(|s| add(s, result)),
+            InnerStatement::Match(_, arms, default) => arms.iter().flat_map(|(_, a)| a.iter()).chain(default.iter()).for_each(|s| add(s, result)),
+            _ => {}
+        }
+    }
+
+    let mut result = Vec::new();
+    for stmt in stmts.iter() {
+        match stmt {
+            OuterStatement::Loop(body) |
+            OuterStatement::While(_, body) |
+            OuterStatement::For(_, _, _, body) => if nested { result.append(&mut inner_stmts(body, nested)) },
+            OuterStatement::Line(inner, _) => inner.iter().for_each(|s| add(s, &mut result)),
+            OuterStatement::Inner(inner) => add(inner, &mut result),
+            OuterStatement::Label(_) => {}
+        }
+    }
+    return result;
+}
+
+// Check if a statement list contains labels, these cannot be duplicated
+fn contains_labels(stmts: &[OuterStatement]) -> bool {
+    stmts.iter().any(|s| match s {
+        OuterStatement::Loop(body) |
+        OuterStatement::While(_, body) |
+        OuterStatement::For(_, _, _, body) => contains_labels(body),
+        OuterStatement::Line(_, label) => label.is_some(),
+        OuterStatement::Inner(_) => false,
+        OuterStatement::Label(_) => true,
+    })
+}
+
+fn is_field(path: &Vec<String>, name: &str) -> bool {
+    path.len() == 1 && path[0] == name
+}
+
+// Check if any statement modifies the given field
+fn writes_field(stmts: &[OuterStatement], name: &str) -> bool {
+    inner_stmts(stmts, true).into_iter().any(|s| {
+        let mut writes = match s {
+            InnerStatement::Assign(path, _) => is_field(path, name),
+            _ => false
+        };
+        visit_stmt_exprs_mut(&mut s.clone(), &mut |e| match e {
+            Expression::PostIncrement(p) |
+            Expression::PostDecrement(p) |
+            Expression::PreIncrement(p) |
+            Expression::PreDecrement(p) => writes |= is_field(p, name),
+            _ => {}
+        });
+        writes
+    })
+}
+
+// Replace every use of the field `name` with the given expression, if the expression is another field then writes are renamed too
+fn replace_field(stmts: &mut Vec<OuterStatement>, name: &str, with: &Expression) {
+    for stmt in stmts.iter_mut() {
+        match stmt {
+            OuterStatement::Loop(body) => replace_field(body, name, with),
+            OuterStatement::While(condition, body) => {
+                visit_expr_mut(condition, &mut |e| replace_field_expr(e, name, with));
+                replace_field(body, name, with);
+            },
+            OuterStatement::For(inner_name, from, to, body) => {
+                visit_expr_mut(from, &mut |e| replace_field_expr(e, name, with));
+                visit_expr_mut(to, &mut |e| replace_field_expr(e, name, with));
+
+                // A nested loop with the same variable name shadows this one
+                if inner_name != name {
+                    replace_field(body, name, with);
+                }
+            },
+            OuterStatement::Line(inner, _) => inner.iter_mut().for_each(|s| replace_field_stmt(s, name, with)),
+            OuterStatement::Inner(inner) => replace_field_stmt(inner, name, with),
+            OuterStatement::Label(_) => {}
+        }
+    }
+}
+
+fn replace_field_stmt(stmt: &mut InnerStatement, name: &str, with: &Expression) {
+    visit_stmt_mut(stmt, &mut |s| if let InnerStatement::Assign(path, _) = s {
+        if let (true, Expression::FieldAccess(renamed)) = (is_field(path, name), with) {
+            *path = renamed.clone();
+        }
+    });
+    visit_stmt_exprs_mut(stmt, &mut |e| replace_field_expr(e, name, with));
+}
+
+fn replace_field_expr(expr: &mut Expression, name: &str, with: &Expression) {
+    match expr {
+        Expression::FieldAccess(path) if is_field(path, name) => *expr = with.clone(),
+        Expression::PostIncrement(path) |
+        Expression::PostDecrement(path) |
+        Expression::PreIncrement(path) |
+        Expression::PreDecrement(path) => if let (true, Expression::FieldAccess(renamed)) = (is_field(path, name), with) {
+            *path = renamed.clone();
+        },
+        _ => {}
+    }
+}
+
+// Build the `if/else` chain comparing a value against each arm of a `match` in turn
+fn if_chain(value: &Expression, arms: Vec<(Expression, Vec<InnerStatement>)>, default: Vec<InnerStatement>) -> Vec<InnerStatement> {
+    let mut chain = default;
+    for (pattern, body) in arms.into_iter().rev() {
+        let condition = Expression::Equals(Box::new(value.clone()), Box::new(pattern));
+        chain = vec![ InnerStatement::If(condition, body, chain) ];
+    }
+    return chain;
+}
+
+// Roughly estimate how many characters statements will take up once written as Yolol. Fields are minified to one or two
+// characters, so every part of an expression is counted as two characters.
+fn estimate_length(stmts: &[InnerStatement]) -> usize {
+    let mut length = 0;
+    for stmt in stmts.iter() {
+        let mut stmt = stmt.clone();
+        visit_stmt_mut(&mut stmt, &mut |s| length += match s {
+            InnerStatement::If(_, _, fail) if fail.len() > 0 => "if  then  else  end ".len(),
+            InnerStatement::If(_, _, _) => "if  then  end ".len(),
+            InnerStatement::Match(_, arms, _) => arms.len() * "if  then  else  end ".len(),
+            _ => 1
+        });
+        visit_stmt_exprs_mut(&mut stmt, &mut |_| length += 2);
+    }
+    return length;
+}
+
+impl Lowering {
+
+    fn new(constants: &Vec<Constant>, config: &BuildConfig) -> Lowering {
+        return Lowering {
+            counter: 0,
+            matches: 0,
+            string_loops: 0,
+            max_unroll: config.max_unroll as usize,
+            line_length: config.line_length as usize,
+            line_count: config.line_count as usize,
+            constants: constants
+                .iter()
+                .filter_map(|c| constant_number(&c.value, &HashMap::new()).map(|n| (c.field.name.clone(), n)))
+                .collect()
+        };
+    }
+
+    fn lower(&mut self, stmts: Vec<OuterStatement>, current: Option<&LoopTargets>) -> Result<Vec<OuterStatement>, CompilerError> {
+        let mut result = Vec::new();
+
+        for stmt in stmts.into_iter() {
+            match stmt {
+                OuterStatement::Loop(body) => {
+                    let prefix = self.prefix();
+                    let targets = LoopTargets { next: format!("{}_start", prefix), exit: format!("{}_end", prefix) };
+
+                    result.push(OuterStatement::Label(targets.next.clone()));
+                    result.append(&mut self.lower(body, Some(&targets))?);
+                    result.push(OuterStatement::Inner(InnerStatement::Goto(targets.next.clone())));
+                    result.push(OuterStatement::Label(targets.exit.clone()));
+                },
+
+                OuterStatement::While(mut condition, body) => {
+                    let prefix = self.prefix();
+                    let targets = LoopTargets { next: format!("{}_start", prefix), exit: format!("{}_end", prefix) };
+
+                    // The condition is evaluated every iteration, so anything it needs is calculated after the start label
+                    let hoisted = hoist_string_loops(&mut condition, &mut self.string_loops);
+                    result.push(OuterStatement::Label(targets.next.clone()));
+                    result.append(&mut self.lower(hoisted, current)?);
+                    result.push(exit_unless(condition, &targets.exit));
+                    result.append(&mut self.lower(body, Some(&targets))?);
+                    result.push(OuterStatement::Inner(InnerStatement::Goto(targets.next.clone())));
+                    result.push(OuterStatement::Label(targets.exit.clone()));
+                },
+
+                OuterStatement::For(name, mut from, mut to, mut body) => {
+
+                    // The range is calculated once, before the loop starts
+                    let mut hoisted = hoist_string_loops(&mut from, &mut self.string_loops);
+                    hoisted.append(&mut hoist_string_loops(&mut to, &mut self.string_loops));
+                    result.append(&mut self.lower(hoisted, current)?);
+
+                    // Unroll the loop if the number of iterations is known and every iteration can be a copy of the body
+                    let loop_control = inner_stmts(&body, false).into_iter().any(|s| match s { InnerStatement::Break | InnerStatement::Continue => true, _ => false });
+                    let declares = inner_stmts(&body, true).into_iter().any(|s| match s { InnerStatement::DeclareAssign(_, _) | InnerStatement::DeclareConst(_, _) => true, _ => false });
+                    let iterations = if loop_control || declares || contains_labels(&body) || writes_field(&body, &name) {
+                        None
+                    } else {
+                        self.unroll_iterations(&from, &to)
+                    };
+
+                    if let Some(iterations) = iterations {
+                        for i in iterations.into_iter() {
+                            let mut copy = body.clone();
+                            replace_field(&mut copy, &name, &Expression::ConstNumber(i));
+                            result.append(&mut self.lower(copy, current)?);
+                        }
+                        continue;
+                    }
+
+                    // Otherwise count through the range in a field
+                    let prefix = self.prefix();
+                    let counter = format!("{}_{}", prefix, name);
+                    let start = format!("{}_start", prefix);
+                    let targets = LoopTargets { next: format!("{}_step", prefix), exit: format!("{}_end", prefix) };
+
+                    // Only split off the step into a separate block if something needs to jump to it
+                    let step_label = inner_stmts(&body, false).into_iter().any(|s| match s { InnerStatement::Continue => true, _ => false });
+
+                    replace_field(&mut body, &name, &Expression::FieldAccess(vec![ counter.clone() ]));
+
+                    let number = TypeName { typename: "number".to_string() };
+                    result.push(OuterStatement::Inner(InnerStatement::DeclareAssign(FieldDefinition { name: counter.clone(), typename: number }, from)));
+                    result.push(OuterStatement::Label(start.clone()));
+                    result.push(exit_unless(Expression::LessThan(Box::new(Expression::FieldAccess(vec![ counter.clone() ])), Box::new(to)), &targets.exit));
+                    result.append(&mut self.lower(body, Some(&targets))?);
+                    if step_label {
+                        result.push(OuterStatement::Label(targets.next.clone()));
+                    }
+                    result.push(OuterStatement::Inner(InnerStatement::Assign(
+                        vec![ counter.clone() ],
+                        Expression::Add(Box::new(Expression::FieldAccess(vec![ counter ])), Box::new(Expression::ConstNumber(YololNumber::one())))
+                    )));
+                    result.push(OuterStatement::Inner(InnerStatement::Goto(start)));
+                    result.push(OuterStatement::Label(targets.exit.clone()));
+                },
+
+                OuterStatement::Line(inner, label) => {
+                    check_no_string_loops(&inner)?;
+                    result.push(OuterStatement::Line(self.lower_inner(inner, current)?, label));
+                },
+                OuterStatement::Inner(mut inner) => {
+                    let hoisted = self.hoist_inner_string_loops(&mut inner)?;
+                    result.append(&mut self.lower(hoisted, current)?);
+
+                    let table = match &inner {
+                        InnerStatement::Match(value, arms, default) => self.jump_table_range(value, arms, default),
+                        _ => None
+                    };
+                    match (inner, table) {
+                        (InnerStatement::Match(value, arms, default), Some((min, span))) => result.append(&mut self.lower_jump_table(value, arms, default, min, span, current)?),
+                        (inner, _) => result.extend(self.lower_inner(vec![ inner ], current)?.into_iter().map(OuterStatement::Inner)),
+                    }
+                },
+                OuterStatement::Label(label) => result.push(OuterStatement::Label(label)),
+            }
+        }
+
+        return Ok(result);
+
+        // Leave the loop if the condition is false
+        fn exit_unless(condition: Expression, exit: &str) -> OuterStatement {
+            OuterStatement::Inner(InnerStatement::If(
+                Expression::Not(Box::new(Expression::Bracket(Box::new(condition)))),
+                vec![ InnerStatement::Goto(exit.to_string()) ],
+                Vec::new()
+            ))
+        }
+    }
+
+    // Replace `break`, `continue` and `match` in inner statements
+    fn lower_inner(&mut self, stmts: Vec<InnerStatement>, current: Option<&LoopTargets>) -> Result<Vec<InnerStatement>, CompilerError> {
+        let mut result = Vec::new();
+
+        for stmt in stmts.into_iter() {
+            match stmt {
+                InnerStatement::Break => result.push(InnerStatement::Goto(current.ok_or(CompilerError::LoopControlOutsideLoop("break".to_string()))?.exit.clone())),
+                InnerStatement::Continue => result.push(InnerStatement::Goto(current.ok_or(CompilerError::LoopControlOutsideLoop("continue".to_string()))?.next.clone())),
+                InnerStatement::If(condition, pass, fail) => {
+                    let pass = self.lower_inner(pass, current)?;
+                    let fail = self.lower_inner(fail, current)?;
+                    result.push(InnerStatement::If(condition, pass, fail));
+                },
+
+                // Match becomes a chain of `if/else` comparing against each arm in turn
+                InnerStatement::Match(value, arms, default) => {
+                    let value = self.match_value(value, &mut result);
+                    let arms = arms
+                        .into_iter()
+                        .map(|(pattern, body)| Ok((pattern, self.lower_inner(body, current)?)))
+                        .collect::<Result<Vec<_>, CompilerError>>()?;
+                    let default = self.lower_inner(default, current)?;
+                    result.append(&mut if_chain(&value, arms, default));
+                },
+
+                other => result.push(other)
+            }
+        }
+
+        return Ok(result);
+    }
+
+    // Get an expression which reads the value being matched on, storing it in a field first if it is not simple
+    fn match_value(&mut self, value: Expression, result: &mut Vec<InnerStatement>) -> Expression {
+        return match value {
+            Expression::FieldAccess(_) | Expression::ExternalFieldAccess(_) | Expression::ConstNumber(_) | Expression::ConstString(_) => value,
+            other => {
+                // Store the value in a field so it is only evaluated once
+                let name = format!("_match_{}", self.matches);
+                self.matches += 1;
+
+                let any = TypeName { typename: "any".to_string() };
+                result.push(InnerStatement::DeclareAssign(FieldDefinition { name: name.clone(), typename: any }, other));
+                Expression::FieldAccess(vec![ name ])
+            }
+        };
+    }
+
+    // Get the smallest pattern and the number of lines for a jump table. A jump table is only used if every pattern is a distinct
+    // whole number, the `if` chain would not fit onto a line, every arm fits onto a line of its own and the table takes up at
+    // most half of the lines.
+    fn jump_table_range(&self, value: &Expression, arms: &[(Expression, Vec<InnerStatement>)], default: &[InnerStatement]) -> Option<(i64, usize)> {
+        let mut values = HashSet::new();
+        for (pattern, _) in arms.iter() {
+            let n = constant_number(pattern, &self.constants)?;
+            if n.floor() != n || !values.insert(n.float_value() as i64) {
+                return None;
+            }
+        }
+
+        let min = *values.iter().min()?;
+        let span = (*values.iter().max()? - min + 1) as usize;
+        if span > self.line_count / 2 {
+            return None;
+        }
+
+        if estimate_length(&if_chain(value, arms.to_vec(), default.to_vec())) <= self.line_length {
+            return None;
+        }
+
+        if arms.iter().any(|(_, body)| estimate_length(body) + "goto 20".len() > self.line_length) {
+            return None;
+        }
+
+        return Some((min, span));
+    }
+
+    // Lower a `match` into a computed `goto` to one line per value from `min` to `min + span - 1`. Values without an arm, and
+    // values which are out of range, run the default statements instead. If checking the value fails at runtime (e.g. it is a
+    // string) the rest of that line is skipped, so the default statements come straight after it.
+    fn lower_jump_table(&mut self, value: Expression, arms: Vec<(Expression, Vec<InnerStatement>)>, default: Vec<InnerStatement>, min: i64, span: usize, current: Option<&LoopTargets>) -> Result<Vec<OuterStatement>, CompilerError> {
+        let mut declare = Vec::new();
+        let value = self.match_value(value, &mut declare);
+        let mut result: Vec<OuterStatement> = declare.into_iter().map(OuterStatement::Inner).collect();
+
+        let prefix = format!("_table_{}", self.matches);
+        self.matches += 1;
+        let arm_label = |i: usize| format!("{}_arm_{}", prefix, i);
+        let default_label = format!("{}_default", prefix);
+        let end_label = format!("{}_end", prefix);
+
+        let num = |n: i64| Box::new(Expression::ConstNumber(YololNumber::from_value(n as i32)));
+        let bracket = |e: Expression| Expression::Bracket(Box::new(e));
+        let in_range = Expression::And(
+            Box::new(Expression::And(
+                Box::new(bracket(Expression::GreaterThanOrEq(Box::new(value.clone()), num(min)))),
+                Box::new(bracket(Expression::LessThanOrEq(Box::new(value.clone()), num(min + span as i64 - 1))))
+            )),
+            Box::new(bracket(Expression::Equals(Box::new(bracket(Expression::Modulus(Box::new(value.clone()), num(1)))), num(0))))
+        );
+        let offset = match min {
+            0 => value.clone(),
+            m if m > 0 => Expression::Subtract(Box::new(value.clone()), num(m)),
+            m => Expression::Add(Box::new(value.clone()), num(-m)),
+        };
+        result.push(OuterStatement::Inner(InnerStatement::If(in_range, vec![ InnerStatement::GotoOffset(arm_label(0), offset) ], vec![])));
+
+        result.push(OuterStatement::Label(default_label.clone()));
+        result.extend(self.lower_inner(default, current)?.into_iter().map(OuterStatement::Inner));
+        result.push(OuterStatement::Inner(InnerStatement::Goto(end_label.clone())));
+
+        let mut bodies: HashMap<i64, Vec<InnerStatement>> = HashMap::new();
+        for (pattern, body) in arms.into_iter() {
+            let n = constant_number(&pattern, &self.constants).unwrap().float_value() as i64;
+            bodies.insert(n, self.lower_inner(body, current)?);
+        }
+
+        for i in 0..span {
+            let line = match bodies.remove(&(min + i as i64)) {
+                Some(mut body) => {
+                    // The last arm falls through to the end
+                    if i + 1 < span {
+                        body.push(InnerStatement::Goto(end_label.clone()));
+                    }
+                    body
+                },
+                None => vec![ InnerStatement::Goto(default_label.clone()) ]
+            };
+            result.push(OuterStatement::Line(line, Some(arm_label(i))));
+        }
+        result.push(OuterStatement::Label(end_label));
+
+        return Ok(result);
+    }
+
+    // Move string helpers which need a loop out of a statement, returning the loops which must run before it. Branches of an `if`
+    // or `match` cannot contain loops, and are only evaluated sometimes, so these helpers cannot be used there.
+    fn hoist_inner_string_loops(&mut self, stmt: &mut InnerStatement) -> Result<Vec<OuterStatement>, CompilerError> {
+        let counter = &mut self.string_loops;
+        return Ok(match stmt {
+            InnerStatement::If(condition, pass, fail) => {
+                check_no_string_loops(pass)?;
+                check_no_string_loops(fail)?;
+                hoist_string_loops(condition, counter)
+            },
+            InnerStatement::Match(value, arms, default) => {
+                for (pattern, body) in arms.iter() {
+                    check_no_string_loops(&[ InnerStatement::ExpressionWrapper(pattern.clone()) ])?;
+                    check_no_string_loops(body)?;
+                }
+                check_no_string_loops(default)?;
+                hoist_string_loops(value, counter)
+            },
+            other => {
+                let mut hoisted = Vec::new();
+                visit_stmt_exprs_mut(other, &mut |e| hoisted.append(&mut hoist_string_loops(e, counter)));
+                hoisted
+            }
+        });
+    }
+
+    // Get a unique prefix for the labels and fields of a loop
+    fn prefix(&mut self) -> String {
+        self.counter += 1;
+        return format!("_loop_{}", self.counter - 1);
+    }
+
+    // Get the value of the loop variable for each iteration, if the range is constant and small enough to unroll
+    fn unroll_iterations(&self, from: &Expression, to: &Expression) -> Option<Vec<YololNumber>> {
+        let mut i = constant_number(from, &self.constants)?;
+        let end = constant_number(to, &self.constants)?;
+
+        let mut result = Vec::new();
+        while i < end {
+            if result.len() >= self.max_unroll {
+                return None;
+            }
+            result.push(i);
+            i = i + YololNumber::one();
+        }
+
+        return Some(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::compiler::{ BuildConfig };
+    use crate::error::{ CompilerError };
+    use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression };
+    use crate::grammar::parser::y_parser;
+    use crate::yolol::interpret::{ compile_and_run, Value };
+    use super::*;
+
+    fn config(max_unroll: u16) -> BuildConfig {
+        BuildConfig { max_unroll: max_unroll, ..BuildConfig::for_tests() }
+    }
+
+    fn for_loop() -> Vec<OuterStatement> {
+        vec![ OuterStatement::For(
+            "i".to_string(),
+            Expression::ConstNumber(YololNumber::zero()),
+            Expression::ConstNumber(YololNumber::from_value(3)),
+            vec![ OuterStatement::Inner(InnerStatement::ExternalAssign("out".to_string(), Expression::FieldAccess(vec![ "i".to_string() ]))) ]
+        ) ]
+    }
+
+    #[test]
+    fn unroll_constant_range() {
+        let stmts = lower_loops(for_loop(), &vec![], &config(20)).ok().unwrap();
+
+        assert_eq!(3, stmts.len());
+        for (i, stmt) in stmts.iter().enumerate() {
+            match stmt {
+                OuterStatement::Inner(InnerStatement::ExternalAssign(_, Expression::ConstNumber(n))) => assert_eq!(YololNumber::from_value(i as i32), *n),
+                _ => panic!("Expected `:out = {}`", i)
+            }
+        }
+    }
+
+    #[test]
+    fn count_when_too_large_to_unroll() {
+        let stmts = lower_loops(for_loop(), &vec![], &config(2)).ok().unwrap();
+
+        // Declare counter, start label, exit check, body, increment, goto start, end label
+        assert_eq!(7, stmts.len());
+        match &stmts[3] {
+            OuterStatement::Inner(InnerStatement::ExternalAssign(_, Expression::FieldAccess(path))) => assert_eq!(vec![ "_loop_0_i".to_string() ], *path),
+            _ => panic!("Expected loop body to read the counter")
+        }
+    }
+
+    fn run_program(code: &str, input: i32) -> Result<Value, CompilerError> {
+        let mut state = HashMap::new();
+        state.insert(":in".to_string(), Value::from(input));
+        return Ok(compile_and_run(y_parser::program(code).ok().unwrap(), state, 1000)?.remove(":out").unwrap());
+    }
+
+    #[test]
+    fn run_while_loop() {
+        let code = "main {
+            :out = 0;
+            var i: number = 0;
+            while (i < :in) { :out += 2; i += 1; };
+        }";
+
+        assert_eq!(Value::from(6), run_program(code, 3).ok().unwrap());
+        assert_eq!(Value::from(0), run_program(code, 0).ok().unwrap());
+    }
+
+    #[test]
+    fn run_loop_until_break() {
+        let code = "main {
+            var i: number = 0;
+            loop { i += 1; if (i >= :in) { break; }; };
+            :out = i;
+        }";
+
+        assert_eq!(Value::from(4), run_program(code, 4).ok().unwrap());
+        assert_eq!(Value::from(1), run_program(code, 0).ok().unwrap());
+    }
+
+    #[test]
+    fn run_nested_break_and_continue() {
+        // `break` and `continue` in the inner loop only affect the inner loop
+        let code = "main {
+            :out = 0;
+            for i in 0..:in {
+                for j in 0..3 {
+                    if (j == 1) { continue; };
+                    if (j == 2) { break; };
+                    :out += 10;
+                };
+                if (i == 1) { continue; };
+                :out += 1;
+            };
+        }";
+
+        assert_eq!(Value::from(32), run_program(code, 3).ok().unwrap());
+    }
+
+    #[test]
+    fn loop_control_outside_loop() {
+        match run_program("main { :out = 1; break; }", 0) {
+            Err(CompilerError::LoopControlOutsideLoop(kw)) => assert_eq!("break", kw),
+            _ => panic!("Expected loop control outside loop error")
+        }
+        match run_program("main { if (:in) { continue; }; }", 0) {
+            Err(CompilerError::LoopControlOutsideLoop(kw)) => assert_eq!("continue", kw),
+            _ => panic!("Expected loop control outside loop error")
+        }
+    }
+
+    #[test]
+    fn match_to_if_chain() {
+        let assign = |v: i32| vec![ InnerStatement::ExternalAssign("out".to_string(), Expression::ConstNumber(YololNumber::from_value(v))) ];
+        let stmts = vec![ OuterStatement::Inner(InnerStatement::Match(
+            Expression::ExternalFieldAccess("in".to_string()),
+            vec![
+                (Expression::ConstNumber(YololNumber::one()), assign(1)),
+                (Expression::ConstNumber(YololNumber::from_value(2)), assign(2)),
+            ],
+            assign(3)
+        )) ];
+
+        let stmts = lower_loops(stmts, &vec![], &config(20)).ok().unwrap();
+
+        // `if :in == 1 { } else { if :in == 2 { } else { } }`
+        assert_eq!(1, stmts.len());
+        match &stmts[0] {
+            OuterStatement::Inner(InnerStatement::If(Expression::Equals(_, _), pass, fail)) => {
+                assert_eq!(1, pass.len());
+                match &fail[0] {
+                    InnerStatement::If(_, _, fail) => assert_eq!(1, fail.len()),
+                    _ => panic!("Expected second arm to be nested in else")
+                }
+            },
+            _ => panic!("Expected match to become an if statement")
+        }
+    }
+
+    #[test]
+    fn long_match_to_jump_table() {
+        let code = "main { match :in {
+            1 => { :out = \"one\"; },
+            2 => { :out = \"two\"; },
+            3 => { :out = \"three\"; },
+            5 => { :out = \"five\"; },
+            6 => { :out = \"six\"; },
+            _ => { :out = \"other\"; },
+        }; }";
+        let program = y_parser::program(code).ok().unwrap();
+
+        // Guard and computed goto, default label and statements, goto end, one line per value from 1 to 6, end label
+        let stmts = lower_loops(program.main.clone().unwrap().statements, &vec![], &config(20)).ok().unwrap();
+        assert_eq!(11, stmts.len());
+        match &stmts[0] {
+            OuterStatement::Inner(InnerStatement::If(_, pass, _)) => match &pass[0] {
+                InnerStatement::GotoOffset(label, _) => assert_eq!("_table_0_arm_0", label),
+                _ => panic!("Expected computed goto")
+            },
+            _ => panic!("Expected range check")
+        }
+
+        let run = |input: Value| compile_and_run(program.clone(), vec![ (":in".to_string(), input) ].into_iter().collect(), 100).ok().unwrap().remove(":out").unwrap();
+        for (input, expected) in vec![ (1, "one"), (2, "two"), (3, "three"), (4, "other"), (5, "five"), (6, "six"), (0, "other"), (7, "other") ] {
+            assert_eq!(Value::from(expected), run(Value::from(input)));
+        }
+        assert_eq!(Value::from("other"), run(Value::from("two")));
+        assert_eq!(Value::from("other"), run(Value::Num(YololNumber::from_value(3) / YololNumber::from_value(2))));
+    }
+
+    #[test]
+    fn else_if_chain_runs_first_match() {
+        let code = "main {
+            :out = 0;
+            if (:in > 2) { :out += 1; } else if (:in > 1) { :out += 10; } else if (:in > 0) { :out += 100; } else { :out += 1000; };
+        }";
+
+        assert_eq!(Value::from(1), run_program(code, 3).ok().unwrap());
+        assert_eq!(Value::from(10), run_program(code, 2).ok().unwrap());
+        assert_eq!(Value::from(100), run_program(code, 1).ok().unwrap());
+        assert_eq!(Value::from(1000), run_program(code, 0).ok().unwrap());
+    }
+
+    #[test]
+    fn jump_table_lands_on_every_arm() {
+        // Each arm adds a different digit, so the total shows how many times each arm ran
+        let code = "main {
+            :out = 0;
+            for i in 0..:in {
+                match i {
+                    0 => { :out += 1; },
+                    1 => { :out += 10; },
+                    2 => { :out += 100; },
+                    3 => { :out += 1000; },
+                    5 => { :out += 10000; },
+                    _ => { :out += 100000; },
+                };
+            };
+        }";
+
+        let program = y_parser::program(code).ok().unwrap();
+        let stmts = lower_loops(program.main.unwrap().statements, &vec![], &config(20)).ok().unwrap();
+        assert!(format!("{:?}", stmts).contains("GotoOffset"));
+
+        assert_eq!(Value::from(211111), run_program(code, 7).ok().unwrap());
+        assert_eq!(Value::from(111), run_program(code, 3).ok().unwrap());
+    }
+}
\ No newline at end of file
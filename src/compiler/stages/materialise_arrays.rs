@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use yolol_number::prelude::*;
+
+use crate::error::{ CompilerError };
+use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression, FieldDefinition, TypeName };
+use crate::grammar::visit::{ visit_expr_mut, visit_stmt_exprs_mut };
+use super::initial_blocks::{ InitialStatementBlocks, Block };
+use super::loops::{ constant_number };
+use super::super::fields::{ canonicalise_field_path };
+
+// Everything known about the fields seen so far
+struct Fields {
+    // Element type and length of every array field
+    arrays: HashMap<String, (TypeName, usize)>,
+
+    // Constants which can be used as an index
+    constants: HashMap<String, YololNumber>,
+
+    // Counter for fields holding an index which is used more than once
+    indices: usize,
+}
+
+impl InitialStatementBlocks {
+
+    // Replace every fixed size array with one field per element. Constant indices access the element field directly, other indices
+    // are lowered into a comparison against every possible index.
+    pub fn materialise_arrays(self) -> Result<InitialStatementBlocks, CompilerError> {
+
+        let mut fields = Fields {
+            arrays: HashMap::new(),
+            constants: HashMap::new(),
+            indices: 0,
+        };
+
+        let result: Result<_, _> = self.blocks
+            .into_iter()
+            .map(|x| handle_block(x, &mut fields))
+            .collect();
+
+        return Ok(InitialStatementBlocks {
+            blocks: result?,
+            callables: self.callables,
+            structs: self.structs,
+        });
+
+        fn handle_block(b: Block, fields: &mut Fields) -> Result<Block, CompilerError> {
+            match b {
+                Block::Statements(label, stmts) => Ok(Block::Statements(label, stmts
+                    .into_iter()
+                    .map(|x| match x {
+                        OuterStatement::Inner(inner) => handle_inner_stmt(inner, fields),
+
+                        // There should only be inner statements here, everything else has been removed by the initial_blocks pass
+                        other => panic!("Encountered outer statement `{:?}` in materialise_arrays pass (1e6d4b8a-2f7c-4c3e-a9d0-5b8e7f6a3c21)", other),
+                    })
+                    .collect::<Result<Vec<_>, CompilerError>>()?
+                    .into_iter()
+                    .flatten()
+                    .map(OuterStatement::Inner)
+                    .collect()
+                )),
+                Block::Line(label, stmts) => Ok(Block::Line(label, handle_inner_stmts(stmts, fields)?)),
+            }
+        }
+
+        fn handle_inner_stmts(stmts: Vec<InnerStatement>, fields: &mut Fields) -> Result<Vec<InnerStatement>, CompilerError> {
+            Ok(stmts
+                .into_iter()
+                .map(|x| handle_inner_stmt(x, fields))
+                .collect::<Result<Vec<_>, CompilerError>>()?
+                .into_iter()
+                .flatten()
+                .collect()
+            )
+        }
+
+        fn handle_inner_stmt(inner: InnerStatement, fields: &mut Fields) -> Result<Vec<InnerStatement>, CompilerError> {
+
+            // Index expressions which are used more than once are calculated into a field before this statement
+            let mut result = Vec::new();
+
+            match inner {
+                InnerStatement::DeclareConst(field, value) => {
+                    if let Some(n) = constant_number(&value, &fields.constants) {
+                        fields.constants.insert(field.name.clone(), n);
+                    }
+                    let value = handle_expr(value, fields, &mut result)?;
+                    result.push(InnerStatement::DeclareConst(field, value));
+                },
+
+                InnerStatement::DeclareAssign(field, value) => match field.typename.array() {
+                    None => {
+                        let value = handle_expr(value, fields, &mut result)?;
+                        result.push(InnerStatement::DeclareAssign(field, value));
+                    },
+                    Some((element, length)) => {
+                        if fields.arrays.contains_key(&field.name) {
+                            return Err(CompilerError::DuplicateFieldDeclaration(field.name.clone()));
+                        }
+                        fields.arrays.insert(field.name.clone(), (element.clone(), length));
+
+                        let path = vec![ field.name.clone() ];
+                        for (i, value) in array_values(&path, value, fields, &mut result)?.into_iter().enumerate() {
+                            let name = canonicalise_field_path(&element_path(&path, i));
+                            result.push(InnerStatement::DeclareAssign(FieldDefinition { name: name, typename: element.clone() }, value));
+                        }
+                    }
+                },
+
+                InnerStatement::Assign(path, value) => match array_length(&path, fields) {
+                    None => {
+                        let value = handle_expr(value, fields, &mut result)?;
+                        result.push(InnerStatement::Assign(path, value));
+                    },
+                    Some(_) => {
+                        for (i, value) in array_values(&path, value, fields, &mut result)?.into_iter().enumerate() {
+                            result.push(InnerStatement::Assign(element_path(&path, i), value));
+                        }
+                    }
+                },
+
+                InnerStatement::AssignIndex(path, index, value) => {
+                    let length = array_length(&path, fields).ok_or(CompilerError::NotAnArray(path.clone()))?;
+                    let value = handle_expr(value, fields, &mut result)?;
+                    let index = handle_expr(index, fields, &mut result)?;
+
+                    match constant_index(&path, &index, length, fields)? {
+                        Some(i) => result.push(InnerStatement::Assign(element_path(&path, i), value)),
+
+                        // Compare the index against every element in turn: `if i == 0 { a_0 = x } else if i == 1 { a_1 = x } ...`
+                        None => {
+                            let index = single_use(index, fields, &mut result);
+                            let mut chain = Vec::new();
+                            for i in (0..length).rev() {
+                                chain = vec![ InnerStatement::If(index_equals(&index, i), vec![ InnerStatement::Assign(element_path(&path, i), value.clone()) ], chain) ];
+                            }
+                            result.append(&mut chain);
+                        }
+                    }
+                },
+
+                // Only the condition is always evaluated, anything the branches need is calculated inside the branches
+                InnerStatement::If(condition, pass, fail) => {
+                    let condition = handle_expr(condition, fields, &mut result)?;
+                    result.push(InnerStatement::If(condition, handle_inner_stmts(pass, fields)?, handle_inner_stmts(fail, fields)?));
+                },
+
+                mut other => {
+                    let mut error = None;
+                    visit_stmt_exprs_mut(&mut other, &mut |e| if error.is_none() {
+                        error = lower_index(e, fields, &mut result).err();
+                    });
+                    if let Some(err) = error {
+                        return Err(err);
+                    }
+                    result.push(other);
+                }
+            }
+
+            return Ok(result);
+        }
+
+        // Get the value for every element when assigning to a whole array. Another array of the same type is copied element by
+        // element, any other value is assigned to the first element and then copied from there into the rest.
+        fn array_values(path: &Vec<String>, value: Expression, fields: &mut Fields, hoisted: &mut Vec<InnerStatement>) -> Result<Vec<Expression>, CompilerError> {
+            let (element, length) = fields.arrays[&canonicalise_field_path(path)].clone();
+
+            if let Expression::FieldAccess(from) = &value {
+                if let Some((from_element, from_length)) = fields.arrays.get(&canonicalise_field_path(from)).cloned() {
+                    if from_element != element || from_length != length {
+                        return Err(CompilerError::TypeCheckFailed(array_type(&element, length), array_type(&from_element, from_length)));
+                    }
+                    return Ok((0..length).map(|i| Expression::FieldAccess(element_path(from, i))).collect());
+                }
+            }
+
+            let first = handle_expr(value, fields, hoisted)?;
+            return Ok(std::iter::once(first)
+                .chain((1..length).map(|_| Expression::FieldAccess(element_path(path, 0))))
+                .collect());
+        }
+
+        fn handle_expr(mut expr: Expression, fields: &mut Fields, hoisted: &mut Vec<InnerStatement>) -> Result<Expression, CompilerError> {
+            let mut error = None;
+            visit_expr_mut(&mut expr, &mut |e| if error.is_none() {
+                error = lower_index(e, fields, hoisted).err();
+            });
+
+            return match error {
+                Some(err) => Err(err),
+                None => Ok(expr)
+            };
+        }
+
+        // Replace an `Index` expression with the element field, or a selection between all of the element fields
+        fn lower_index(expr: &mut Expression, fields: &mut Fields, hoisted: &mut Vec<InnerStatement>) -> Result<(), CompilerError> {
+            let (path, index) = match expr {
+                Expression::Index(path, index) => (path.clone(), (**index).clone()),
+                _ => return Ok(())
+            };
+
+            let length = array_length(&path, fields).ok_or(CompilerError::NotAnArray(path.clone()))?;
+            let index = handle_expr(index, fields, hoisted)?;
+
+            *expr = match constant_index(&path, &index, length, fields)? {
+                Some(i) => Expression::FieldAccess(element_path(&path, i)),
+                None => {
+                    let index = single_use(index, fields, hoisted);
+                    index_chain(&path, &index, length, fields)?
+                }
+            };
+
+            return Ok(());
+        }
+
+        // Read an element with an index which is not known at compile time. Numbers are selected with arithmetic: `(i==0)*a_0+(i==1)*a_1+...`,
+        // other types with a chain of conditionals: `i==0 ? a_0 : i==1 ? a_1 : ...`. Indices which are out of range read as 0 or an empty string.
+        fn index_chain(path: &Vec<String>, index: &Expression, length: usize, fields: &Fields) -> Result<Expression, CompilerError> {
+            let (element, _) = &fields.arrays[&canonicalise_field_path(path)];
+            let element_at = |i| Box::new(Expression::FieldAccess(element_path(path, i)));
+
+            return Ok(match element.typename.as_str() {
+                "number" | "bool" => (0..length)
+                    .map(|i| Expression::Multiply(Box::new(Expression::Bracket(Box::new(index_equals(index, i)))), element_at(i)))
+                    .reduce(|a, b| Expression::Add(Box::new(a), Box::new(b)))
+                    .unwrap_or(Expression::ConstNumber(YololNumber::zero())),
+
+                "string" => (0..length)
+                    .rev()
+                    .fold(Expression::ConstString("".to_string()), |rest, i| Expression::Ternary(Box::new(index_equals(index, i)), element_at(i), Box::new(rest))),
+
+                _ => return Err(CompilerError::CompilerStageNotImplemented(format!("Index array of `{}` with a value which is not constant", element.typename))),
+            });
+        }
+
+        // Get the index if it is known at compile time, checking that it is within the array
+        fn constant_index(path: &Vec<String>, index: &Expression, length: usize, fields: &Fields) -> Result<Option<usize>, CompilerError> {
+            let n = match constant_number(index, &fields.constants) {
+                Some(n) => n,
+                None => return Ok(None)
+            };
+
+            return match (0..length).find(|i| YololNumber::from_value(*i as i32) == n) {
+                Some(i) => Ok(Some(i)),
+                None => Err(CompilerError::IndexOutOfBounds(path.clone(), n, length))
+            };
+        }
+
+        // Store an index which is compared more than once in a field, unless it is already a simple value
+        fn single_use(index: Expression, fields: &mut Fields, hoisted: &mut Vec<InnerStatement>) -> Expression {
+            match index {
+                Expression::FieldAccess(_) | Expression::ExternalFieldAccess(_) | Expression::ConstNumber(_) => index,
+                other => {
+                    let name = format!("_index_{}", fields.indices);
+                    fields.indices += 1;
+
+                    hoisted.push(InnerStatement::DeclareAssign(FieldDefinition { name: name.clone(), typename: TypeName { typename: "number".to_string() } }, other));
+                    Expression::FieldAccess(vec![ name ])
+                }
+            }
+        }
+
+        fn index_equals(index: &Expression, i: usize) -> Expression {
+            Expression::Equals(Box::new(index.clone()), Box::new(Expression::ConstNumber(YololNumber::from_value(i as i32))))
+        }
+
+        fn array_length(path: &Vec<String>, fields: &Fields) -> Option<usize> {
+            fields.arrays.get(&canonicalise_field_path(path)).map(|(_, l)| *l)
+        }
+
+        fn element_path(path: &Vec<String>, i: usize) -> Vec<String> {
+            let mut path = path.clone();
+            path.push(i.to_string());
+            return path;
+        }
+
+        fn array_type(element: &TypeName, length: usize) -> crate::compiler::Type {
+            TypeName { typename: format!("{}[{}]", element.typename, length) }.to_type()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use crate::grammar::parser::y_parser;
+    use crate::error::{ CompilerError };
+    use crate::yolol::interpret::{ compile_and_run, Value };
+
+    fn run_program(code: &str, input: i32) -> Result<Value, CompilerError> {
+        let mut state = HashMap::new();
+        state.insert(":in".to_string(), Value::from(input));
+        return Ok(compile_and_run(y_parser::program(code).ok().unwrap(), state, 100)?.remove(":out").unwrap());
+    }
+
+    #[test]
+    fn constant_and_dynamic_indices() {
+        let code = "main {
+            var v: number[3] = 0;
+            for i in 0..3 { v[i] = i * 10; };
+            v[:in] = 5;
+            var j: number = 3;
+            :out = v[:in] + v[j - 1];
+        }";
+
+        assert_eq!(Value::from(25), run_program(code, 1).ok().unwrap());
+        assert_eq!(Value::from(10), run_program(code, 2).ok().unwrap());
+    }
+
+    #[test]
+    fn copy_strings() {
+        let code = "main {
+            var a: string[2] = \"x\";
+            a[1] = \"y\";
+            var b: string[2] = a;
+            :out = b[:in] + b[0];
+        }";
+
+        assert_eq!(Value::from("yx"), run_program(code, 1).ok().unwrap());
+    }
+
+    #[test]
+    fn constant_index_out_of_bounds() {
+        match run_program("main { var v: number[2] = 0; :out = v[2]; }", 0) {
+            Err(CompilerError::IndexOutOfBounds(path, _, 2)) => assert_eq!(vec![ "v".to_string() ], path),
+            _ => panic!("Expected index out of bounds error")
+        }
+    }
+}
\ No newline at end of file
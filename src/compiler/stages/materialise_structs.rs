@@ -55,6 +55,11 @@ impl InitialStatementBlocks {
 
                 // There should be no `Line` statements here, they've been separated into Line blocks by the initial_blocks pass
                 OuterStatement::Line(_, name) => panic!("Encountered line `{:?}` as an outer statement (0dc74572-9c3d-4a64-be5f-1293d26c7fb8)", name),
+
+                // There should be no loops here, they've been lowered into labels and gotos by the initial_blocks pass
+                OuterStatement::Loop(_) => panic!("Encountered loop as an outer statement (85392d9f-7260-4931-b6d8-575465fc00ab)"),
+                OuterStatement::While(_, _) => panic!("Encountered while loop as an outer statement (2041ac9c-b06f-40cb-a448-2575b9b06834)"),
+                OuterStatement::For(_, _, _, _) => panic!("Encountered for loop as an outer statement (ead99122-f867-460d-8f85-38c516144c8b)"),
             }
         }
 
@@ -0,0 +1,176 @@
+use std::collections::{ HashMap, HashSet };
+
+use crate::error::{ CompilerError };
+use crate::yolol::ast::{ Identifier };
+use crate::yolol::visit::{ visit_stmt_identifiers_mut };
+use super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+use super::super::fields::{ is_goto_label_field };
+
+// Words which cannot be used as a variable name in Yolol
+const RESERVED: &[&str] = &[
+    "if", "then", "else", "end", "goto",
+    "and", "or", "not",
+    "abs", "sqrt", "sin", "cos", "tan", "asin", "acos", "atan"
+];
+
+// Generate the nth shortest identifier (a, b, ..., z, aa, ab, ...)
+fn short_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.insert(0, (b'a' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    return name.into_iter().collect();
+}
+
+fn visit_blocks<F: FnMut(&mut Identifier)>(blocks: &mut Vec<YololBlock>, f: &mut F) {
+    for b in blocks.iter_mut() {
+        let stmts = match b {
+            YololBlock::Statements(_, stmts) => stmts,
+            YololBlock::Line(_, stmts) => stmts,
+        };
+        stmts.iter_mut().for_each(|s| visit_stmt_identifiers_mut(s, f));
+    }
+}
+
+impl YololStatementBlocks {
+
+    // Rename every internal variable to the shortest available name, the most used variables get the shortest names.
+    // Returns the renamed blocks and a map from each short name back to the original name.
+    pub fn minify_names(self) -> Result<(YololStatementBlocks, HashMap<String, String>), CompilerError> {
+
+        let mut blocks = self.blocks;
+
+        // Count how many times each internal variable is used. Goto labels are placeholders for line numbers, not real variables
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut kept: HashSet<String> = HashSet::new();
+        visit_blocks(&mut blocks, &mut |id| {
+            if id.external {
+                return;
+            }
+            if is_goto_label_field(&id.name) {
+                kept.insert(id.name.clone());
+            } else {
+                *counts.entry(id.name.clone()).or_insert(0) += 1;
+            }
+        });
+
+        // Names which are not renamed (labels and types of fields which are never used) cannot be given to another field
+        kept.extend(self.types.keys().filter(|k| !counts.contains_key(*k)).cloned());
+
+        // Most used first, ties broken by name so output is deterministic
+        let mut order: Vec<(String, usize)> = counts.into_iter().collect();
+        order.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut names = (0..).map(short_name).filter(|n| !RESERVED.contains(&n.as_str()) && !kept.contains(n));
+        let renames: HashMap<String, String> = order
+            .into_iter()
+            .map(|(name, _)| (name, names.next().unwrap()))
+            .collect();
+
+        visit_blocks(&mut blocks, &mut |id| {
+            if let Some(short) = renames.get(&id.name) {
+                if !id.external {
+                    id.name = short.clone();
+                }
+            }
+        });
+
+        let types = self.types
+            .into_iter()
+            .map(|(k, v)| (renames.get(&k).cloned().unwrap_or(k), v))
+            .collect();
+
+        let source_map = renames
+            .into_iter()
+            .map(|(k, v)| (v, k))
+            .collect();
+
+        return Ok((YololStatementBlocks {
+            blocks: blocks,
+            types: types,
+            consts: self.consts
+        }, source_map));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::compiler::{ Type };
+    use crate::yolol::ast::{ Statement, Expression };
+    use super::*;
+
+    #[test]
+    fn short_names() {
+        assert_eq!("a", short_name(0));
+        assert_eq!("z", short_name(25));
+        assert_eq!("aa", short_name(26));
+        assert_eq!("az", short_name(51));
+        assert_eq!("ba", short_name(52));
+        assert_eq!("zz", short_name(701));
+        assert_eq!("aaa", short_name(702));
+    }
+
+    fn id(name: &str, external: bool) -> Identifier {
+        Identifier { name: name.to_string(), external: external }
+    }
+
+    fn assign(target: Identifier, value: Identifier) -> Statement {
+        Statement::Assignment(target, Expression::VariableAccess(value))
+    }
+
+    fn minify(stmts: Vec<Statement>, types: Vec<&str>) -> (Vec<Statement>, HashMap<String, Type>, HashMap<String, String>) {
+        let blocks = YololStatementBlocks {
+            blocks: vec![ YololBlock::Statements(None, stmts) ],
+            types: types.into_iter().map(|t| (t.to_string(), Type::Num)).collect(),
+            consts: HashMap::new()
+        };
+
+        let (mut blocks, source_map) = blocks.minify_names().ok().unwrap();
+        return match blocks.blocks.remove(0) {
+            YololBlock::Statements(_, stmts) => (stmts, blocks.types, source_map),
+            _ => panic!("Unexpected block")
+        };
+    }
+
+    #[test]
+    fn most_used_names_are_shortest() {
+        let (stmts, _, source_map) = minify(vec![
+            assign(id("x", false), id("z", false)),
+            assign(id("z", false), id("x", false)),
+            assign(id("y", false), id("x", false)),
+        ], vec![]);
+
+        assert_eq!(vec![ "a=b", "b=a", "c=a" ], stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+        let mut map: Vec<_> = source_map.into_iter().collect();
+        map.sort();
+        assert_eq!(vec![
+            ("a".to_string(), "x".to_string()),
+            ("b".to_string(), "z".to_string()),
+            ("c".to_string(), "y".to_string()),
+        ], map);
+    }
+
+    #[test]
+    fn externals_are_not_renamed() {
+        let (stmts, _, source_map) = minify(vec![ assign(id("out", true), id("value", false)), assign(id("value", false), id("out", true)) ], vec![]);
+
+        assert_eq!(":out=a", stmts[0].to_string());
+        assert_eq!("a=:out", stmts[1].to_string());
+        assert_eq!(1, source_map.len());
+    }
+
+    #[test]
+    fn unused_names_are_not_reused() {
+        let (stmts, types, _) = minify(vec![ assign(id("out", true), id("value", false)) ], vec![ "a", "value" ]);
+
+        assert_eq!(":out=b", stmts[0].to_string());
+        assert_eq!(2, types.len());
+        assert!(types.contains_key("a") && types.contains_key("b"));
+    }
+}
\ No newline at end of file
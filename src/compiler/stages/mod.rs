@@ -1,4 +1,13 @@
 mod initial_blocks;
+mod loops;
+mod string_loops;
 mod inline_macros;
-mod yolol_blocks;
-mod materialise_structs;
\ No newline at end of file
+pub(crate) mod yolol_blocks;
+mod materialise_structs;
+mod materialise_arrays;
+mod fold_constants;
+mod peephole;
+mod minify_names;
+mod dead_code;
+mod control_flow;
+mod compact_loops;
\ No newline at end of file
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use yolol_number::prelude::*;
+
+use crate::error::{ CompilerError };
+use crate::yolol::ast::{ Statement, StatementList, Expression, Identifier, Op };
+use crate::compiler::typecheck::{ Type };
+use super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+use super::super::fields::{ goto_label_field };
+
+// Binding strength of an expression when it is printed without brackets, higher binds tighter
+fn precedence(expr: &Expression) -> u8 {
+    match expr {
+        Expression::ConstantNumber(n) => if *n < YololNumber::zero() { 8 } else { 10 },
+        Expression::ConstantString(_) => 10,
+        Expression::VariableAccess(_) => 10,
+        Expression::Bracket(_) => 10,
+        Expression::PostDecrement(_) => 10,
+        Expression::PostIncrement(_) => 10,
+        Expression::PreDecrement(_) => 10,
+        Expression::PreIncrement(_) => 10,
+
+        Expression::Exponent(_, _) => 9,
+
+        Expression::ACos(_) => 8,
+        Expression::ASin(_) => 8,
+        Expression::ATan(_) => 8,
+        Expression::Sqrt(_) => 8,
+        Expression::Cosine(_) => 8,
+        Expression::Sine(_) => 8,
+        Expression::Tangent(_) => 8,
+        Expression::Abs(_) => 8,
+        Expression::Negate(_) => 8,
+
+        Expression::Multiply(_, _) => 7,
+        Expression::Divide(_, _) => 7,
+        Expression::Modulus(_, _) => 7,
+
+        Expression::Add(_, _) => 6,
+        Expression::Subtract(_, _) => 6,
+
+        Expression::Equal(_, _) => 5,
+        Expression::NotEqual(_, _) => 5,
+        Expression::GreaterThan(_, _) => 5,
+        Expression::GreaterThanOrEq(_, _) => 5,
+        Expression::LessThan(_, _) => 5,
+        Expression::LessThanOrEq(_, _) => 5,
+
+        Expression::Not(_) => 4,
+        Expression::And(_, _) => 3,
+        Expression::Or(_, _) => 2,
+    }
+}
+
+// Check if an expression is guaranteed to evaluate to exactly 0 or 1
+fn is_boolean(expr: &Expression, types: &HashMap<String, Type>) -> bool {
+    match expr {
+        Expression::Equal(_, _) => true,
+        Expression::NotEqual(_, _) => true,
+        Expression::GreaterThan(_, _) => true,
+        Expression::GreaterThanOrEq(_, _) => true,
+        Expression::LessThan(_, _) => true,
+        Expression::LessThanOrEq(_, _) => true,
+        Expression::Not(_) => true,
+        Expression::And(_, _) => true,
+        Expression::Or(_, _) => true,
+
+        Expression::Bracket(x) => is_boolean(x, types),
+        Expression::ConstantNumber(n) => *n == YololNumber::zero() || *n == YololNumber::one(),
+        Expression::VariableAccess(id) => if let Some(Type::Bool) = internal_type(id, types) { true } else { false },
+
+        _ => false
+    }
+}
+
+// Get the type of an internal field, externals have no known type
+fn internal_type(id: &Identifier, types: &HashMap<String, Type>) -> Option<Type> {
+    if id.external {
+        return None;
+    }
+
+    return types.get(&id.name).map(|t| t.canonicalise());
+}
+
+fn is_field(expr: &Expression, id: &Identifier) -> bool {
+    match expr {
+        Expression::VariableAccess(v) => v.name == id.name && v.external == id.external,
+        _ => false
+    }
+}
+
+fn is_number(expr: &Expression, value: i32) -> bool {
+    match expr {
+        Expression::ConstantNumber(n) => *n == YololNumber::from_value(value),
+        _ => false
+    }
+}
+
+fn strip_root(expr: Expression) -> Expression {
+    match expr {
+        Expression::Bracket(x) => strip_root(*x),
+        x => x
+    }
+}
+
+impl YololStatementBlocks {
+
+    pub fn peephole_optimise(self) -> Result<YololStatementBlocks, CompilerError> {
+
+        let types = self.types;
+
+        // Find the label of the block after each block, execution falls through into it at the end of a line
+        let next_labels: Vec<Option<String>> = self.blocks
+            .iter()
+            .skip(1)
+            .map(|b| match b {
+                YololBlock::Statements(label, _) => label.clone(),
+                YololBlock::Line(label, _) => label.clone(),
+            })
+            .chain(std::iter::once(None))
+            .collect();
+
+        let blocks = self.blocks
+            .into_iter()
+            .zip(next_labels)
+            .map(|(b, next)| handle_block(b, next, &types))
+            .collect();
+
+        return Ok(YololStatementBlocks {
+            blocks: blocks,
+            types: types,
+            consts: self.consts
+        });
+
+        fn handle_block(b: YololBlock, next: Option<String>, types: &HashMap<String, Type>) -> YololBlock {
+            match b {
+                YololBlock::Statements(label, stmts) => YololBlock::Statements(label, handle_stmts(stmts, types)),
+                YololBlock::Line(label, stmts) => {
+                    let mut stmts = handle_stmts(stmts, types);
+
+                    // A conditional goto at the very end of a line either jumps or falls through to the next line, if the next line is
+                    // labelled that can be expressed as a single arithmetic goto
+                    if let Some(next) = next {
+                        if let Some(last) = stmts.pop() {
+                            stmts.push(arithmetic_goto(last, &next, types));
+                        }
+                    }
+
+                    YololBlock::Line(label, stmts)
+                }
+            }
+        }
+
+        fn arithmetic_goto(stmt: Statement, next: &str, types: &HashMap<String, Type>) -> Statement {
+            match stmt {
+                Statement::If(condition, pass, fail) => {
+                    if fail.statements.len() == 0 && pass.statements.len() == 1 && is_boolean(&condition, types) {
+                        if let Statement::Goto(target) = &pass.statements[0] {
+
+                            // goto next + (target - next) * condition
+                            let next = Expression::VariableAccess(Identifier { name: goto_label_field(next), external: false });
+                            let target = if precedence(target) >= 6 { target.clone() } else { Expression::Bracket(Box::new(target.clone())) };
+                            let distance = Expression::Bracket(Box::new(Expression::Subtract(Box::new(target), Box::new(next.clone()))));
+                            let condition = if precedence(&condition) > 7 { condition } else { Expression::Bracket(Box::new(condition)) };
+
+                            return Statement::Goto(Expression::Add(
+                                Box::new(next),
+                                Box::new(Expression::Multiply(Box::new(distance), Box::new(condition)))
+                            ));
+                        }
+                    }
+
+                    Statement::If(condition, pass, fail)
+                }
+                other => other
+            }
+        }
+
+        fn handle_stmts(stmts: Vec<Statement>, types: &HashMap<String, Type>) -> Vec<Statement> {
+            stmts
+                .into_iter()
+                .filter_map(|x| handle_stmt(x, types))
+                .collect()
+        }
+
+        fn handle_stmt(stmt: Statement, types: &HashMap<String, Type>) -> Option<Statement> {
+            match stmt {
+                Statement::Assignment(id, value) => {
+                    let value = strip_root(handle_expr(value, types));
+
+                    // `a = a` does nothing
+                    if is_field(&value, &id) && !id.external {
+                        return None;
+                    }
+
+                    // `a = a op b` can be written as `a op= b`
+                    match value {
+                        Expression::Add(l, r) if is_field(&l, &id) => handle_compound(id, Op::Add, *r, types),
+                        Expression::Subtract(l, r) if is_field(&l, &id) => handle_compound(id, Op::Subtract, *r, types),
+                        Expression::Multiply(l, r) if is_field(&l, &id) => handle_compound(id, Op::Multiply, *r, types),
+                        Expression::Divide(l, r) if is_field(&l, &id) => handle_compound(id, Op::Divide, *r, types),
+                        Expression::Modulus(l, r) if is_field(&l, &id) => handle_compound(id, Op::Modulo, *r, types),
+                        Expression::Exponent(l, r) if is_field(&l, &id) => handle_compound(id, Op::Exponent, *r, types),
+                        value => Some(Statement::Assignment(id, value))
+                    }
+                },
+
+                Statement::CompoundAssignment(id, op, value) => handle_compound(id, op, handle_expr(value, types), types),
+                Statement::ExpressionWrapper(expr) => Some(Statement::ExpressionWrapper(strip_root(handle_expr(expr, types)))),
+                Statement::Goto(expr) => Some(Statement::Goto(strip_root(handle_expr(expr, types)))),
+
+                Statement::If(condition, pass, fail) => Some(Statement::If(
+                    handle_condition(handle_expr(condition, types)),
+                    Box::new(StatementList { statements: handle_stmts(pass.statements, types) }),
+                    Box::new(StatementList { statements: handle_stmts(fail.statements, types) })
+                )),
+
+                other => Some(other)
+            }
+        }
+
+        fn handle_compound(id: Identifier, op: Op, value: Expression, types: &HashMap<String, Type>) -> Option<Statement> {
+            let value = strip_root(value);
+
+            // Increments and identity operations mean something different for strings, so only apply these to numeric fields
+            let numeric = match internal_type(&id, types) {
+                Some(Type::Num) | Some(Type::Bool) => true,
+                _ => false
+            };
+
+            if numeric {
+                match op {
+                    Op::Add if is_number(&value, 1) => return Some(Statement::ExpressionWrapper(Expression::PostIncrement(id))),
+                    Op::Subtract if is_number(&value, 1) => return Some(Statement::ExpressionWrapper(Expression::PostDecrement(id))),
+                    Op::Add | Op::Subtract if is_number(&value, 0) => return None,
+                    Op::Multiply | Op::Divide | Op::Exponent if is_number(&value, 1) => return None,
+                    _ => {}
+                }
+            }
+
+            return Some(Statement::CompoundAssignment(id, op, value));
+        }
+
+        // Conditions only care about truthiness, so a double negation can always be removed
+        fn handle_condition(condition: Expression) -> Expression {
+            match strip_root(condition) {
+                Expression::Not(x) => match strip_root(*x) {
+                    Expression::Not(y) => handle_condition(*y),
+                    y => Expression::Not(Box::new(unary_operand(y)))
+                },
+                other => other
+            }
+        }
+
+        // Remove the brackets around an operand if they're not needed to preserve the order of operations
+        fn operand(expr: Expression, parent: u8, allow_equal: bool) -> Expression {
+            match expr {
+                Expression::Bracket(x) => {
+                    let p = precedence(&x);
+                    if p > parent || (allow_equal && p == parent) {
+                        *x
+                    } else {
+                        Expression::Bracket(x)
+                    }
+                },
+                x => x
+            }
+        }
+
+        // Unary operators keep brackets around anything which isn't a single value
+        fn unary_operand(expr: Expression) -> Expression {
+            operand(Expression::Bracket(Box::new(strip_root(expr))), 9, false)
+        }
+
+        fn handle_unary(x: Box<Expression>, build: fn(Box<Expression>) -> Expression, types: &HashMap<String, Type>) -> Expression {
+            build(Box::new(unary_operand(handle_expr(*x, types))))
+        }
+
+        fn handle_binary(x: Box<Expression>, y: Box<Expression>, build: fn(Box<Expression>, Box<Expression>) -> Expression, precedence: u8, associative: bool, types: &HashMap<String, Type>) -> Expression {
+            build(
+                Box::new(operand(handle_expr(*x, types), precedence, associative)),
+                Box::new(operand(handle_expr(*y, types), precedence, false))
+            )
+        }
+
+        fn handle_expr(expr: Expression, types: &HashMap<String, Type>) -> Expression {
+            match expr {
+                Expression::Bracket(x) => {
+                    match handle_expr(*x, types) {
+                        x if precedence(&x) == 10 => x,
+                        x => Expression::Bracket(Box::new(x))
+                    }
+                },
+
+                // `not not x` is `x` if x is already a boolean
+                Expression::Not(x) => {
+                    match strip_root(handle_expr(*x, types)) {
+                        Expression::Not(y) if is_boolean(&y, types) => match precedence(&y) {
+                            10 => *y,
+                            _ => Expression::Bracket(Box::new(strip_root(*y)))
+                        },
+                        x => Expression::Not(Box::new(unary_operand(x)))
+                    }
+                },
+
+                Expression::ACos(x) => handle_unary(x, Expression::ACos, types),
+                Expression::ASin(x) => handle_unary(x, Expression::ASin, types),
+                Expression::ATan(x) => handle_unary(x, Expression::ATan, types),
+                Expression::Sqrt(x) => handle_unary(x, Expression::Sqrt, types),
+                Expression::Cosine(x) => handle_unary(x, Expression::Cosine, types),
+                Expression::Sine(x) => handle_unary(x, Expression::Sine, types),
+                Expression::Tangent(x) => handle_unary(x, Expression::Tangent, types),
+                Expression::Abs(x) => handle_unary(x, Expression::Abs, types),
+                Expression::Negate(x) => handle_unary(x, Expression::Negate, types),
+
+                Expression::Add(x, y) => handle_binary(x, y, Expression::Add, 6, true, types),
+                Expression::Subtract(x, y) => handle_binary(x, y, Expression::Subtract, 6, true, types),
+                Expression::Multiply(x, y) => handle_binary(x, y, Expression::Multiply, 7, true, types),
+                Expression::Divide(x, y) => handle_binary(x, y, Expression::Divide, 7, true, types),
+                Expression::Modulus(x, y) => handle_binary(x, y, Expression::Modulus, 7, true, types),
+                Expression::And(x, y) => handle_binary(x, y, Expression::And, 3, true, types),
+                Expression::Or(x, y) => handle_binary(x, y, Expression::Or, 2, true, types),
+                Expression::Exponent(x, y) => handle_binary(x, y, Expression::Exponent, 9, false, types),
+                Expression::Equal(x, y) => handle_binary(x, y, Expression::Equal, 5, false, types),
+                Expression::NotEqual(x, y) => handle_binary(x, y, Expression::NotEqual, 5, false, types),
+                Expression::GreaterThan(x, y) => handle_binary(x, y, Expression::GreaterThan, 5, false, types),
+                Expression::GreaterThanOrEq(x, y) => handle_binary(x, y, Expression::GreaterThanOrEq, 5, false, types),
+                Expression::LessThan(x, y) => handle_binary(x, y, Expression::LessThan, 5, false, types),
+                Expression::LessThanOrEq(x, y) => handle_binary(x, y, Expression::LessThanOrEq, 5, false, types),
+
+                other => other
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::compiler::typecheck::Type;
+    use crate::yolol::ast::{ Statement, StatementList, Expression, Identifier, Op };
+    use crate::grammar::parser::y_parser;
+    use crate::yolol::interpret::{ run, compile_and_run, Value };
+    use super::super::yolol_blocks::{ YololStatementBlocks, YololBlock };
+    use super::super::super::fields::{ goto_label_field };
+
+    fn id(name: &str) -> Identifier {
+        Identifier { name: name.to_string(), external: false }
+    }
+
+    fn optimise(stmts: Vec<Statement>) -> Vec<Statement> {
+        let mut types = HashMap::new();
+        types.insert("n".to_string(), Type::Num);
+        types.insert("s".to_string(), Type::Str);
+
+        let blocks = YololStatementBlocks {
+            blocks: vec![ YololBlock::Statements(None, stmts) ],
+            types: types,
+            consts: HashMap::new()
+        };
+
+        match blocks.peephole_optimise().ok().unwrap().blocks.remove(0) {
+            YololBlock::Statements(_, stmts) => stmts,
+            _ => panic!("Unexpected block")
+        }
+    }
+
+    fn add_one(name: &str) -> Statement {
+        Statement::Assignment(id(name), Expression::Bracket(Box::new(Expression::Add(
+            Box::new(Expression::VariableAccess(id(name))),
+            Box::new(Expression::ConstantNumber(YololNumber::one()))
+        ))))
+    }
+
+    #[test]
+    fn increment_numbers_only() {
+        let stmts = optimise(vec![ add_one("n"), add_one("s") ]);
+
+        match &stmts[0] {
+            Statement::ExpressionWrapper(Expression::PostIncrement(i)) => assert_eq!("n", i.name),
+            _ => panic!("Expected `n++`")
+        }
+
+        match &stmts[1] {
+            Statement::CompoundAssignment(i, Op::Add, Expression::ConstantNumber(_)) => assert_eq!("s", i.name),
+            _ => panic!("Expected `s += 1`")
+        }
+    }
+
+    #[test]
+    fn remove_noop_assignment() {
+        let stmts = optimise(vec![ Statement::Assignment(id("n"), Expression::Bracket(Box::new(Expression::VariableAccess(id("n"))))) ]);
+        assert_eq!(0, stmts.len());
+    }
+
+    #[test]
+    fn external_compound_assignment() {
+        // `:out -= n + 1` is parsed as `:out = :out - (n + 1)`
+        let out = Identifier { name: "out".to_string(), external: true };
+        let value = Expression::Add(Box::new(Expression::VariableAccess(id("n"))), Box::new(Expression::ConstantNumber(YololNumber::one())));
+        let stmts = optimise(vec![ Statement::Assignment(out.clone(), Expression::Subtract(
+            Box::new(Expression::VariableAccess(out)),
+            Box::new(Expression::Bracket(Box::new(value)))
+        )) ]);
+
+        assert_eq!(":out-=n+1", stmts[0].to_string());
+    }
+
+    #[test]
+    fn conditional_goto_at_end_of_line() {
+        let input = Identifier { name: "in".to_string(), external: true };
+        let out = Identifier { name: "out".to_string(), external: true };
+        let label = |l: &str| Expression::VariableAccess(id(&goto_label_field(l)));
+        let condition = Expression::GreaterThan(Box::new(Expression::VariableAccess(input)), Box::new(Expression::ConstantNumber(YololNumber::zero())));
+
+        // `if :in>0 then goto target end` followed by the `next` line, which falls through into `target`
+        let blocks = YololStatementBlocks {
+            blocks: vec![
+                YololBlock::Line(Some("start".to_string()), vec![
+                    Statement::If(condition, Box::new(StatementList { statements: vec![ Statement::Goto(label("target")) ] }), Box::new(StatementList { statements: vec![] }))
+                ]),
+                YololBlock::Line(Some("next".to_string()), vec![ Statement::Assignment(out.clone(), Expression::ConstantNumber(YololNumber::one())) ]),
+                YololBlock::Line(Some("target".to_string()), vec![ Statement::CompoundAssignment(out, Op::Add, Expression::ConstantNumber(YololNumber::from_value(10))) ]),
+            ],
+            types: HashMap::new(),
+            consts: HashMap::new()
+        };
+
+        let lines: Vec<_> = blocks.peephole_optimise().ok().unwrap().blocks.into_iter().map(|b| match b {
+            YololBlock::Line(_, stmts) => stmts,
+            _ => panic!("Unexpected block")
+        }).collect();
+        assert_eq!(format!("goto {}+({}-{})*(:in>0)", goto_label_field("next"), goto_label_field("target"), goto_label_field("next")), lines[0][0].to_string());
+
+        let run_with = |input: i32| {
+            let mut state = HashMap::new();
+            state.insert(":in".to_string(), Value::from(input));
+            for (i, l) in [ "start", "next", "target" ].iter().enumerate() {
+                state.insert(goto_label_field(l), Value::from(i as i32 + 1));
+            }
+            return run(&lines, state, 10).remove(":out").unwrap();
+        };
+        assert_eq!(Value::from(10), run_with(1));
+        assert_eq!(Value::from(11), run_with(0));
+    }
+
+    #[test]
+    fn run_compound_assignments() {
+        let code = "main {
+            var n: number = 5;
+            n -= 1;
+            n *= 3;
+            n /= 2;
+            n ^= 2;
+            n %= 5;
+            :out = n;
+
+            var s: string = \"abcab\";
+            s -= \"ab\";
+            :name = s;
+            :text -= \"ab\";
+
+            :x -= 1;
+            :x *= 4;
+            :x /= 2;
+            :x ^= 3;
+            :x %= 7;
+        }";
+
+        let mut state = HashMap::new();
+        state.insert(":text".to_string(), Value::from("abcabd"));
+        state.insert(":x".to_string(), Value::from(3));
+        let mut state = compile_and_run(y_parser::program(code).ok().unwrap(), state, 100).ok().unwrap();
+
+        // ((((5 - 1) * 3) / 2) ^ 2) % 5 and ((((3 - 1) * 4) / 2) ^ 3) % 7, subtracting a string removes the last occurrence
+        assert_eq!(Value::from(1), state.remove(":out").unwrap());
+        assert_eq!(Value::from(1), state.remove(":x").unwrap());
+        assert_eq!(Value::from("abc"), state.remove(":name").unwrap());
+        assert_eq!(Value::from("abcd"), state.remove(":text").unwrap());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,154 @@
+use yolol_number::prelude::*;
+
+use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression, FieldDefinition, TypeName };
+use crate::grammar::visit::{ visit_expr_mut, visit_stmt_exprs_mut };
+use crate::error::{ CompilerError };
+use crate::compiler::intrinsics::{ find_string_helper, StringHelper };
+
+// Find a call to a string helper which needs a loop (`len` or `parse_number`)
+fn looping_helper(expr: &Expression) -> Option<StringHelper> {
+    match expr {
+        Expression::Call(name, args) => find_string_helper(name).filter(|h| h.needs_loop() && args.len() == h.parameter_count()),
+        _ => None
+    }
+}
+
+// Replace calls to string helpers which need a loop with fields. Returns the statements which calculate those fields, these must run before the expression.
+pub fn hoist_string_loops(expr: &mut Expression, counter: &mut usize) -> Vec<OuterStatement> {
+    let mut hoisted = Vec::new();
+
+    visit_expr_mut(expr, &mut |e| {
+        let helper = match looping_helper(e) {
+            Some(h) => h,
+            None => return
+        };
+
+        let arg = match e {
+            Expression::Call(_, args) => args.remove(0),
+            _ => panic!("Expected string helper call (b7e0d5a1-96c2-4f3b-a8e4-1d5c7f2b9e06)")
+        };
+
+        let prefix = format!("_{}_{}", helper.name(), counter);
+        *counter += 1;
+
+        let (mut stmts, value) = string_loop(helper, arg, &prefix);
+        hoisted.append(&mut stmts);
+        *e = value;
+    });
+
+    return hoisted;
+}
+
+// Check that statements do not contain calls which need a loop, used where a loop cannot be placed
+pub fn check_no_string_loops(stmts: &[InnerStatement]) -> Result<(), CompilerError> {
+    let mut found = None;
+    for stmt in stmts.iter() {
+        visit_stmt_exprs_mut(&mut stmt.clone(), &mut |e| found = found.or(looping_helper(e)));
+    }
+
+    return match found {
+        Some(helper) => Err(CompilerError::StringLoopNotAllowed(helper.name().to_string())),
+        None => Ok(())
+    };
+}
+
+// Build the loop which calculates a string helper into a field named by the prefix
+fn string_loop(helper: StringHelper, arg: Expression, prefix: &str) -> (Vec<OuterStatement>, Expression) {
+    let field = |suffix: &str| if suffix.len() == 0 { prefix.to_string() } else { format!("{}_{}", prefix, suffix) };
+    let get = |suffix: &str| Expression::FieldAccess(vec![ field(suffix) ]);
+    let declare = |suffix: &str, typename: &str, value: Expression| OuterStatement::Inner(InnerStatement::DeclareAssign(
+        FieldDefinition { name: field(suffix), typename: TypeName { typename: typename.to_string() } },
+        value
+    ));
+    let num = |n: i32| Expression::ConstNumber(YololNumber::from_value(n));
+    let bracket = |x: Expression| Expression::Bracket(Box::new(x));
+
+    // Work on a copy of the string, consuming it one character at a time until it is empty
+    let not_empty = Expression::NotEquals(Box::new(get("string")), Box::new(Expression::ConstString("".to_string())));
+    let mut result = vec![
+        declare("string", "any", arg),
+        declare("", "number", num(0)),
+    ];
+
+    match helper {
+
+        // `while (s != "") { s--; n++; }`
+        StringHelper::Length => {
+            result.push(OuterStatement::While(not_empty, vec![
+                OuterStatement::Inner(InnerStatement::ExpressionWrapper(Expression::PostDecrement(vec![ field("string") ]))),
+                OuterStatement::Inner(InnerStatement::ExpressionWrapper(Expression::PostIncrement(vec![ field("") ]))),
+            ]));
+        },
+
+        // Digits are popped off the end, so each one is worth 10 times more than the last. The digit is found from the character
+        // by comparing it (as a string) against numbers: `d=3*((c>1)+(c>4)+(c>7))` is within 1 of the digit, then `d+(c>d)-(c<d)` corrects it.
+        StringHelper::ParseNumber => {
+            let compare = |op: fn(Box<Expression>, Box<Expression>) -> Expression, x: Expression| bracket(op(Box::new(get("char")), Box::new(x)));
+            let estimate = Expression::Multiply(
+                Box::new(num(3)),
+                Box::new(bracket(Expression::Add(
+                    Box::new(Expression::Add(Box::new(compare(Expression::GreaterThan, num(1))), Box::new(compare(Expression::GreaterThan, num(4))))),
+                    Box::new(compare(Expression::GreaterThan, num(7)))
+                )))
+            );
+            let digit = Expression::Subtract(
+                Box::new(Expression::Add(Box::new(get("digit")), Box::new(compare(Expression::GreaterThan, get("digit"))))),
+                Box::new(compare(Expression::LessThan, get("digit")))
+            );
+
+            result.push(declare("scale", "number", num(1)));
+            result.push(OuterStatement::While(not_empty, vec![
+                declare("char", "string", Expression::Call("pop_back".to_string(), vec![ get("string") ])),
+                declare("digit", "number", estimate),
+                OuterStatement::Inner(InnerStatement::Assign(vec![ field("") ], Expression::Add(
+                    Box::new(get("")),
+                    Box::new(bracket(Expression::Multiply(Box::new(bracket(digit)), Box::new(get("scale")))))
+                ))),
+                OuterStatement::Inner(InnerStatement::Assign(vec![ field("scale") ], Expression::Multiply(Box::new(get("scale")), Box::new(bracket(num(10)))))),
+            ]));
+        },
+
+        _ => panic!("`{}` does not need a loop (4d9a2f6e-0c1b-4e8d-97a3-b6f5e2c8d104)", helper.name())
+    }
+
+    return (result, get(""));
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use crate::grammar::parser::y_parser;
+    use crate::yolol::interpret::{ compile_and_run, Value };
+
+    // Compile a program and run it, returning the value of `:out`
+    fn run_program(code: &str, input: &str) -> Value {
+        let mut state = HashMap::new();
+        state.insert(":in".to_string(), Value::from(input));
+        return compile_and_run(y_parser::program(code).ok().unwrap(), state, 1000).ok().unwrap().remove(":out").unwrap();
+    }
+
+    #[test]
+    fn string_length() {
+        let code = "main { :out = len(:in); }";
+        assert_eq!(Value::from(0), run_program(code, ""));
+        assert_eq!(Value::from(5), run_program(code, "hello"));
+    }
+
+    #[test]
+    fn parse_numbers() {
+        let code = "main { :out = parse_number(:in) + 1; }";
+        assert_eq!(Value::from(1), run_program(code, ""));
+        assert_eq!(Value::from(1235), run_program(code, "1234"));
+        assert_eq!(Value::from(9087), run_program(code, "9086"));
+    }
+
+    #[test]
+    fn pop_contains_and_last_char() {
+        assert_eq!(Value::from("o"), run_program("main { :out = last_char(:in); }", "hello"));
+        assert_eq!(Value::from("hell"), run_program("main { var c: string = pop_back(:in); :out = :in; }", "hello"));
+        assert_eq!(Value::from(1), run_program("main { :out = contains(:in, \"ell\"); }", "hello"));
+        assert_eq!(Value::from(0), run_program("main { :out = contains(:in, \"lo!\"); }", "hello"));
+    }
+}
\ No newline at end of file
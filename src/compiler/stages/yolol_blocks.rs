@@ -2,13 +2,15 @@ use std::collections::HashMap;
 
 use yolol_number::prelude::*;
 
-use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression };
+use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression, FieldDefinition, TypeName };
+use crate::grammar::visit::{ visit_expr_mut, visit_stmt_mut, visit_stmt_exprs_mut };
 use crate::error::{ CompilerError };
 use crate::yolol;
 use crate::compiler::typecheck::{ Type, infer_expr_type, type_check_assignment };
 use crate::compiler::calls::{ CallType };
+use crate::compiler::intrinsics::{ find_intrinsic, find_string_helper, StringHelper };
 use super::initial_blocks::{ InitialStatementBlocks, Block };
-use super::super::fields::{ canonicalise_field_path };
+use super::super::fields::{ canonicalise_field_path, goto_label_field };
 
 
 #[derive(Debug)]
@@ -72,6 +74,11 @@ impl InitialStatementBlocks {
 
                 // There should be no `Line` statements here, they've been separated into Line blocks by the previous pass
                 OuterStatement::Line(_, name) => panic!("Encountered line `{:?}` as an outer statement (7e35f035-1da2-41bc-a5eb-641699a16e93)", name),
+
+                // There should be no loops here, they've been lowered into labels and gotos by the initial_blocks pass
+                OuterStatement::Loop(_) => panic!("Encountered loop as an outer statement (7f1d20d3-68e7-4a88-8dea-b8d778da3283)"),
+                OuterStatement::While(_, _) => panic!("Encountered while loop as an outer statement (a1961bd4-ba9b-454a-ad5f-5528d57b4c86)"),
+                OuterStatement::For(_, _, _, _) => panic!("Encountered for loop as an outer statement (8cd2119d-f7b2-4418-a5cf-f1a32b11c588)"),
             }
         }
 
@@ -88,6 +95,16 @@ impl InitialStatementBlocks {
 
         fn handle_inner_stmt(inner: &InnerStatement, types: &mut HashMap<String, Type>, consts: &mut HashMap<String, yolol::ast::Expression>) -> Result<Vec<yolol::ast::Statement>, CompilerError> {
 
+            // Conditional expressions which cannot be written as arithmetic are calculated by an `if` before this statement, `last_char`
+            // copies the string into a field before this statement
+            let mut hoisted = Vec::new();
+            let inner = &hoist_expressions(inner, types, &mut hoisted)?;
+            if hoisted.len() > 0 {
+                let mut result = handle_inner_stmts(&hoisted, types, consts)?;
+                result.append(&mut handle_inner_stmt(inner, types, consts)?);
+                return Ok(result);
+            }
+
             match inner {
                 InnerStatement::CompilePanic(msg, pos) => Err(CompilerError::ExplicitPanic(msg.to_string(), *pos)),
 
@@ -162,6 +179,20 @@ impl InitialStatementBlocks {
                     return Ok(Vec::new());
                 },
 
+                InnerStatement::ExpressionWrapper(expr) => {
+
+                    // Check an internal field being modified exists and has a type which can be modified this way
+                    match expr {
+                        Expression::ExternalPostIncrement(_) |
+                        Expression::ExternalPostDecrement(_) |
+                        Expression::ExternalPreIncrement(_) |
+                        Expression::ExternalPreDecrement(_) => {},
+                        other => { infer_expr_type(other, types)?; }
+                    }
+
+                    return Ok(vec![ yolol::ast::Statement::ExpressionWrapper(handle_expr(expr, types)?) ]);
+                },
+
                 InnerStatement::ExternalAssign(field, value) => {
 
                     // todo: Typecheck Externals according to device map.
@@ -179,6 +210,17 @@ impl InitialStatementBlocks {
 
                 // Return statements should have all been written out of existence in the macro inlining pass
                 InnerStatement::Return(_) => panic!("Encountered return statement in yolol_blocks pass (b2e2df60-218e-4f92-a9ac-603bad83ff0d)"),
+
+                // Match statements should have been turned into `if` chains or jump tables in the initial_blocks pass
+                InnerStatement::Match(_, _, _) => panic!("Encountered match statement in yolol_blocks pass (4b0e6c2a-93d1-4f8e-a7b5-1c2d3e9f6a80)"),
+
+                // Array element assignments should have been replaced with element fields in the materialise_arrays pass
+                InnerStatement::AssignIndex(path, _, _) => panic!("Encountered assignment to element of `{:?}` in yolol_blocks pass (c93e0b74-6d2f-4a1e-8b5d-7f4a3c9e1d06)", path),
+                InnerStatement::MethodCall(path, name, _) => panic!("Encountered method call `{}` on `{:?}` in yolol_blocks pass (0e5b7d2a-94c1-4f63-b8a0-3d6e1c9f2b57)", name, path),
+
+                // Loop control should have been turned into gotos when loops were lowered in the initial_blocks pass
+                InnerStatement::Break => Err(CompilerError::LoopControlOutsideLoop("break".to_string())),
+                InnerStatement::Continue => Err(CompilerError::LoopControlOutsideLoop("continue".to_string())),
                 
                 InnerStatement::Goto(name) => {
                     Ok(vec![
@@ -188,19 +230,224 @@ impl InitialStatementBlocks {
                                     external: false,
 
                                     // todo: A later stage will create lines, it'll need to assign these `goto_layout_label_foo` constants to the right line number
-                                    name: format!("goto_layout_label_{}", name)
+                                    name: goto_label_field(name)
                                 }
                             )
                         )
                     ])
+                },
+
+                InnerStatement::GotoOffset(name, offset) => {
+                    Ok(vec![
+                        yolol::ast::Statement::Goto(
+                            yolol::ast::Expression::Add(
+                                Box::new(yolol::ast::Expression::VariableAccess(yolol::ast::Identifier { external: false, name: goto_label_field(name) })),
+                                Box::new(yolol::ast::Expression::Bracket(Box::new(handle_expr(offset, types)?)))
+                            )
+                        )
+                    ])
                 }
             }
         }
 
+        // Check if `c ? a : b` can be calculated with arithmetic, which requires a boolean condition (0 or 1) and numeric values
+        fn is_arithmetic_conditional(c: &Expression, a: &Expression, b: &Expression, types: &HashMap<String, Type>) -> Result<bool, CompilerError> {
+            let numeric = |x: &Expression| Ok(match infer_expr_type(x, types)?.canonicalise() {
+                Type::Num | Type::Bool => true,
+                _ => false
+            });
+
+            let condition = match infer_expr_type(c, types)?.canonicalise() {
+                Type::Bool => true,
+                _ => false
+            };
+
+            // Both values are always evaluated, so neither may change anything or fail
+            return Ok(condition && numeric(a)? && numeric(b)? && is_safe(a) && is_safe(b));
+        }
+
+        // Check if an expression can be evaluated without side effects and without failing at runtime, so it is safe to evaluate
+        // even if the value is not used
+        fn is_safe(expr: &Expression) -> bool {
+            let mut safe = true;
+            visit_expr_mut(&mut expr.clone(), &mut |e| match e {
+                Expression::PostIncrement(_) | Expression::PostDecrement(_) |
+                Expression::PreIncrement(_) | Expression::PreDecrement(_) |
+                Expression::ExternalPostIncrement(_) | Expression::ExternalPostDecrement(_) |
+                Expression::ExternalPreIncrement(_) | Expression::ExternalPreDecrement(_) |
+                Expression::Call(_, _) | Expression::MethodCall(_, _, _) |
+                Expression::Index(_, _) | Expression::CompilePanic(_, _) => safe = false,
+
+                Expression::Divide(_, divisor) |
+                Expression::Modulus(_, divisor) => match **divisor {
+                    Expression::ConstNumber(n) if n != YololNumber::zero() => {},
+                    _ => safe = false
+                },
+                _ => {}
+            });
+            return safe;
+        }
+
+        // Find a name for a hoisted field which is not used by any other field, including fields declared inside hoisted branches
+        fn hoisted_name(prefix: &str, types: &HashMap<String, Type>, hoisted: &Vec<InnerStatement>) -> String {
+            let mut declared = Vec::new();
+            for stmt in hoisted.iter() {
+                visit_stmt_mut(&mut stmt.clone(), &mut |s| if let InnerStatement::DeclareAssign(f, _) = s {
+                    declared.push(f.name.clone());
+                });
+            }
+
+            return (0..)
+                .map(|i| format!("{}_{}", prefix, i))
+                .filter(|n| !types.contains_key(n) && !declared.contains(n))
+                .next()
+                .unwrap();
+        }
+
+        fn hoist_expressions(stmt: &InnerStatement, types: &HashMap<String, Type>, hoisted: &mut Vec<InnerStatement>) -> Result<InnerStatement, CompilerError> {
+            let mut stmt = stmt.clone();
+            let mut error = None;
+
+            // Only the condition of an `if` is always evaluated, the branches are handled when they are converted
+            match &mut stmt {
+                InnerStatement::If(condition, _, _) => hoist_tree(condition, types, hoisted, &mut error),
+                other => visit_stmt_exprs_mut(other, &mut |e| hoist_node(e, types, hoisted, &mut error)),
+            }
+
+            return match error {
+                Some(e) => Err(e),
+                None => Ok(stmt)
+            };
+        }
+
+        fn hoist_tree(expr: &mut Expression, types: &HashMap<String, Type>, hoisted: &mut Vec<InnerStatement>, error: &mut Option<CompilerError>) {
+            visit_expr_mut(expr, &mut |e| hoist_node(e, types, hoisted, error));
+        }
+
+        // Hoist a single expression. Anything which is always evaluated is hoisted from inside it first, so nested hoisted
+        // fields are declared before the field which uses them and all get different names.
+        fn hoist_node(e: &mut Expression, types: &HashMap<String, Type>, hoisted: &mut Vec<InnerStatement>, error: &mut Option<CompilerError>) {
+            let arithmetic = match e {
+                Expression::Ternary(c, a, b) => is_arithmetic_conditional(c, a, b, types),
+
+                // Copy the string into a field and pop the last character off of the copy
+                Expression::Call(name, args) if find_string_helper(name) == Some(StringHelper::LastChar) && args.len() == 1 => {
+                    let mut arg = args.remove(0);
+                    hoist_tree(&mut arg, types, hoisted, error);
+
+                    let name = hoisted_name("_last_char", types, hoisted);
+                    let any = TypeName { typename: "any".to_string() };
+                    hoisted.push(InnerStatement::DeclareAssign(FieldDefinition { name: name.clone(), typename: any }, arg));
+                    *e = Expression::Call("pop_back".to_string(), vec![ Expression::FieldAccess(vec![ name ]) ]);
+                    return;
+                },
+
+                _ => return
+            };
+
+            let typename = match arithmetic.and_then(|arithmetic| Ok((arithmetic, infer_expr_type(e, types)?))) {
+                Ok((true, _)) => return,
+                Ok((false, t)) => t.to_typename(),
+                Err(err) => { *error = error.take().or(Some(err)); return; }
+            };
+
+            let (mut c, mut a, mut b) = match std::mem::replace(e, Expression::ConstNumber(YololNumber::zero())) {
+                Expression::Ternary(c, a, b) => (*c, *a, *b),
+                _ => panic!("Expected conditional expression (d0f3b0a6-6a55-4d1e-9d39-5b8c7e2f41a9)")
+            };
+
+            // Only the condition is always evaluated. If `b` is safe to evaluate it's assigned first and overwritten by `a` when the
+            // condition is true, `a` is handled when the `if` branch is converted. Otherwise each value is assigned in its own branch,
+            // along with anything hoisted out of it, and the field is declared in the `if` branch which is converted first.
+            hoist_tree(&mut c, types, hoisted, error);
+            let name = if is_safe(&b) {
+                hoist_tree(&mut b, types, hoisted, error);
+                let name = hoisted_name("_conditional", types, hoisted);
+
+                hoisted.push(InnerStatement::DeclareAssign(FieldDefinition { name: name.clone(), typename: typename }, b));
+                hoisted.push(InnerStatement::If(c, vec![ InnerStatement::Assign(vec![ name.clone() ], a) ], vec![]));
+                name
+            } else {
+                let start = hoisted.len();
+                hoist_tree(&mut a, types, hoisted, error);
+                let middle = hoisted.len();
+                hoist_tree(&mut b, types, hoisted, error);
+                let name = hoisted_name("_conditional", types, hoisted);
+
+                let mut fail = hoisted.split_off(middle);
+                let mut pass = hoisted.split_off(start);
+                pass.push(InnerStatement::DeclareAssign(FieldDefinition { name: name.clone(), typename: typename }, a));
+                fail.push(InnerStatement::Assign(vec![ name.clone() ], b));
+                hoisted.push(InnerStatement::If(c, pass, fail));
+                name
+            };
+            *e = Expression::FieldAccess(vec![ name ]);
+        }
+
+        // Intrinsics are unary operators, which bind tighter than any binary operator. Anything except a single value or another
+        // unary operator must be in brackets (`sqrt(a+b)`).
+        fn unary_operand(expr: yolol::ast::Expression) -> yolol::ast::Expression {
+            match expr {
+                yolol::ast::Expression::ConstantNumber(_) |
+                yolol::ast::Expression::ConstantString(_) |
+                yolol::ast::Expression::VariableAccess(_) |
+                yolol::ast::Expression::Bracket(_) |
+                yolol::ast::Expression::ACos(_) |
+                yolol::ast::Expression::ASin(_) |
+                yolol::ast::Expression::ATan(_) |
+                yolol::ast::Expression::Sqrt(_) |
+                yolol::ast::Expression::Cosine(_) |
+                yolol::ast::Expression::Sine(_) |
+                yolol::ast::Expression::Tangent(_) |
+                yolol::ast::Expression::Abs(_) |
+                yolol::ast::Expression::Negate(_) |
+                yolol::ast::Expression::PostDecrement(_) |
+                yolol::ast::Expression::PostIncrement(_) |
+                yolol::ast::Expression::PreDecrement(_) |
+                yolol::ast::Expression::PreIncrement(_) => expr,
+                other => yolol::ast::Expression::Bracket(Box::new(other))
+            }
+        }
+
         fn find_call(name: &str) -> Result<CallType, CompilerError> {
             return Err(CompilerError::CompilerStageNotImplemented(format!("Find Call `{}`", name)));
         }
 
+        fn handle_string_helper(helper: StringHelper, expr: &Expression, args: &Vec<Expression>, types: &mut HashMap<String, Type>) -> Result<yolol::ast::Expression, CompilerError> {
+            let bracket = |x| yolol::ast::Expression::Bracket(Box::new(x));
+
+            return Ok(match helper {
+
+                // Evaluating the field before decrementing it leaves just the removed character after subtraction: `s-(--s)`
+                StringHelper::PopBack => {
+                    let id = match &args[0] {
+                        Expression::FieldAccess(path) => yolol::ast::Identifier { name: canonicalise_field_path(path), external: false },
+                        Expression::ExternalFieldAccess(name) => yolol::ast::Identifier { name: name.clone(), external: true },
+                        _ => return Err(CompilerError::StaticTypeError("Call `pop_back` on a value which is not a field".to_string(), expr.clone())),
+                    };
+                    yolol::ast::Expression::Subtract(
+                        Box::new(yolol::ast::Expression::VariableAccess(id.clone())),
+                        Box::new(bracket(yolol::ast::Expression::PreDecrement(id)))
+                    )
+                },
+
+                // Subtracting a string only changes the value if it contains that string: `s-x!=s`
+                StringHelper::Contains => {
+                    let s = handle_expr(&args[0], types)?;
+                    let x = handle_expr(&args[1], types)?;
+                    yolol::ast::Expression::NotEqual(
+                        Box::new(bracket(yolol::ast::Expression::Subtract(Box::new(bracket(s.clone())), Box::new(bracket(x))))),
+                        Box::new(bracket(s))
+                    )
+                },
+
+                // These have already been replaced by fields, `last_char` by `hoist_expressions` and the others by `while` loops when loops were lowered
+                StringHelper::LastChar |
+                StringHelper::Length |
+                StringHelper::ParseNumber => panic!("Encountered `{}` call which should have been lowered (8e2b6c1d-4f7a-4a9e-b3d5-0c6f1e9a7d24)", helper.name()),
+            });
+        }
+
         fn handle_expr(expr: &Expression, types: &mut HashMap<String, Type>) -> Result<yolol::ast::Expression, CompilerError> {
             Ok(match expr {
 
@@ -229,10 +476,36 @@ impl InitialStatementBlocks {
                 Expression::Equals(ref x, ref y) => yolol::ast::Expression::Equal(Box::new(handle_expr(x, types)?), Box::new(handle_expr(y, types)?)),
                 Expression::NotEquals(ref x, ref y) => yolol::ast::Expression::NotEqual(Box::new(handle_expr(x, types)?), Box::new(handle_expr(y, types)?)),
 
+                // Numeric conditionals with a boolean condition are calculated without branching as `b + (a - b) * c`, other conditionals have already been hoisted
+                Expression::Ternary(ref c, ref a, ref b) => {
+                    let a = handle_expr(a, types)?;
+                    let b = handle_expr(b, types)?;
+                    yolol::ast::Expression::Add(
+                        Box::new(yolol::ast::Expression::Bracket(Box::new(b.clone()))),
+                        Box::new(yolol::ast::Expression::Multiply(
+                            Box::new(yolol::ast::Expression::Bracket(Box::new(yolol::ast::Expression::Subtract(
+                                Box::new(yolol::ast::Expression::Bracket(Box::new(a))),
+                                Box::new(yolol::ast::Expression::Bracket(Box::new(b.clone())))
+                            )))),
+                            Box::new(yolol::ast::Expression::Bracket(Box::new(handle_expr(c, types)?)))
+                        ))
+                    )
+                },
+
                 Expression::FieldAccess(x) => yolol::ast::Expression::VariableAccess(yolol::ast::Identifier { name: canonicalise_field_path(x), external: false }),
                 Expression::ExternalFieldAccess(x) => yolol::ast::Expression::VariableAccess(yolol::ast::Identifier { name: x.clone(), external: true }),
 
-                Expression::Call(name, args) => panic!("Call {:?} with {:?}", name, args),
+                Expression::Call(name, args) => match (find_intrinsic(name), find_string_helper(name)) {
+                    (Some(intrinsic), _) => {
+                        infer_expr_type(expr, types)?;
+                        intrinsic(Box::new(unary_operand(handle_expr(&args[0], types)?)))
+                    },
+                    (None, Some(helper)) => {
+                        infer_expr_type(expr, types)?;
+                        handle_string_helper(helper, expr, args, types)?
+                    },
+                    (None, None) => panic!("Call {:?} with {:?}", name, args),
+                },
 
                 Expression::Is(ref expr, ref typename) => {
                     if type_check_assignment(&typename.to_type(), &infer_expr_type(expr, types)?).is_ok() {
@@ -243,14 +516,117 @@ impl InitialStatementBlocks {
                 },
                 Expression::TypeOf(ref expr) => yolol::ast::Expression::ConstantString(format!("{}", infer_expr_type(expr, types)?)),
                 
-                Expression::PostIncrement(name) => yolol::ast::Expression::PostDecrement(yolol::ast::Identifier { name: canonicalise_field_path(name), external: false }),
+                Expression::PostIncrement(name) => yolol::ast::Expression::PostIncrement(yolol::ast::Identifier { name: canonicalise_field_path(name), external: false }),
                 Expression::PostDecrement(name) => yolol::ast::Expression::PostDecrement(yolol::ast::Identifier { name: canonicalise_field_path(name), external: false }),
                 Expression::PreIncrement(name) => yolol::ast::Expression::PreIncrement(yolol::ast::Identifier { name: canonicalise_field_path(name), external: false }),
                 Expression::PreDecrement(name) => yolol::ast::Expression::PreDecrement(yolol::ast::Identifier { name: canonicalise_field_path(name), external: false }),
+                Expression::ExternalPostIncrement(name) => yolol::ast::Expression::PostIncrement(yolol::ast::Identifier { name: name.clone(), external: true }),
+                Expression::ExternalPostDecrement(name) => yolol::ast::Expression::PostDecrement(yolol::ast::Identifier { name: name.clone(), external: true }),
+                Expression::ExternalPreIncrement(name) => yolol::ast::Expression::PreIncrement(yolol::ast::Identifier { name: name.clone(), external: true }),
+                Expression::ExternalPreDecrement(name) => yolol::ast::Expression::PreDecrement(yolol::ast::Identifier { name: name.clone(), external: true }),
 
                 // There should be no `Constructor` expressions here, they've been replaced with simple variables in the materialise_structs pass
                 Expression::Constructor(ctor) => panic!("Encountered constructor expression `{:?}` (e4147676-1a10-4cf1-8b4f-eb7b11044000)", ctor),
+
+                // There should be no `Index` expressions here, they've been replaced with element fields in the materialise_arrays pass
+                Expression::Index(path, _) => panic!("Encountered index expression on `{:?}` (5a7c2e91-3b4d-4f8a-9e6c-0d1b8f7a2c35)", path),
+                Expression::MethodCall(path, name, _) => panic!("Encountered method call `{}` on `{:?}` (7b3f9c16-2e8d-4a05-a1c4-5f0e6d8b3a92)", name, path),
+                Expression::NamedArgument(name, _) => panic!("Encountered named argument `{}` outside of a call (e2a64c0b-5d17-4b9e-8f31-9c7a0d5e6b48)", name),
             })
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use yolol_number::prelude::*;
+
+    use crate::grammar::ast::{ InnerStatement, OuterStatement, Expression };
+    use crate::grammar::parser::y_parser;
+    use crate::yolol::interpret::{ compile_and_run, State, Value };
+    use super::super::initial_blocks::{ InitialStatementBlocks, Block };
+    use super::*;
+
+    fn convert(value: Expression) -> Vec<yolol::ast::Statement> {
+        let blocks = InitialStatementBlocks {
+            blocks: vec![ Block::Statements(None, vec![ OuterStatement::Inner(InnerStatement::ExternalAssign("out".to_string(), value)) ]) ],
+            callables: HashMap::new(),
+            structs: HashMap::new()
+        };
+
+        match blocks.covert_yolol_blocks().ok().unwrap().blocks.remove(0) {
+            YololBlock::Statements(_, stmts) => stmts,
+            _ => panic!("Unexpected block")
+        }
+    }
+
+    fn conditional(a: Expression, b: Expression) -> Expression {
+        let condition = Expression::GreaterThan(Box::new(Expression::ExternalFieldAccess("x".to_string())), Box::new(Expression::ConstNumber(YololNumber::zero())));
+        Expression::Ternary(Box::new(condition), Box::new(a), Box::new(b))
+    }
+
+    #[test]
+    fn numeric_conditional_is_arithmetic() {
+        let stmts = convert(conditional(Expression::ConstNumber(YololNumber::one()), Expression::ConstNumber(YololNumber::from_value(2))));
+
+        assert_eq!(1, stmts.len());
+        assert_eq!(":out=(2)+((1)-(2))*(:x>0)", stmts[0].to_string());
+    }
+
+    #[test]
+    fn string_conditional_is_hoisted() {
+        let stmts = convert(conditional(Expression::ConstString("a".to_string()), Expression::ConstString("b".to_string())));
+
+        assert_eq!(3, stmts.len());
+        assert_eq!("_conditional_0=\"b\"", stmts[0].to_string());
+        assert_eq!("if :x>0 then _conditional_0=\"a\" end", stmts[1].to_string());
+        assert_eq!(":out=_conditional_0", stmts[2].to_string());
+    }
+
+    fn run_program(code: &str, input: i32) -> State {
+        let state = vec![ (":in".to_string(), Value::from(input)) ].into_iter().collect();
+        return compile_and_run(y_parser::program(code).ok().unwrap(), state, 100).ok().unwrap();
+    }
+
+    #[test]
+    fn conditional_only_evaluates_one_branch() {
+        let code = "main { var i: number = 0; var x: number = 5; var r: number = :in > 0 ? x : i++; :out = i; :r = r; }";
+        let mut state = run_program(code, 1);
+        assert_eq!(Value::from(0), state.remove(":out").unwrap());
+        assert_eq!(Value::from(5), state.remove(":r").unwrap());
+        assert_eq!(Value::from(1), run_program(code, 0).remove(":out").unwrap());
+
+        let code = "main { var i: number = 0; var x: number = 0; :out = :in > 0 ? i++ : x / i; }";
+        assert_eq!(Value::from(0), run_program(code, 1).remove(":out").unwrap());
+    }
+
+    #[test]
+    fn conditional_does_not_fail_in_unused_branch() {
+        let code = "main { var x: number = 0; if (:in > 0) { x = 4; }; :out = x != 0 ? 1 / x : 7; }";
+        assert_eq!(Value::Num(YololNumber::one() / YololNumber::from_value(4)), run_program(code, 1).remove(":out").unwrap());
+        assert_eq!(Value::from(7), run_program(code, 0).remove(":out").unwrap());
+    }
+
+    #[test]
+    fn increment_and_decrement() {
+        let add = Expression::Add(
+            Box::new(Expression::PostIncrement(vec![ "a".to_string() ])),
+            Box::new(Expression::ExternalPreDecrement("b".to_string()))
+        );
+
+        assert_eq!(":out=a++ + --:b", convert(add)[0].to_string());
+    }
+
+    #[test]
+    fn intrinsic_calls() {
+        let call = Expression::Call("sqrt".to_string(), vec![ Expression::Call("abs".to_string(), vec![ Expression::ConstNumber(YololNumber::from_value(-4)) ]) ]);
+        assert_eq!(":out=sqrt abs -4", convert(call)[0].to_string());
+
+        let sum = Expression::Add(Box::new(Expression::ConstNumber(YololNumber::one())), Box::new(Expression::ConstNumber(YololNumber::from_value(3))));
+        let call = Expression::Call("sqrt".to_string(), vec![ sum ]);
+        assert_eq!(":out=sqrt(1+3)", convert(call)[0].to_string());
+    }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use crate::error::{ CompilerError };
 use crate::grammar::ast::{ Expression, TypeName };
 use crate::compiler::fields::{ canonicalise_field_path };
+use crate::compiler::intrinsics::{ find_intrinsic, find_string_helper };
 
 #[derive(Debug, Clone)]
 pub enum Type {
@@ -150,6 +151,92 @@ pub fn infer_expr_type(expr: &Expression, fields: &HashMap<String, Type>) -> Res
             }
         }
 
+        Expression::Modulus(a, b) => {
+            let l = infer_expr_type(a, fields)?;
+            let r = infer_expr_type(b, fields)?;
+            match (l, r) {
+                (Type::Num, Type::Str) => return Err(CompilerError::StaticTypeError("Modulus number by string".to_string(), expr.clone())),
+                (Type::Bool, Type::Str) => return Err(CompilerError::StaticTypeError("Modulus bool by string".to_string(), expr.clone())),
+                (Type::Str, Type::Num) => return Err(CompilerError::StaticTypeError("Modulus string by number".to_string(), expr.clone())),
+                (Type::Str, Type::Bool) => return Err(CompilerError::StaticTypeError("Modulus string by bool".to_string(), expr.clone())),
+                (Type::Str, Type::Str) => return Err(CompilerError::StaticTypeError("Modulus string by string".to_string(), expr.clone())),
+
+                (l, r) => default_binary_expr(&l, &r, expr, inference_failed)?
+            }
+        }
+
+        Expression::Exponent(a, b) => {
+            let l = infer_expr_type(a, fields)?;
+            let r = infer_expr_type(b, fields)?;
+            match (l, r) {
+                (Type::Num, Type::Str) => return Err(CompilerError::StaticTypeError("Raise number to the power of string".to_string(), expr.clone())),
+                (Type::Bool, Type::Str) => return Err(CompilerError::StaticTypeError("Raise bool to the power of string".to_string(), expr.clone())),
+                (Type::Str, Type::Num) => return Err(CompilerError::StaticTypeError("Raise string to the power of number".to_string(), expr.clone())),
+                (Type::Str, Type::Bool) => return Err(CompilerError::StaticTypeError("Raise string to the power of bool".to_string(), expr.clone())),
+                (Type::Str, Type::Str) => return Err(CompilerError::StaticTypeError("Raise string to the power of string".to_string(), expr.clone())),
+
+                (l, r) => default_binary_expr(&l, &r, expr, inference_failed)?
+            }
+        }
+
+        Expression::Ternary(_, a, b) => {
+            let l = infer_expr_type(a, fields)?;
+            let r = infer_expr_type(b, fields)?;
+            match (l.canonicalise(), r.canonicalise()) {
+                (Type::Num, Type::Bool) => Type::Num,
+                (Type::Bool, Type::Num) => Type::Num,
+                (Type::Num, Type::Num) => Type::Num,
+                (Type::Bool, Type::Bool) => Type::Bool,
+                (Type::Str, Type::Str) => Type::Str,
+                (Type::Other(a), Type::Other(b)) if a == b => Type::Other(a),
+                (l, r) => return Err(CompilerError::StaticTypeError(format!("Conditional branches have different types ({} and {})", l, r), expr.clone())),
+            }
+        }
+
+        Expression::PostIncrement(f) |
+        Expression::PostDecrement(f) |
+        Expression::PreIncrement(f) |
+        Expression::PreDecrement(f) => {
+            // Incrementing a string appends a space, decrementing removes the last character
+            match fields.get(&canonicalise_field_path(f)).map(|t| t.canonicalise()) {
+                Some(Type::Num) => Type::Num,
+                Some(Type::Str) => Type::Str,
+                Some(Type::Any) => Type::Any,
+                Some(t) => return Err(CompilerError::StaticTypeError(format!("Increment or decrement {}", t), expr.clone())),
+                None => return Err(CompilerError::FieldTypeNotKnown(f.clone())),
+            }
+        }
+
+        Expression::Call(name, args) if find_intrinsic(name).is_some() => {
+            if args.len() != 1 {
+                return Err(CompilerError::IncorrectCallParameterCount(name.clone(), 1, args.len()));
+            }
+
+            match infer_expr_type(&args[0], fields)?.canonicalise() {
+                Type::Num | Type::Bool => Type::Num,
+                t => return Err(CompilerError::StaticTypeError(format!("Pass {} to `{}`", t, name), expr.clone())),
+            }
+        }
+
+        Expression::Call(name, args) if find_string_helper(name).is_some() => {
+            let helper = find_string_helper(name).unwrap();
+            if args.len() != helper.parameter_count() {
+                return Err(CompilerError::IncorrectCallParameterCount(name.clone(), helper.parameter_count(), args.len()));
+            }
+
+            for arg in args.iter() {
+                match infer_expr_type(arg, fields)?.canonicalise() {
+                    Type::Str | Type::Any => {},
+                    t => return Err(CompilerError::StaticTypeError(format!("Pass {} to `{}`", t, name), expr.clone())),
+                }
+            }
+
+            helper.return_type()
+        }
+
+        // External fields can hold any value
+        Expression::ExternalFieldAccess(_) => Type::Any,
+
         Expression::FieldAccess(f) => {
             let canonical = canonicalise_field_path(f);
             if let Some(t) = fields.get(&canonical) {
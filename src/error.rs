@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use crate::compiler::Type;
 use crate::grammar::ast::Expression;
+use crate::yolol;
+use yolol_number::YololNumber;
 
 pub enum CompilerError {
     IO(PathBuf, std::io::Error),
@@ -18,5 +20,28 @@ pub enum CompilerError {
     ExpressionTypeInferenceFailed(Expression),
     StaticTypeError(String, Expression),
     ConstructorExpression(),
-    FieldConstructorAssignment(Type, Vec<(String, Expression)>)
+    FieldConstructorAssignment(Type, Vec<(String, Expression)>),
+    ConstantDivisionByZero(yolol::ast::Expression),
+    LoopControlOutsideLoop(String),
+    StringLoopNotAllowed(String),
+    NotAnArray(Vec<String>),
+    IndexOutOfBounds(Vec<String>, YololNumber, usize),
+    TypeParameterNotInferred(String, String),
+    NoReturnValue(String),
+    MissingReturn(String),
+    MisplacedReturn(String),
+    DuplicateCallableDefinition(String),
+    AmbiguousCall(String, Vec<String>),
+    NoMatchingOverload(String, Vec<String>),
+    UnknownNamedArgument(String, String),
+    DuplicateArgument(String, String),
+    ConstEvaluationFailed(String, String),
+    RecursiveCall(Vec<String>),
+    PrivateItem(String),
+    UnresolvedName(String),
+    ImportCycle(Vec<PathBuf>),
+    LibraryNotFound(String, Vec<PathBuf>),
+    StdModuleNotFound(String),
+    RecursiveConstant(Vec<String>),
+    RecursionTooDeep(String, usize)
 }
\ No newline at end of file
@@ -1,6 +1,12 @@
-#[derive(Debug)]
+use std::collections::HashSet;
+
+use crate::error::{ CompilerError };
+use super::resolve::{ rename_references };
+
+#[derive(Debug, Clone)]
 pub struct Program {
     pub imports: Vec<Import>,
+    pub uses: Vec<Use>,
     pub constants: Vec<Constant>,
     pub enums: Vec<EnumDefinition>,
     pub structs: Vec<StructDefinition>,
@@ -10,11 +16,14 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn combine(mut self, mut b: Self, namespace: Option<String>) -> Self {
+    // Merge an imported module into this one. `references` are the names of items used by this module which it does not define, these
+    // must not be private to the imported module.
+    pub fn combine(mut self, mut b: Self, namespace: Option<String>, references: &HashSet<String>) -> Result<Self, CompilerError> {
 
         if let Some(ns) = namespace {
             b.apply_namespace(&ns);
         }
+        b.check_references(references)?;
 
         self.imports.append(&mut b.imports);
         self.constants.append(&mut b.constants);
@@ -25,7 +34,27 @@ impl Program {
 
         self.main = self.main.or(b.main);
 
-        return self;
+        return Ok(self);
+    }
+
+    // Check that an importer does not use any item which is private to this module, once it is placed into `namespace`. Used when
+    // the module has already been merged into the same namespace through another import.
+    pub fn check_private(&self, namespace: &Option<String>, references: &HashSet<String>) -> Result<(), CompilerError> {
+        let mut module = self.clone();
+        if let Some(ns) = namespace {
+            module.apply_namespace(ns);
+        }
+        return module.check_references(references);
+    }
+
+    fn check_references(&self, references: &HashSet<String>) -> Result<(), CompilerError> {
+        let private = self.private_names();
+        let mut used: Vec<_> = references.iter().filter(|r| private.contains(*r)).collect();
+        used.sort();
+        return match used.first() {
+            Some(name) => Err(CompilerError::PrivateItem(name.replace(":", "::"))),
+            None => Ok(())
+        };
     }
 
     pub fn clear_imports(mut self) -> Self {
@@ -33,8 +62,63 @@ impl Program {
         return self;
     }
 
+    // Replace the aliases declared with `use` in this module with the items they refer to
+    pub fn apply_uses(mut self) -> Self {
+        let uses = self.uses.clone();
+        rename_references(&mut self, &mut |n| uses.iter().find(|u| u.alias == n).map(|u| u.path.clone()));
+        return self;
+    }
+
+    // Names of items used by this module which it does not define
+    pub fn references(&self) -> HashSet<String> {
+        let defined = self.defined_names();
+        let mut references = HashSet::new();
+        rename_references(&mut self.clone(), &mut |n| {
+            if !defined.contains(n) {
+                references.insert(n.to_string());
+            }
+            None
+        });
+        return references;
+    }
+
+    fn defined_names(&self) -> HashSet<String> {
+        return self.items().into_iter().map(|(n, _)| n).collect();
+    }
+
+    // Names of items which are not `pub`, overloads of a callable are private if none of them are `pub`
+    fn private_names(&self) -> HashSet<String> {
+        let items = self.items();
+        let public: HashSet<_> = items.iter().filter(|(_, p)| *p).map(|(n, _)| n.clone()).collect();
+        return items.into_iter().map(|(n, _)| n).filter(|n| !public.contains(n)).collect();
+    }
+
+    // Name and visibility of every item defined in this program
+    fn items(&self) -> Vec<(String, bool)> {
+        return self.constants.iter().map(|x| (x.field.name.clone(), x.public))
+            .chain(self.enums.iter().map(|x| (x.name.clone(), x.public)))
+            .chain(self.structs.iter().map(|x| (x.name.clone(), x.public)))
+            .chain(self.ranges.iter().map(|x| (x.name.clone(), x.public)))
+            .chain(self.callables.iter().map(|x| (x.name.clone(), x.public)))
+            .collect();
+    }
+
+    // Check that every qualified name (`ns::item`) refers to an item, once every import has been merged
+    pub fn check_qualified_names(&self) -> Result<(), CompilerError> {
+        let mut unresolved: Vec<_> = self.references().into_iter().filter(|n| n.contains(':')).collect();
+        unresolved.sort();
+        return match unresolved.first() {
+            Some(name) => Err(CompilerError::UnresolvedName(name.replace(":", "::"))),
+            None => Ok(())
+        };
+    }
+
     fn apply_namespace(&mut self, ns: &str) {
 
+        // References inside the module to its own items (including items it imported) are renamed along with the items
+        let defined = self.defined_names();
+        rename_references(self, &mut |n| if defined.contains(n) { Some(format!("{}:{}", ns, n)) } else { None });
+
         fn apply<T, FA, FG>(ns: &str, items: &mut Vec<T>, get: FG, app: FA)
             where FG: Fn(&T) -> String,
                   FA: Fn(&mut T, String) -> ()
@@ -57,31 +141,45 @@ impl Program {
 #[derive(Clone, Debug)]
 pub struct Import {
     pub path: String,
-    pub namespace: Option<String>
+    pub namespace: Option<String>,
+
+    // `import <path>;` is searched for in the library paths instead of beside the importing file
+    pub library: bool
+}
+
+// `use ns::item as alias;` makes `alias` refer to `ns:item`
+#[derive(Clone, Debug)]
+pub struct Use {
+    pub path: String,
+    pub alias: String
 }
 
 #[derive(Debug, Clone)]
 pub struct Constant {
     pub field: FieldDefinition,
-    pub value: Expression
+    pub value: Expression,
+    pub public: bool
 }
 
 #[derive(Debug, Clone)]
 pub struct EnumDefinition {
     pub name: String,
     pub base: String,
-    pub items: Vec<EnumItemDefinition>
+    pub items: Vec<EnumItemDefinition>,
+    pub public: bool
 }
 
 #[derive(Debug, Clone)]
 pub struct EnumItemDefinition {
     pub name: String,
+    pub value: Expression
 }
 
 #[derive(Debug, Clone)]
 pub struct StructDefinition {
     pub name: String,
-    pub fields: Vec<FieldDefinition>
+    pub fields: Vec<FieldDefinition>,
+    pub public: bool
 }
 
 #[derive(Debug, Clone)]
@@ -89,6 +187,7 @@ pub struct RangeDefinition {
     pub name: String,
     pub base: String,
     pub expression: Expression,
+    pub public: bool
 }
 
 #[derive(Debug, Clone)]
@@ -101,16 +200,24 @@ pub enum CallType {
 pub struct CallableDefinition {
     pub name: String,
     pub call_type: CallType,
+
+    // Names of generic type parameters, these are replaced with concrete types at each call site
+    pub type_parameters: Vec<String>,
+
     pub parameters: Vec<ParameterDefinition>,
     pub return_type: Option<String>,
     pub statements: Vec<InnerStatement>,
-    pub attributes: Vec<Attribute>
+    pub attributes: Vec<Attribute>,
+    pub public: bool
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct ParameterDefinition {
     pub field: FieldDefinition,
-    pub copy: bool
+    pub copy: bool,
+
+    // Value used when no argument is passed for this parameter
+    pub default: Option<Expression>
 }
 
 #[derive(Debug, Clone)]
@@ -129,6 +236,15 @@ pub struct TypeName {
     pub typename: String
 }
 
+impl TypeName {
+    // Fixed size arrays are written `element[length]`, get the element type and length if this is an array type
+    pub fn array(&self) -> Option<(TypeName, usize)> {
+        let open = self.typename.find('[')?;
+        let length = self.typename[open + 1..].strip_suffix(']')?.parse().ok()?;
+        return Some((TypeName { typename: self.typename[..open].to_string() }, length));
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Attribute {
     pub name: String,
@@ -137,7 +253,9 @@ pub struct Attribute {
 
 #[derive(Debug, Clone)]
 pub enum OuterStatement {
-    //Loop(Vec<OuterStatement>),
+    Loop(Vec<OuterStatement>),
+    While(Expression, Vec<OuterStatement>),
+    For(String, Expression, Expression, Vec<OuterStatement>),
     Line(Vec<InnerStatement>, Option<String>),
     Inner(InnerStatement),
     Label(String),
@@ -150,15 +268,32 @@ pub enum InnerStatement {
     Emit(String),
 
     Call(String, Vec<Expression>),
+
+    // Call a method of the struct at the path (`a.b.update(x)`)
+    MethodCall(Vec<String>, String, Vec<Expression>),
+
     If(Expression, Vec<InnerStatement>, Vec<InnerStatement>),
+    Match(Expression, Vec<(Expression, Vec<InnerStatement>)>, Vec<InnerStatement>),
 
     Assign(Vec<String>, Expression),
+
+    // Assign to an element of a fixed size array (`a[i] = x`)
+    AssignIndex(Vec<String>, Expression, Expression),
+
     DeclareAssign(FieldDefinition, Expression),
     DeclareConst(FieldDefinition, Expression),
     ExternalAssign(String, Expression),
 
+    // An expression evaluated only for its side effects (`a++`)
+    ExpressionWrapper(Expression),
+
     Return(Expression),
-    Goto(String)
+    Goto(String),
+
+    // Jump to the line `offset` lines after the labelled line, used for jump tables. Never written in source code.
+    GotoOffset(String, Expression),
+    Break,
+    Continue
 }
 
 use yolol_number::YololNumber;
@@ -171,9 +306,17 @@ pub enum Expression {
     ConstString(String),
     FieldAccess(Vec<String>),
     ExternalFieldAccess(String),
+
+    // Read an element of a fixed size array (`a[i]`)
+    Index(Vec<String>, Box<Expression>),
+
     Negate(Box<Expression>),
     Not(Box<Expression>),
     Call(String, Vec<Expression>),
+    MethodCall(Vec<String>, String, Vec<Expression>),
+
+    // An argument passed to a parameter by name (`f(a: 1)`)
+    NamedArgument(String, Box<Expression>),
 
     Is(Box<Expression>, TypeName),
     TypeOf(Box<Expression>),
@@ -186,6 +329,7 @@ pub enum Expression {
     And(Box<Expression>, Box<Expression>),
     Or(Box<Expression>, Box<Expression>),
     Bracket(Box<Expression>),
+    Ternary(Box<Expression>, Box<Expression>, Box<Expression>),
 
     GreaterThan(Box<Expression>, Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
@@ -199,5 +343,10 @@ pub enum Expression {
     PreIncrement(Vec<String>),
     PreDecrement(Vec<String>),
 
+    ExternalPostIncrement(String),
+    ExternalPostDecrement(String),
+    ExternalPreIncrement(String),
+    ExternalPreDecrement(String),
+
     Constructor(Vec<(String, Expression)>),
 }
\ No newline at end of file
@@ -1,2 +1,5 @@
 pub mod ast;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod visit;
+pub mod resolve;
+pub mod modules;
\ No newline at end of file
@@ -0,0 +1,260 @@
+use std::collections::{ HashMap, HashSet };
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use rayon::prelude::*;
+
+use crate::error::{ CompilerError };
+use crate::stdlib;
+use super::ast::{ Program, Import };
+use super::parser::{ y_parser };
+
+// A parsed file, with the canonical paths (and namespaces) of the files it imports
+struct Module {
+    program: Program,
+    imports: Vec<(PathBuf, Option<String>)>
+}
+
+// The first import of a file found while loading. `depth` is the number of imports between the root file and the importing file.
+#[derive(Debug)]
+pub struct ImportEdge {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub depth: usize
+}
+
+// Parse a file and everything it imports into a single program. Every file is parsed once no matter how many times it is imported,
+// and every module is merged once into each namespace it is imported into. Libraries (`import <path>;`) are found in `lib_paths`.
+// Also returns the import which first found each file, in the order the files were loaded.
+pub fn parse_program(path: &Path, lib_paths: &[PathBuf]) -> Result<(Program, Vec<ImportEdge>), CompilerError> {
+    let root = canonicalise(path)?;
+    let (modules, edges) = load(&root, lib_paths)?;
+    check_cycles(&root, &modules)?;
+
+    let mut merged = HashSet::new();
+    merged.insert((root.clone(), None));
+    let program = assemble(&root, &modules, &mut merged)?;
+    program.check_qualified_names()?;
+
+    return Ok((program, edges));
+}
+
+fn canonicalise(path: &Path) -> Result<PathBuf, CompilerError> {
+    return fs::canonicalize(path).map_err(|x| CompilerError::IO(path.to_path_buf(), x));
+}
+
+// Find the file an import refers to, libraries are searched for in each library path in order. Standard library modules are not
+// files, they keep the path they were imported with.
+fn import_path(import: &Import, parent: &Path, lib_paths: &[PathBuf]) -> Result<PathBuf, CompilerError> {
+    if !import.library && import.path.starts_with(stdlib::PREFIX) {
+        return match stdlib::module_source(&import.path) {
+            Some(_) => Ok(PathBuf::from(&import.path)),
+            None => Err(CompilerError::StdModuleNotFound(import.path.clone()))
+        };
+    }
+
+    if !import.library {
+        return canonicalise(&parent.join(&import.path));
+    }
+
+    return match lib_paths.iter().map(|l| l.join(&import.path)).find(|p| p.is_file()) {
+        Some(p) => canonicalise(&p),
+        None => Err(CompilerError::LibraryNotFound(import.path.clone(), lib_paths.to_vec()))
+    };
+}
+
+fn parse_file(path: &PathBuf, lib_paths: &[PathBuf]) -> Result<Module, CompilerError> {
+    let code = match path.to_str().and_then(stdlib::module_source) {
+        Some(code) => code.to_string(),
+        None => fs::read_to_string(path).map_err(|x| CompilerError::IO(path.clone(), x))?
+    };
+    let program = y_parser::program(&code).map_err(|x| CompilerError::Parse(path.clone(), code, x))?.apply_uses();
+
+    let parent = path.parent().unwrap();
+    let imports = program.imports
+        .iter()
+        .map(|i| Ok((import_path(i, parent, lib_paths)?, i.namespace.clone())))
+        .collect::<Result<Vec<_>, CompilerError>>()?;
+
+    return Ok(Module { program, imports });
+}
+
+// Parse every file reachable from the root, each layer of imports is parsed in parallel
+fn load(root: &PathBuf, lib_paths: &[PathBuf]) -> Result<(HashMap<PathBuf, Module>, Vec<ImportEdge>), CompilerError> {
+    let mut modules = HashMap::new();
+    let mut edges = Vec::new();
+    let mut layer = vec![ root.clone() ];
+    let mut depth = 0;
+
+    while layer.len() > 0 {
+        let parsed = layer.par_iter().map(|p| parse_file(p, lib_paths)).collect::<Result<Vec<_>, _>>()?;
+
+        let mut next = Vec::new();
+        for (path, module) in layer.into_iter().zip(parsed.into_iter()) {
+            for (p, _) in module.imports.iter() {
+                if !modules.contains_key(p) && *p != path && !next.contains(p) {
+                    edges.push(ImportEdge { from: path.clone(), to: p.clone(), depth: depth });
+                    next.push(p.clone());
+                }
+            }
+            modules.insert(path, module);
+        }
+
+        layer = next.into_iter().filter(|p| !modules.contains_key(p)).collect();
+        depth += 1;
+    }
+
+    return Ok((modules, edges));
+}
+
+// Find the first chain of imports which leads back to a file already being imported
+fn check_cycles(root: &PathBuf, modules: &HashMap<PathBuf, Module>) -> Result<(), CompilerError> {
+    let mut stack = Vec::new();
+    let mut done = HashSet::new();
+    return visit(root, modules, &mut stack, &mut done);
+
+    fn visit<'a>(path: &'a PathBuf, modules: &'a HashMap<PathBuf, Module>, stack: &mut Vec<&'a PathBuf>, done: &mut HashSet<&'a PathBuf>) -> Result<(), CompilerError> {
+        if let Some(start) = stack.iter().position(|p| *p == path) {
+            let chain = stack[start..].iter().map(|p| p.to_path_buf()).chain(std::iter::once(path.clone())).collect();
+            return Err(CompilerError::ImportCycle(chain));
+        }
+
+        if !done.insert(path) {
+            return Ok(());
+        }
+
+        stack.push(path);
+        for (p, _) in modules[path].imports.iter() {
+            visit(p, modules, stack, done)?;
+        }
+        stack.pop();
+
+        return Ok(());
+    }
+}
+
+// Merge a module with everything it imports, skipping modules which have already been merged into the same namespace. Everything
+// imported into a namespace is renamed along with it, so a namespaced import starts again with nothing merged.
+fn assemble(path: &PathBuf, modules: &HashMap<PathBuf, Module>, merged: &mut HashSet<(PathBuf, Option<String>)>) -> Result<Program, CompilerError> {
+    let module = &modules[path];
+    let references = module.program.references();
+
+    let mut program = module.program.clone().clear_imports();
+    for (p, namespace) in module.imports.iter() {
+
+        // A module which was merged through another import must still only be used through its public items
+        if !merged.insert((p.clone(), namespace.clone())) {
+            let imported = assemble(p, modules, &mut vec![ (p.clone(), None) ].into_iter().collect())?;
+            imported.check_private(namespace, &references)?;
+            continue;
+        }
+
+        let imported = match namespace {
+            Some(_) => assemble(p, modules, &mut vec![ (p.clone(), None) ].into_iter().collect())?,
+            None => assemble(p, modules, merged)?,
+        };
+        program = program.combine(imported, namespace.clone(), &references)?;
+    }
+
+    return Ok(program);
+}
+
+// Files written to a new temporary directory for a test, the directory is deleted when this is dropped
+#[cfg(test)]
+pub struct TestFiles {
+    pub dir: PathBuf
+}
+
+#[cfg(test)]
+impl TestFiles {
+    pub fn new(name: &str, files: &[(&str, &str)]) -> TestFiles {
+        let dir = std::env::temp_dir().join(format!("yc_{}_{}", name, uuid::Uuid::new_v4()));
+        for (file, code) in files.iter() {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), code).unwrap();
+        }
+        return TestFiles { dir };
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn diamond_imports_are_merged_once() {
+        let files = TestFiles::new("modules_diamond", &[
+            ("main.y", "import \"a.y\"; import \"b.y\"; import \"shared.y\" in s; main { }"),
+            ("a.y", "import \"shared.y\"; pub def macro a() -> number { return k; }"),
+            ("b.y", "import \"shared.y\"; pub def macro b() -> number { return k; }"),
+            ("shared.y", "pub const k: number = 1;"),
+        ]);
+
+        let program = parse_program(&files.dir.join("main.y"), &[]).ok().unwrap().0;
+        let mut names: Vec<_> = program.constants.iter().map(|c| c.field.name.clone()).collect();
+        names.sort();
+        assert_eq!(vec![ "k", "s:k" ], names);
+        assert_eq!(2, program.callables.len());
+    }
+
+    #[test]
+    fn private_items_are_checked_for_every_importer() {
+        let files = TestFiles::new("modules_diamond_private", &[
+            ("main.y", "import \"a.y\"; import \"b.y\"; main { }"),
+            ("a.y", "import \"shared.y\"; pub def macro a() -> number { return k; }"),
+            ("b.y", "import \"shared.y\"; pub def macro b() -> number { return secret; }"),
+            ("shared.y", "pub const k: number = 1; const secret: number = 2;"),
+        ]);
+
+        match parse_program(&files.dir.join("main.y"), &[]) {
+            Err(CompilerError::PrivateItem(name)) => assert_eq!("secret", name),
+            _ => panic!("Expected private item error")
+        }
+    }
+
+    #[test]
+    fn import_cycles_are_reported() {
+        let files = TestFiles::new("modules_cycle", &[
+            ("main.y", "import \"a.y\"; main { }"),
+            ("a.y", "import \"b.y\";"),
+            ("b.y", "import \"a.y\";"),
+        ]);
+
+        match parse_program(&files.dir.join("main.y"), &[]) {
+            Err(CompilerError::ImportCycle(chain)) => {
+                let names: Vec<_> = chain.iter().map(|p| p.file_name().unwrap().to_str().unwrap().to_string()).collect();
+                assert_eq!(vec![ "a.y", "b.y", "a.y" ], names);
+            },
+            _ => panic!("Expected import cycle")
+        }
+    }
+
+    #[test]
+    fn libraries_are_found_in_lib_paths() {
+        let first = TestFiles::new("modules_lib_first", &[ ("util.y", "pub const k: number = 1;") ]);
+        let second = TestFiles::new("modules_lib_second", &[ ("util.y", "pub const k: number = 2;"), ("std/extra.y", "pub const e: number = 3;") ]);
+        let files = TestFiles::new("modules_lib_main", &[ ("main.y", "import <util.y>; import <std/extra.y>; main { }") ]);
+
+        let program = parse_program(&files.dir.join("main.y"), &[ first.dir.clone(), second.dir.clone() ]).ok().unwrap().0;
+        let values: Vec<_> = program.constants.iter().map(|c| format!("{}={:?}", c.field.name, c.value)).collect();
+        assert_eq!(2, values.len());
+        assert!(values[0].starts_with("k=") && values[0].contains("1000"));
+        assert!(values[1].starts_with("e="));
+
+        match parse_program(&files.dir.join("main.y"), &[ first.dir.clone() ]) {
+            Err(CompilerError::LibraryNotFound(path, searched)) => {
+                assert_eq!("std/extra.y", path);
+                assert_eq!(1, searched.len());
+            },
+            _ => panic!("Expected library not found")
+        }
+    }
+}
\ No newline at end of file
@@ -26,85 +26,138 @@ fn split_types(types : Vec<Type>) -> (Vec<EnumDefinition>, Vec<StructDefinition>
     return (enums, structs, ranges);
 }
 
+// Builds the binary expression for a compound assignment operator
+type CompoundOp = fn(Box<Expression>, Box<Expression>) -> Expression;
+
 peg::parser!{
 
     pub grammar y_parser() for str {
 
         pub rule program() -> Program
-            = __ i:import()* __ t:typedef()* __ con:constant()* __ c:callable()* __ m:main()? __
+            = __ i:import()* __ u:use_item()* __ t:typedef()* __ con:constant()* __ c:callables()* __ m:main()? __
             {
                 let (enums, structs, ranges) = split_types(t);
 
                 Program {
                     imports: i,
+                    uses: u,
                     constants: con,
                     enums: enums,
                     structs: structs,
                     ranges: ranges,
-                    callables: c,
+                    callables: c.into_iter().flatten().collect(),
                     main: m
                 }
             }
 
         rule import() -> Import
-            = "import" __ "\"" p:path() "\"" __ n:("in" __ n:identifier() { n })? __ ";" __
-            { Import { path: p, namespace: n } }
+            = "import" __ l:import_path() __ n:("in" __ n:identifier() { n })? __ ";" __
+            { Import { path: l.0, library: l.1, namespace: n } }
             / expected!("Import Definition")
 
+        rule import_path() -> (String, bool)
+            = "\"" p:path() "\"" { (p, false) }
+            / "<" p:path() ">" { (p, true) }
+
+        rule use_item() -> Use
+            = "use" __ p:qualified_identifier() __ a:("as" __ a:identifier() { a })? __ ";" __
+            { Use { alias: a.unwrap_or(p.rsplit(':').next().unwrap().to_string()), path: p } }
+
+        // Items are private to the module which defines them unless they are marked `pub`
+        rule visibility() -> bool
+            = p:("pub" !ident_char() __)?
+            { p.is_some() }
+
+        // Items in other modules are referred to as `namespace::item`, which is stored as `namespace:item`
+        rule qualified_identifier() -> String
+            = i:(identifier() ++ "::")
+            { i.join(":") }
+
 
         rule typedef() -> Type
-            = "type" __ t:(
-                e:enumdef() { Type::Enum(e) }
-                / s:structdef() { Type::Struct(s)}
-                / r:rangedef() { Type::Range(r) }
+            = p:visibility() "type" __ t:(
+                e:enumdef(p) { Type::Enum(e) }
+                / s:structdef(p) { Type::Struct(s)}
+                / r:rangedef(p) { Type::Range(r) }
             ) __
             { t }
 
         rule constant() -> Constant
-            = "const" __ f:field() __ "=" __ e:expression() __ ";" __
+            = p:visibility() "const" __ f:field() __ "=" __ e:expression() __ ";" __
             { Constant {
                 field: f,
-                value: e
+                value: e,
+                public: p
             } }
 
-        rule enumdef() -> EnumDefinition
-            = "enum" __ "<" __ b:identifier() __ ">" __ n:identifier() __ "{" __ e:(enum_item() ** ("," __)) __ "}"
-            { EnumDefinition { name: n, base: b, items: e } }
+        rule enumdef(public: bool) -> EnumDefinition
+            = "enum" __ "<" __ b:qualified_identifier() __ ">" __ n:identifier() __ "{" __ e:(enum_item() ** ("," __)) __ "}"
+            { EnumDefinition { name: n, base: b, items: e, public: public } }
 
         rule enum_item() -> EnumItemDefinition
-            = i:identifier() __ "(" __ "todo_enum_value" __ ")"
-            { EnumItemDefinition { name: i } }
+            = i:identifier() __ "(" __ e:expression() __ ")"
+            { EnumItemDefinition { name: i, value: e } }
 
-        rule rangedef() -> RangeDefinition
-            = "range" __ "<" __ b:identifier() __ ">" __ n:identifier() __ "->" __  e:expression() __ ";"
-            { RangeDefinition { name: n, base: b, expression: e } }
+        rule rangedef(public: bool) -> RangeDefinition
+            = "range" __ "<" __ b:qualified_identifier() __ ">" __ n:identifier() __ "->" __  e:expression() __ ";"
+            { RangeDefinition { name: n, base: b, expression: e, public: public } }
 
-        rule structdef() -> StructDefinition
+        rule structdef(public: bool) -> StructDefinition
             = "struct" __ i:identifier() __ "{" __ f:(field() ** ("," __)) __ ","? __ "}"
-            { StructDefinition { name: i, fields: f } }
+            { StructDefinition { name: i, fields: f, public: public } }
 
 
 
+        rule callables() -> Vec<CallableDefinition>
+            = c:callable() { vec![ c ] }
+            / impl_block()
+
+        // Methods of a struct are named `struct.method`, `self` is a reference to the struct
+        rule impl_block() -> Vec<CallableDefinition>
+            = "impl" __ t:identifier() __ "{" __ c:callable()* __ "}" __
+            {
+                c.into_iter().map(|mut c| {
+                    c.name = format!("{}.{}", t, c.name);
+                    for p in c.parameters.iter_mut().filter(|p| p.field.name == "self") {
+                        p.field.typename = TypeName { typename: t.clone() };
+                    }
+                    c
+                }).collect()
+            }
+
         rule callable() -> CallableDefinition
-            = at:attributes()? __ "def" __ c:call_type() __ n:identifier() __ a:arglist() __ r:("->" __ i:identifier() { i })? __ "{" __ s:statement_list() __ "}" __
+            = at:attributes()? __ p:visibility() "def" __ c:call_type() __ n:identifier() __ g:type_parameters()? __ a:arglist() __ r:("->" __ i:qualified_identifier() { i })? __ "{" __ s:statement_list() __ "}" __
             { CallableDefinition {
                 call_type: c,
                 name: n,
+                type_parameters: g.unwrap_or(Vec::new()),
                 parameters: a,
                 return_type: r,
                 statements: s,
-                attributes: at.unwrap_or(Vec::new())
+                attributes: at.unwrap_or(Vec::new()),
+                public: p
             } }
 
+        rule type_parameters() -> Vec<String>
+            = "<" __ g:(identifier() ** ("," __)) __ ">"
+            { g }
+
         rule arglist() -> Vec<ParameterDefinition>
             = "(" __ a:(arg() ** ("," __)) __ ")"
             { a }
         
         rule arg() -> ParameterDefinition
-            = c:"copy "? f:field()
+            = "self" !(['A'..='Z' | 'a'..='z' | '0'..='9' | '_'] / __ ":")
+            { ParameterDefinition {
+                field: FieldDefinition { name: "self".to_string(), typename: TypeName { typename: "self".to_string() } },
+                copy: false,
+                default: None
+            }}
+            / c:"copy "? f:field() d:(__ "=" __ e:expression() { e })?
             { ParameterDefinition {
                 field: f,
-                copy: c.is_some()
+                copy: c.is_some(),
+                default: d
             }}
 
         rule call_type() -> CallType
@@ -128,8 +181,12 @@ peg::parser!{
         rule outer_statement() -> OuterStatement
             = "line" __ id:("(" id:identifier() ")" { id })? __ "{" __ l:statement_list() __ "}"
             { OuterStatement::Line(l, id) }
-            //"loop" __ "{" __ l:outer_statement_list() __ "}"
-            //{ OuterStatement::Loop(l) }
+            / "loop" !ident_char() __ "{" __ l:outer_statement_list() __ "}"
+            { OuterStatement::Loop(l) }
+            / "while" !ident_char() __ "(" __ c:expression() __ ")" __ "{" __ l:outer_statement_list() __ "}"
+            { OuterStatement::While(c, l) }
+            / "for" !ident_char() __ i:identifier() __ "in" __ s:expression() __ ".." __ e:expression() __ "{" __ l:outer_statement_list() __ "}"
+            { OuterStatement::For(i, s, e, l) }
             / "@" i:identifier()
             { OuterStatement::Label(i) }
             / s:statement()
@@ -144,29 +201,82 @@ peg::parser!{
         rule statement() -> InnerStatement
             = p:position!() "panic" __ "(" __ m:string() __ ")"
             { InnerStatement::CompilePanic(m, p) }
-            / "if" __ "(" __ c:expression() __ ")" __ "{" __ t:statement_list() __ "}" f:(__ "else" __ "{" __ f:statement_list() __ "}" { f })?
-            { InnerStatement::If(c, t, f.unwrap_or(Vec::new())) }
-            / "return" __ e:expression()
+            / i:if_statement()
+            { i }
+            / "match" !ident_char() __ v:expression() __ "{" __ a:(match_arm() ** ("," __)) __ ","? __ "}"
+            {
+                // Arms after the default can never be reached
+                let default = a.iter().position(|(p, _)| p.is_none());
+                let fallback = default.map(|d| a[d].1.clone()).unwrap_or(Vec::new());
+                let arms = a.into_iter().take(default.unwrap_or(usize::MAX)).map(|(p, s)| (p.unwrap(), s)).collect();
+                InnerStatement::Match(v, arms, fallback)
+            }
+            / "return" !ident_char() __ e:expression()
             { InnerStatement::Return(e) }
             / "emit" __ "{" __ s:string() __ "}"
             { InnerStatement::Emit(s) }
-            / "goto" __ i:identifier()
+            / "goto" !ident_char() __ i:identifier()
             { InnerStatement::Goto(i) }
-            / i:identifier() __ "(" __ a:(expression() ** ("," __)) __ ")"
+            / "break" !ident_char()
+            { InnerStatement::Break }
+            / "continue" !ident_char()
+            { InnerStatement::Continue }
+            / i:qualified_identifier() __ "(" __ a:call_arguments() __ ")"
             { InnerStatement::Call(i, a) }
+            / m:method_call()
+            { InnerStatement::MethodCall(m.0, m.1, m.2) }
             / "var" __ f:field() __ "=" __ e:expression()
             { InnerStatement::DeclareAssign(f, e) }
             / "const" __ f:field() __ "=" __ e:expression()
             { InnerStatement::DeclareConst(f, e) }
             / i:field_access() __ "=" __ e:expression()
             { InnerStatement::Assign(i, e) }
+            / i:field_access() __ "[" __ x:expression() __ "]" __ "=" __ e:expression()
+            { InnerStatement::AssignIndex(i, x, e) }
             / ":" i:identifier() __ "=" __ e:expression()
             { InnerStatement::ExternalAssign(i, e) }
-            / i:field_access() __ "+=" __ e:expression()
-            { InnerStatement::Assign(i.clone(), Expression::Add(Box::new(Expression::FieldAccess(i)), Box::new(e)))}
+            / i:field_access() __ o:compound_op() __ e:expression()
+            { InnerStatement::Assign(i.clone(), o(Box::new(Expression::FieldAccess(i)), Box::new(Expression::Bracket(Box::new(e))))) }
+            / ":" i:identifier() __ o:compound_op() __ e:expression()
+            { InnerStatement::ExternalAssign(i.clone(), o(Box::new(Expression::ExternalFieldAccess(i)), Box::new(Expression::Bracket(Box::new(e))))) }
+            / i:increment()
+            { InnerStatement::ExpressionWrapper(i) }
+
+        // `a op= b` is written as `a = a op (b)`, this is turned back into a compound assignment in the peephole pass
+        rule compound_op() -> CompoundOp
+            = "+=" { Expression::Add }
+            / "-=" { Expression::Subtract }
+            / "*=" { Expression::Multiply }
+            / "/=" { Expression::Divide }
+            / "%=" { Expression::Modulus }
+            / "^=" { Expression::Exponent }
+
+        rule increment() -> Expression
+            = i:field_access() __ "++" { Expression::PostIncrement(i) }
+            / i:field_access() __ "--" { Expression::PostDecrement(i) }
+            / "++" __ i:field_access() { Expression::PreIncrement(i) }
+            / "--" __ i:field_access() { Expression::PreDecrement(i) }
+            / ":" i:identifier() __ "++" { Expression::ExternalPostIncrement(i) }
+            / ":" i:identifier() __ "--" { Expression::ExternalPostDecrement(i) }
+            / "++" __ ":" i:identifier() { Expression::ExternalPreIncrement(i) }
+            / "--" __ ":" i:identifier() { Expression::ExternalPreDecrement(i) }
+
+        rule if_statement() -> InnerStatement
+            = "if" __ "(" __ c:expression() __ ")" __ "{" __ t:statement_list() __ "}" f:(__ "else" __ f:else_branch() { f })?
+            { InnerStatement::If(c, t, f.unwrap_or(Vec::new())) }
+
+        rule else_branch() -> Vec<InnerStatement>
+            = i:if_statement()
+            { vec![ i ] }
+            / "{" __ f:statement_list() __ "}"
+            { f }
+
+        rule match_arm() -> (Option<Expression>, Vec<InnerStatement>)
+            = p:("_" !ident_char() { None } / e:expression() { Some(e) }) __ "=>" __ "{" __ s:statement_list() __ "}"
+            { (p, s) }
 
         rule field_access() -> Vec<String> =
-            s:identifier() f:("." f:(f:identifier() ** "." { f }) { f })?
+            s:qualified_identifier() f:("." f:(f:identifier() ** "." { f }) { f })?
             {
                 let mut v = f.unwrap_or(Vec::new());
                 v.insert(0, s);
@@ -178,19 +288,21 @@ peg::parser!{
             { a } 
 
         rule attribute() -> Attribute
-            = n:identifier() __ "(" __ a:(expression() ** ("," __)) __ ")"
-            { Attribute { name: n, parameters: a } }
+            = n:identifier() __ a:("(" __ a:(expression() ** ("," __)) __ ")" { a })?
+            { Attribute { name: n, parameters: a.unwrap_or(Vec::new()) } }
 
 
         rule expression() -> Expression
             = precedence!{
                 p:position!() "panic" __ "(" __ m:string() __ ")" { Expression::CompilePanic(m, p) }
                 --
+                c:@ __ "?" __ a:expression() __ ":" __ b:(@) { Expression::Ternary(Box::new(c), Box::new(a), Box::new(b)) }
+                --
                 x:(@) __ "&" "&"? __ y:@ { Expression::And(Box::new(x), Box::new(y)) }
                 --
                 x:(@) __ "|" "|"? __ y:@ { Expression::Or(Box::new(x), Box::new(y)) }
                 --
-                x:(@) __ "is" __ y:identifier() { Expression::Is(Box::new(x), TypeName { typename: y }) }
+                x:(@) __ "is" __ y:qualified_identifier() { Expression::Is(Box::new(x), TypeName { typename: y }) }
                 --
                 x:(@) __ "==" __ y:@ { Expression::Equals(Box::new(x), Box::new(y)) }
                 x:(@) __ "!=" __ y:@ { Expression::NotEquals(Box::new(x), Box::new(y)) }
@@ -209,24 +321,44 @@ peg::parser!{
                 --
                 x:(@) __ "^" __ y:@ { Expression::Exponent(Box::new(x), Box::new(y)) }
                 --
-                "-" x:expression() { Expression::Negate(Box::new(x)) }
+                "-" !"-" x:expression() { Expression::Negate(Box::new(x)) }
                 "!" x:expression() { Expression::Not(Box::new(x)) }
                 "typeof" __ "(" __ x:expression() __ ")" { Expression::TypeOf(Box::new(x)) }
                 --
-                i:field_access() __ "++" { Expression::PostIncrement(i) }
-                i:field_access() __ "--" { Expression::PostDecrement(i) }
-                "++" __ i:field_access() { Expression::PreIncrement(i) }
-                "--" __ i:field_access() { Expression::PreDecrement(i) }
+                i:increment() { i }
                 --
                 "{" __ c:(constructor_field() ** ("," __)) __ ","? __ "}" { Expression::Constructor(c) }
                 n:number() { Expression::ConstNumber(n) }
                 s:string() { Expression::ConstString(s) }
                 ":" i:identifier() { Expression::ExternalFieldAccess(i) }
                 "(" __ e:expression() __ ")" { Expression::Bracket(Box::new(e)) }
-                n:identifier() __ "(" __ e:(e:expression() ** ("," __) { e }) __ ")" { Expression::Call(n, e) }
+                n:qualified_identifier() __ "(" __ e:call_arguments() __ ")" { Expression::Call(n, e) }
+                m:method_call() { Expression::MethodCall(m.0, m.1, m.2) }
+                i:field_access() __ "[" __ e:expression() __ "]" { Expression::Index(i, Box::new(e)) }
                 i:field_access() { Expression::FieldAccess(i) }
             }
 
+        rule call_arguments() -> Vec<Expression>
+            = a:(call_argument() ** ("," __))
+            { a }
+
+        rule call_argument() -> Expression
+            = n:identifier() __ ":" !":" __ e:expression()
+            { Expression::NamedArgument(n, Box::new(e)) }
+            / expression()
+
+        rule method_call() -> (Vec<String>, String, Vec<Expression>)
+            = p:field_access() __ "(" __ a:call_arguments() __ ")" {?
+                let mut p = p;
+                match p.len() {
+                    1 => Err("method call"),
+                    _ => {
+                        let m = p.pop().unwrap();
+                        Ok((p, m, a))
+                    }
+                }
+            }
+
         rule constructor_field() -> (String, Expression)
             = i:identifier() __ ":" __ e:expression()
             { (i, e) }
@@ -236,6 +368,10 @@ peg::parser!{
             { i.to_string() }
             / expected!("Identifier")
 
+        // Keywords must not be followed by a character which could continue an identifier
+        rule ident_char()
+            = ['A'..='Z' | 'a'..='z' | '0'..='9' | '_']
+
         rule path() -> String
             = p:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '\\' | '/' | ':' | '.' | '_' | ' ']+)
             { p.to_string().replace("\\", "/") }
@@ -244,8 +380,15 @@ peg::parser!{
 
 
         rule field() -> FieldDefinition
-            = n:identifier() __ ":" __ t:identifier()
-            { FieldDefinition { name: n, typename: TypeName { typename: t } } }
+            = n:identifier() __ ":" __ t:qualified_identifier() l:(__ "[" __ l:uint() __ "]" { l })?
+            {
+                // Array types keep their length in the type name, e.g. `number[4]`
+                let t = match l {
+                    Some(l) => format!("{}[{}]", t, l),
+                    None => t
+                };
+                FieldDefinition { name: n, typename: TypeName { typename: t } }
+            }
 
         rule string() -> String
             = "\"" s:$((!"\"" [_])*) "\""
@@ -261,7 +404,7 @@ peg::parser!{
             = "\r"? "\n"
 
         rule comment()
-            = "//" (!newline() [_])* newline()
+            = "//" (!newline() [_])* (newline() / ![_])
             / "/*" (!"*/" [_])* "*/"
 
         rule whitespace()
@@ -303,6 +446,29 @@ mod tests {
         assert_eq!(3, prog.imports.len());
         assert_eq!("file.y", prog.imports[0].path);
         assert_eq!("x/y/z.y", prog.imports[1].path);
-        assert_eq!("lib/number_parser.y", prog.imports[2].path);
+        assert_eq!("std:number_parser", prog.imports[2].path);
+    }
+
+    #[test]
+    fn identifiers_starting_with_keywords() {
+        let prog = y_parser::program("main { loop { break_count = 2; continued = 1; returned = 3; gotos = 4; break; }; }").unwrap();
+        let main = format!("{:?}", prog.main.unwrap());
+
+        assert!(main.contains("Assign([\"break_count\"]"));
+        assert!(main.contains("Assign([\"continued\"]"));
+        assert!(main.contains("Assign([\"returned\"]"));
+        assert!(main.contains("Assign([\"gotos\"]"));
+        assert!(main.contains("Break"));
+    }
+
+    #[test]
+    fn identifiers_starting_with_block_keywords() {
+        let prog = y_parser::program("main { format = 1; loopy = 2; whiled = 3; matcher = 4; }").unwrap();
+        let main = format!("{:?}", prog.main.unwrap());
+
+        assert!(main.contains("Assign([\"format\"]"));
+        assert!(main.contains("Assign([\"loopy\"]"));
+        assert!(main.contains("Assign([\"whiled\"]"));
+        assert!(main.contains("Assign([\"matcher\"]"));
     }
 }
\ No newline at end of file
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+
+use super::ast::{ Program, InnerStatement, OuterStatement, Expression, TypeName };
+use super::visit::{ visit_expr_mut, visit_stmt_mut, visit_stmt_exprs_mut };
+
+// Rename every reference to a named item (a callable, type, constant or enum) in a program. `rename` is called with each name and returns
+// the new name, or None to leave it unchanged. Fields declared inside a callable or `main` hide items with the same name.
+pub fn rename_references<F: FnMut(&str) -> Option<String>>(program: &mut Program, rename: &mut F) {
+    let none = HashSet::new();
+
+    for c in program.constants.iter_mut() {
+        rename_type(&mut c.field.typename, rename);
+        rename_expr(&mut c.value, &none, rename);
+    }
+
+    for e in program.enums.iter_mut() {
+        rename_name(&mut e.base, rename);
+        e.items.iter_mut().for_each(|i| rename_expr(&mut i.value, &none, rename));
+    }
+
+    for s in program.structs.iter_mut() {
+        s.fields.iter_mut().for_each(|f| rename_type(&mut f.typename, rename));
+    }
+
+    // The expression of a range refers to the value being checked by the name of the range
+    for r in program.ranges.iter_mut() {
+        rename_name(&mut r.base, rename);
+        let locals = vec![ r.name.clone() ].into_iter().collect();
+        rename_expr(&mut r.expression, &locals, rename);
+    }
+
+    for c in program.callables.iter_mut() {
+        let mut locals: HashSet<_> = c.parameters.iter().map(|p| p.field.name.clone()).collect();
+        c.statements.iter().for_each(|s| declared_inner(s, &mut locals));
+
+        for p in c.parameters.iter_mut() {
+            rename_type(&mut p.field.typename, rename);
+            if let Some(d) = &mut p.default {
+                rename_expr(d, &none, rename);
+            }
+        }
+        if let Some(r) = &mut c.return_type {
+            rename_name(r, rename);
+        }
+        c.statements.iter_mut().for_each(|s| rename_inner(s, &locals, rename));
+    }
+
+    if let Some(main) = &mut program.main {
+        let mut locals = HashSet::new();
+        main.statements.iter().for_each(|s| declared_outer(s, &mut locals));
+        main.statements.iter_mut().for_each(|s| rename_outer(s, &locals, rename));
+    }
+}
+
+fn rename_name<F: FnMut(&str) -> Option<String>>(name: &mut String, rename: &mut F) {
+    if let Some(n) = rename(name) {
+        *name = n;
+    }
+}
+
+// Array types keep their length in the type name, only the element type is renamed
+fn rename_type<F: FnMut(&str) -> Option<String>>(typename: &mut TypeName, rename: &mut F) {
+    match typename.array() {
+        Some((mut element, length)) => {
+            rename_name(&mut element.typename, rename);
+            typename.typename = format!("{}[{}]", element.typename, length);
+        },
+        None => rename_name(&mut typename.typename, rename)
+    }
+}
+
+fn rename_root<F: FnMut(&str) -> Option<String>>(path: &mut Vec<String>, locals: &HashSet<String>, rename: &mut F) {
+    if !locals.contains(&path[0]) {
+        rename_name(&mut path[0], rename);
+    }
+}
+
+fn rename_expr<F: FnMut(&str) -> Option<String>>(expr: &mut Expression, locals: &HashSet<String>, rename: &mut F) {
+    visit_expr_mut(expr, &mut |e| rename_expr_node(e, locals, rename));
+}
+
+fn rename_expr_node<F: FnMut(&str) -> Option<String>>(expr: &mut Expression, locals: &HashSet<String>, rename: &mut F) {
+    match expr {
+        Expression::Call(name, _) => rename_name(name, rename),
+        Expression::Is(_, typename) => rename_type(typename, rename),
+
+        Expression::FieldAccess(path) |
+        Expression::Index(path, _) |
+        Expression::MethodCall(path, _, _) |
+        Expression::PostIncrement(path) |
+        Expression::PostDecrement(path) |
+        Expression::PreIncrement(path) |
+        Expression::PreDecrement(path) => rename_root(path, locals, rename),
+
+        _ => {}
+    }
+}
+
+fn rename_inner<F: FnMut(&str) -> Option<String>>(stmt: &mut InnerStatement, locals: &HashSet<String>, rename: &mut F) {
+    visit_stmt_mut(stmt, &mut |s| match s {
+        InnerStatement::DeclareAssign(f, _) |
+        InnerStatement::DeclareConst(f, _) => rename_type(&mut f.typename, rename),
+        InnerStatement::Call(name, _) => rename_name(name, rename),
+
+        InnerStatement::Assign(path, _) |
+        InnerStatement::AssignIndex(path, _, _) |
+        InnerStatement::MethodCall(path, _, _) => rename_root(path, locals, rename),
+
+        _ => {}
+    });
+    visit_stmt_exprs_mut(stmt, &mut |e| rename_expr_node(e, locals, rename));
+}
+
+fn rename_outer<F: FnMut(&str) -> Option<String>>(stmt: &mut OuterStatement, locals: &HashSet<String>, rename: &mut F) {
+    match stmt {
+        OuterStatement::Loop(body) => body.iter_mut().for_each(|s| rename_outer(s, locals, rename)),
+        OuterStatement::While(condition, body) => {
+            rename_expr(condition, locals, rename);
+            body.iter_mut().for_each(|s| rename_outer(s, locals, rename));
+        },
+        OuterStatement::For(_, start, end, body) => {
+            rename_expr(start, locals, rename);
+            rename_expr(end, locals, rename);
+            body.iter_mut().for_each(|s| rename_outer(s, locals, rename));
+        },
+        OuterStatement::Line(body, _) => body.iter_mut().for_each(|s| rename_inner(s, locals, rename)),
+        OuterStatement::Inner(s) => rename_inner(s, locals, rename),
+        OuterStatement::Label(_) => {},
+    }
+}
+
+fn declared_inner(stmt: &InnerStatement, names: &mut HashSet<String>) {
+    visit_stmt_mut(&mut stmt.clone(), &mut |s| match s {
+        InnerStatement::DeclareAssign(f, _) |
+        InnerStatement::DeclareConst(f, _) => { names.insert(f.name.clone()); },
+        _ => {}
+    });
+}
+
+fn declared_outer(stmt: &OuterStatement, names: &mut HashSet<String>) {
+    match stmt {
+        OuterStatement::Loop(body) |
+        OuterStatement::While(_, body) => body.iter().for_each(|s| declared_outer(s, names)),
+        OuterStatement::For(name, _, _, body) => {
+            names.insert(name.clone());
+            body.iter().for_each(|s| declared_outer(s, names));
+        },
+        OuterStatement::Line(body, _) => body.iter().for_each(|s| declared_inner(s, names)),
+        OuterStatement::Inner(s) => declared_inner(s, names),
+        OuterStatement::Label(_) => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::error::{ CompilerError };
+    use super::super::parser::y_parser;
+    use super::*;
+
+    #[test]
+    fn rename_items_but_not_fields() {
+        let code = "
+            const k: number = 2;
+            def macro f(x: number, y: ns::t) { var k: number = x; g(k); }
+            main { var a: t[2] = f(k, k); a.b = k.c; }
+        ";
+        let mut program = y_parser::program(code).ok().unwrap();
+        rename_references(&mut program, &mut |n| match n {
+            "k" | "g" | "t" | "f" => Some(format!("m:{}", n)),
+            _ => None
+        });
+
+        let callable = format!("{:?}", program.callables[0]);
+        assert!(callable.contains("Call(\"m:g\", [FieldAccess([\"k\"])])"));
+        assert!(callable.contains("typename: \"ns:t\""));
+
+        let main = format!("{:?}", program.main.unwrap());
+        assert!(main.contains("typename: \"m:t[2]\""));
+        assert!(main.contains("Call(\"m:f\", [FieldAccess([\"m:k\"]), FieldAccess([\"m:k\"])])"));
+        assert!(main.contains("Assign([\"a\", \"b\"], FieldAccess([\"m:k\", \"c\"]))"));
+    }
+
+    #[test]
+    fn private_items_are_not_visible_to_importers() {
+        let library = || y_parser::program("def macro helper() { } pub def macro api() { helper(); }").ok().unwrap();
+
+        let importer = y_parser::program("use lib::api as run; main { run(); }").ok().unwrap().apply_uses();
+        assert!(importer.clone().combine(library(), Some("lib".to_string()), &importer.references()).is_ok());
+
+        let importer = y_parser::program("main { lib::helper(); }").ok().unwrap().apply_uses();
+        match importer.clone().combine(library(), Some("lib".to_string()), &importer.references()) {
+            Err(CompilerError::PrivateItem(name)) => assert_eq!("lib::helper", name),
+            _ => panic!("Expected private item error")
+        }
+    }
+
+    #[test]
+    fn namespaced_module_refers_to_its_own_items() {
+        let library = y_parser::program("
+            type struct inner { n: number }
+            pub type struct outer { i: inner }
+            def macro helper(x: inner) -> number { return x.n; }
+            pub def macro api(x: outer) -> number { return helper(x.i); }
+        ").ok().unwrap();
+        let importer = y_parser::program("main { var o: lib::outer = { i: { n: 1 } }; :a = lib::api(o); }").ok().unwrap();
+
+        let program = importer.clone().combine(library, Some("lib".to_string()), &importer.references()).ok().unwrap();
+        assert!(program.check_qualified_names().is_ok());
+        assert!(format!("{:?}", program.structs[1]).contains("typename: \"lib:inner\""));
+        assert!(format!("{:?}", program.callables[1]).contains("Call(\"lib:helper\""));
+
+        let unresolved = y_parser::program("main { :a = lib::missing(); }").ok().unwrap();
+        match unresolved.check_qualified_names() {
+            Err(CompilerError::UnresolvedName(name)) => assert_eq!("lib::missing", name),
+            _ => panic!("Expected unresolved name")
+        }
+    }
+}
\ No newline at end of file
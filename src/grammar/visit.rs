@@ -0,0 +1,98 @@
+use super::ast::{ InnerStatement, Expression };
+
+// Call `f` on every expression node, parents are visited before their children
+pub fn visit_expr_mut<F: FnMut(&mut Expression)>(expr: &mut Expression, f: &mut F) {
+    f(expr);
+    match expr {
+        Expression::CompilePanic(_, _) => {},
+        Expression::ConstNumber(_) => {},
+        Expression::ConstString(_) => {},
+        Expression::FieldAccess(_) => {},
+        Expression::ExternalFieldAccess(_) => {},
+        Expression::PostIncrement(_) => {},
+        Expression::PostDecrement(_) => {},
+        Expression::PreIncrement(_) => {},
+        Expression::PreDecrement(_) => {},
+        Expression::ExternalPostIncrement(_) => {},
+        Expression::ExternalPostDecrement(_) => {},
+        Expression::ExternalPreIncrement(_) => {},
+        Expression::ExternalPreDecrement(_) => {},
+
+        Expression::Index(_, x) => visit_expr_mut(x, f),
+        Expression::Call(_, args) |
+        Expression::MethodCall(_, _, args) => args.iter_mut().for_each(|a| visit_expr_mut(a, f)),
+        Expression::Constructor(fields) => fields.iter_mut().for_each(|(_, v)| visit_expr_mut(v, f)),
+
+        Expression::Negate(x) |
+        Expression::Not(x) |
+        Expression::Is(x, _) |
+        Expression::NamedArgument(_, x) |
+        Expression::TypeOf(x) |
+        Expression::Bracket(x) => visit_expr_mut(x, f),
+
+        Expression::Add(x, y) |
+        Expression::Subtract(x, y) |
+        Expression::Multiply(x, y) |
+        Expression::Divide(x, y) |
+        Expression::Modulus(x, y) |
+        Expression::Exponent(x, y) |
+        Expression::And(x, y) |
+        Expression::Or(x, y) |
+        Expression::GreaterThan(x, y) |
+        Expression::LessThan(x, y) |
+        Expression::GreaterThanOrEq(x, y) |
+        Expression::LessThanOrEq(x, y) |
+        Expression::Equals(x, y) |
+        Expression::NotEquals(x, y) => { visit_expr_mut(x, f); visit_expr_mut(y, f); },
+
+        Expression::Ternary(c, x, y) => { visit_expr_mut(c, f); visit_expr_mut(x, f); visit_expr_mut(y, f); },
+    }
+}
+
+// Call `f` on every statement, statements are visited before the statements nested inside them
+pub fn visit_stmt_mut<F: FnMut(&mut InnerStatement)>(stmt: &mut InnerStatement, f: &mut F) {
+    f(stmt);
+    match stmt {
+        InnerStatement::If(_, pass, fail) => pass.iter_mut().chain(fail.iter_mut()).for_each(|s| visit_stmt_mut(s, f)),
+        InnerStatement::Match(_, arms, default) => arms.iter_mut().flat_map(|(_, a)| a.iter_mut()).chain(default.iter_mut()).for_each(|s| visit_stmt_mut(s, f)),
+        _ => {}
+    }
+}
+
+// Call `f` on every expression directly or indirectly contained in a statement
+pub fn visit_stmt_exprs_mut<F: FnMut(&mut Expression)>(stmt: &mut InnerStatement, f: &mut F) {
+    match stmt {
+        InnerStatement::CompilePanic(_, _) => {},
+        InnerStatement::Emit(_) => {},
+        InnerStatement::Goto(_) => {},
+        InnerStatement::Break => {},
+        InnerStatement::Continue => {},
+
+        InnerStatement::Call(_, args) |
+        InnerStatement::MethodCall(_, _, args) => args.iter_mut().for_each(|a| visit_expr_mut(a, f)),
+        InnerStatement::If(condition, pass, fail) => {
+            visit_expr_mut(condition, f);
+            pass.iter_mut().chain(fail.iter_mut()).for_each(|s| visit_stmt_exprs_mut(s, f));
+        },
+        InnerStatement::AssignIndex(_, index, value) => {
+            visit_expr_mut(index, f);
+            visit_expr_mut(value, f);
+        },
+        InnerStatement::Match(value, arms, default) => {
+            visit_expr_mut(value, f);
+            for (pattern, body) in arms.iter_mut() {
+                visit_expr_mut(pattern, f);
+                body.iter_mut().for_each(|s| visit_stmt_exprs_mut(s, f));
+            }
+            default.iter_mut().for_each(|s| visit_stmt_exprs_mut(s, f));
+        },
+
+        InnerStatement::Assign(_, value) |
+        InnerStatement::DeclareAssign(_, value) |
+        InnerStatement::DeclareConst(_, value) |
+        InnerStatement::ExternalAssign(_, value) |
+        InnerStatement::ExpressionWrapper(value) |
+        InnerStatement::Return(value) |
+        InnerStatement::GotoOffset(_, value) => visit_expr_mut(value, f),
+    }
+}
\ No newline at end of file
@@ -6,7 +6,6 @@ use std::fs;
 use std::path::PathBuf;
 use std::time::Instant;
 
-use rayon::prelude::*;
 use peg_runtime;
 use colored::Colorize;
 
@@ -14,9 +13,10 @@ mod grammar;
 mod compiler;
 mod error;
 mod yolol;
+mod stdlib;
 
 use error::CompilerError;
-use compiler::BuildConfig;
+use compiler::{ BuildConfig, Emit };
 
 fn main() {
 
@@ -49,6 +49,29 @@ fn main() {
         Err(CompilerError::StaticTypeError(cause, expr)) => println!("{}", format!("Static error caused by {} in expression `{:?}`", cause, expr).red()),
         Err(CompilerError::ConstructorExpression()) => println!("{}", format!("Must assign constructor expression to a field").red()),
         Err(CompilerError::FieldConstructorAssignment(typ, initialisers)) => println!("{}", format!("Cannot assign a field of type `{}` from constructor expression `{:?}`", typ, initialisers).red()),
+        Err(CompilerError::ConstantDivisionByZero(expr)) => println!("{}", format!("Division by zero in constant expression `{:?}`", expr).red()),
+        Err(CompilerError::LoopControlOutsideLoop(kw)) => println!("{}", format!("`{}` used outside of a loop", kw).red()),
+        Err(CompilerError::StringLoopNotAllowed(name)) => println!("{}", format!("`{}` needs a loop, so it cannot be used inside a `line`, a macro or the branches of an `if` or `match`", name).red()),
+        Err(CompilerError::NotAnArray(path)) => println!("{}", format!("Cannot index `{}`, it is not an array", path.join(".")).red()),
+        Err(CompilerError::IndexOutOfBounds(path, index, length)) => println!("{}", format!("Index {} is out of bounds for array `{}` of length {}", index, path.join("."), length).red()),
+        Err(CompilerError::TypeParameterNotInferred(name, param)) => println!("{}", format!("Cannot find type parameter `{}` of `{}` from the arguments", param, name).red()),
+        Err(CompilerError::NoReturnValue(name)) => println!("{}", format!("`{}` does not return a value", name).red()),
+        Err(CompilerError::MissingReturn(name)) => println!("{}", format!("`{}` must end with a `return` statement", name).red()),
+        Err(CompilerError::MisplacedReturn(name)) => println!("{}", format!("`{}` has no return type, it cannot `return` a value", name).red()),
+        Err(CompilerError::DuplicateCallableDefinition(signature)) => println!("{}", format!("`{}` has already been defined", signature).red()),
+        Err(CompilerError::AmbiguousCall(name, candidates)) => println!("{}", format!("Call to `{}` is ambiguous, it could call: {}", name, candidates.join(", ")).red()),
+        Err(CompilerError::NoMatchingOverload(name, candidates)) => println!("{}", format!("No overload of `{}` matches the arguments, candidates are: {}", name, candidates.join(", ")).red()),
+        Err(CompilerError::UnknownNamedArgument(name, arg)) => println!("{}", format!("`{}` does not have a parameter named `{}`", name, arg).red()),
+        Err(CompilerError::DuplicateArgument(name, arg)) => println!("{}", format!("Parameter `{}` of `{}` was passed more than once", arg, name).red()),
+        Err(CompilerError::ConstEvaluationFailed(name, reason)) => println!("{}", format!("Cannot evaluate const macro `{}` at compile time: {}", name, reason).red()),
+        Err(CompilerError::RecursiveCall(cycle)) => println!("{}", format!("Recursive call `{}`, only a `proc` with a `max_depth` attribute may be recursive", cycle.join(" -> ")).red()),
+        Err(CompilerError::PrivateItem(name)) => println!("{}", format!("`{}` is private to the module which defines it, mark it `pub` to use it from other modules", name).red()),
+        Err(CompilerError::UnresolvedName(name)) => println!("{}", format!("Cannot find `{}`", name).red()),
+        Err(CompilerError::ImportCycle(chain)) => println!("{}", format!("Import cycle `{}`", chain.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" -> ")).red()),
+        Err(CompilerError::LibraryNotFound(path, searched)) => println!("{}", format!("Cannot find library `{}` in library paths ({}), add directories with `--lib-path` or `YC_PATH`", path, searched.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")).red()),
+        Err(CompilerError::StdModuleNotFound(path)) => println!("{}", format!("`{}` is not a standard library module", path).red()),
+        Err(CompilerError::RecursiveConstant(chain)) => println!("{}", format!("Constant `{}` is defined in terms of itself", chain.join(" -> ")).red()),
+        Err(CompilerError::RecursionTooDeep(name, depth)) => println!("{}", format!("`{}` reached its `max_depth` of {} inside an expression, where it must return a value", name, depth).red()),
     }
 }
 
@@ -56,7 +79,10 @@ fn compile(input: &PathBuf, output: &PathBuf, config: &BuildConfig) -> Result<()
 
     println!("{} `{}`", "#".bright_blue(), input.display());
     let now = Instant::now();
-    let ast = try_parse(&input, 0)?;
+    let (ast, imports) = grammar::modules::parse_program(&input, &config.lib_paths)?;
+    for import in imports.iter() {
+        println!("{}{}{} `{}` (from `{}`)", "|-".blue(), "-".repeat(import.depth).bright_blue(), ">".blue(), import.to.display(), import.from.display());
+    }
     println!("# {}ms", now.elapsed().as_millis());
 
     println!("");
@@ -90,43 +116,39 @@ fn compile(input: &PathBuf, output: &PathBuf, config: &BuildConfig) -> Result<()
     println!("| | {} blocks", blocks.blocks.len());
     let blocks = do_with_timing("Copy Macros Inline", || blocks.inline_macros(config))?;
     let blocks = do_with_timing("Materialise Struct Fields", || blocks.materialise_structs(config))?;
+    let blocks = do_with_timing("Materialise Array Fields", || blocks.materialise_arrays())?;
     let blocks = do_with_timing("Blocks To Yolol AST", || blocks.covert_yolol_blocks())?;
     println!("| | {} type mappings", blocks.types.len());
     println!("| | {} const expr", blocks.consts.len());
+    let blocks = do_with_timing("Fold Constants", || blocks.fold_constants())?;
+    let blocks = do_with_timing("Peephole Optimise", || blocks.peephole_optimise())?;
+    let blocks = do_with_timing("Eliminate Dead Code", || blocks.eliminate_dead_code())?;
+    let (blocks, source_map) = do_with_timing("Minify Names", || blocks.minify_names())?;
+    println!("| | {} names minified", source_map.len());
+    let blocks = do_with_timing("Compact Loops", || blocks.compact_loops(config))?;
+
+    if let Some(path) = &config.source_map {
+        let mut names = source_map.iter().collect::<Vec<_>>();
+        names.sort();
+        let map = names.iter().map(|(short, original)| format!("{} = {}\n", short, original)).collect::<String>();
+        fs::write(path, map).map_err(|x| CompilerError::IO(path.clone(), x))?;
+    }
 
     //todo: split blocks into smaller blocks (which can fit on a single line)
     //todo: layout lines in order
 
-    fs::write(output, format!("{:#?}", blocks)).map_err(|x| CompilerError::IO(output.clone(), x))?;
+    let result = match config.emit {
+        Emit::Ast => format!("{:#?}", blocks),
+        Emit::Cfg => do_with_timing("Build Control Flow Graph", || blocks.build_control_flow_graph())?.to_graphviz(),
+    };
+
+    fs::write(output, result).map_err(|x| CompilerError::IO(output.clone(), x))?;
 
     println!("# {}ms", now.elapsed().as_millis());
 
     Ok(())
 }
 
-fn try_parse(path: &PathBuf, depth: usize) -> Result<grammar::ast::Program, CompilerError> {
-
-    // Parse this file
-    //let now = Instant::now();
-    let code = fs::read_to_string(path).map_err(|x| CompilerError::IO(path.clone(), x))?;
-    let ast = grammar::parser::y_parser::program(&code).map_err(|x| CompilerError::Parse(path.clone(), code, x))?;
-    //println!("# {}: {}us", path.display(), now.elapsed().as_micros());
-
-    // Parse imported files and merge into this ast
-    ast.imports.clone()
-        .par_iter()
-        .map(|x| {
-            let parent = path.parent().unwrap();
-            let p = [parent, &PathBuf::from(&x.path)].iter().collect::<PathBuf>();
-            println!("{}{}{} `{}` (from `{}`)", "|-".blue(), "-".repeat(depth).bright_blue(), ">".blue(), p.display(), path.display());
-            let ast = try_parse(&p, depth + 1)?;
-            return Ok((ast, x.namespace.clone()));
-        })
-        .collect::<Vec<_>>()
-        .into_iter()
-        .try_fold(ast, |a, b| { let b = b?; Ok(a.combine(b.0, b.1).clear_imports()) })
-}
-
 fn parser_error_handler(path: &PathBuf, code: &str, err: peg_runtime::error::ParseError<peg_runtime::str::LineCol>) {
 
     fn print_lines(lines: &Vec<&str>) {
@@ -1,11 +1,12 @@
-def macro num2bool(num: number) -> bool {
+pub def macro num2bool(num: number) -> bool {
     return num != 0;
 }
 
-def macro num2str(num: number) -> string {
+pub def macro num2str(num: number) -> string {
     return num + "";
 }
 
+// The `any2*` casts need `emit`, they are not exported until it is implemented
 def macro any2num(item: any) -> number {
     var r:number = 0;
     emit { "r = item" };
@@ -22,10 +23,4 @@ def macro any2bool(item: any) -> bool {
     var r:bool = "";
     emit { "r = item" };
     return r;
-}
-
-main {
-    var a:number = 7;
-    var b:number = 10;
-    :out = a + b;
 }
\ No newline at end of file
@@ -0,0 +1,9 @@
+pub const true:number = 1;
+pub const false:number = 0;
+
+pub const pi:number = 3.141;
+pub const tau:number = 6.282;
+pub const e:number = 2.718;
+
+pub const max_num:number = 9223372036854775.807;
+pub const min_num:number = -9223372036854775.808;
\ No newline at end of file
@@ -0,0 +1,3 @@
+// `abs`, `sqrt`, `sin`, `cos`, `tan`, `asin`, `acos` and `atan` are built in to the compiler and compile directly to the Yolol operators.
+// The string helpers `len`, `last_char`, `pop_back`, `contains` and `parse_number` are also built in.
+// This module is kept so existing imports continue to work.
\ No newline at end of file
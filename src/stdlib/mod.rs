@@ -0,0 +1,130 @@
+// The standard library is embedded in the compiler, so it is always the version the compiler was built with.
+// Modules are imported with `import "std:name";`.
+pub const PREFIX: &str = "std:";
+
+const MODULES: &[(&str, &str)] = &[
+    ("casts", include_str!("casts.y")),
+    ("constants", include_str!("constants.y")),
+    ("intrinsics", include_str!("intrinsics.y")),
+    ("number_parser", include_str!("number_parser.y")),
+    ("numbers", include_str!("numbers.y")),
+    ("yunit", include_str!("yunit.y")),
+];
+
+// Get the code of a standard library module from the path it is imported with (e.g. `std:casts`)
+pub fn module_source(path: &str) -> Option<&'static str> {
+    if !path.starts_with(PREFIX) {
+        return None;
+    }
+
+    let name = &path[PREFIX.len()..];
+    return MODULES.iter().find(|(n, _)| *n == name).map(|(_, code)| *code);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use crate::compiler::{ BuildConfig };
+    use crate::error::{ CompilerError };
+    use crate::grammar::modules::{ parse_program, TestFiles };
+    use crate::grammar::parser::y_parser;
+    use crate::yolol::interpret::{ compile_and_run, Value };
+    use super::*;
+
+    fn run_program(code: &str) -> Result<Value, CompilerError> {
+        let files = TestFiles::new("stdlib", &[ ("main.y", code) ]);
+        return Ok(compile_and_run(parse_program(&files.dir.join("main.y"), &[])?.0, HashMap::new(), 100)?.remove(":out").unwrap());
+    }
+
+    #[test]
+    fn every_module_parses() {
+        for (name, code) in MODULES.iter() {
+            let program = y_parser::program(code).ok().expect(name);
+            assert!(program.main.is_none());
+            assert!(program.imports.iter().all(|i| module_source(&i.path).is_some()));
+        }
+        assert!(module_source("std:missing").is_none());
+        assert!(module_source("casts").is_none());
+    }
+
+    #[test]
+    fn constants_and_casts() {
+        let code = "import \"std:constants\"; import \"std:casts\"; main { :out = num2str(tau - pi) + \"!\"; }";
+        assert_eq!(Value::from("3.141!"), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn number_parser() {
+        let code = "
+            import \"std:number_parser\";
+            main {
+                var d: string = \"42\"; var x: string = \"1F\";
+                var a: number = 0; var i: number = 0;
+                var b: number = 0; var j: number = 0;
+                parse_base10_char(d, a, i); parse_base10_char(d, a, i);
+                parse_base16_char(x, b, j); parse_base16_char(x, b, j);
+                :out = a + b;
+            }
+        ";
+        assert_eq!(Value::from(73), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn number_ranges() {
+        let files = TestFiles::new("stdlib", &[ ("main.y", "import \"std:numbers\"; main { }") ]);
+        let program = parse_program(&files.dir.join("main.y"), &[]).ok().unwrap().0;
+        let ranges: Vec<_> = program.ranges.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(vec![ "positive", "positive_or_zero", "negative", "negative_or_zero", "integer", "natural", "square" ], ranges);
+    }
+
+    #[test]
+    fn casts() {
+        let code = "import \"std:casts\"; main { :out = num2bool(3) + num2bool(0) * 10 + num2str(2); }";
+        assert_eq!(Value::from("12"), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn intrinsics() {
+        let code = "import \"std:intrinsics\"; main { :out = sqrt(16) + abs(-2); }";
+        assert_eq!(Value::from(6), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn yunit_passing_asserts() {
+        let code = "
+            import \"std:yunit\";
+            main {
+                :assert_fail_msg = \"none\";
+                assert(1 < 2, \"a\"); assert_eq(1, 1, \"b\"); assert_neq(\"x\", \"y\", \"c\");
+                :out = :assert_fail_msg;
+            }
+        ";
+        assert_eq!(Value::from("none"), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn yunit_failing_assert() {
+        let code = "import \"std:yunit\"; main { assert_eq(1, 2, \"not equal\"); :out = :assert_fail_msg; }";
+        assert_eq!(Value::from("not equal"), run_program(code).ok().unwrap());
+    }
+
+    #[test]
+    fn yunit_requires_test_config() {
+        let files = TestFiles::new("stdlib", &[ ("main.y", "import \"std:yunit\"; main { assert(1, \"x\"); }") ]);
+        let program = parse_program(&files.dir.join("main.y"), &[]).ok().unwrap().0;
+        match program.build_blocks(&BuildConfig::for_tests()).and_then(|b| b.inline_macros(&BuildConfig::for_tests())) {
+            Err(CompilerError::CallableNotFound(name)) => assert_eq!("assert", name),
+            _ => panic!("Expected `assert` to be left out")
+        }
+    }
+
+    #[test]
+    fn unknown_module() {
+        match run_program("import \"std:missing\"; main { }") {
+            Err(CompilerError::StdModuleNotFound(path)) => assert_eq!("std:missing", path),
+            _ => panic!("Expected unknown module")
+        }
+    }
+}
\ No newline at end of file
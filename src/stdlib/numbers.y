@@ -0,0 +1,11 @@
+import "std:intrinsics";
+
+pub type range<number> positive -> positive > 0;
+pub type range<number> positive_or_zero -> positive >= 0;
+pub type range<number> negative -> negative < 0;
+pub type range<number> negative_or_zero -> negative <= 0;
+
+pub type range<number> integer -> integer / 1000 * 1000 == integer;
+pub type range<integer> natural -> natural > 0;
+
+pub type range<number> square -> sqrt(square) is integer;
\ No newline at end of file
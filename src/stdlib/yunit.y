@@ -0,0 +1,15 @@
+import "std:constants";
+
+[cfg("test")] pub def macro assert(a:bool, msg:string) {
+    if (!a) {
+        :assert_fail_msg = msg;
+    };
+}
+
+[cfg("test")] pub def macro assert_eq<T>(a:T, b:T, msg:string) {
+    assert(a == b, msg);
+}
+
+[cfg("test")] pub def macro assert_neq<T>(a:T, b:T, msg:string) {
+    assert(a != b, msg);
+}
\ No newline at end of file
@@ -0,0 +1,124 @@
+use std::fmt::{ Display, Formatter, Result };
+
+use super::ast::{ Statement, StatementList, Expression, Identifier, Op };
+
+impl Display for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if self.external {
+            write!(f, ":{}", self.name)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Op::Add => write!(f, "+"),
+            Op::Subtract => write!(f, "-"),
+            Op::Multiply => write!(f, "*"),
+            Op::Divide => write!(f, "/"),
+            Op::Modulo => write!(f, "%"),
+            Op::Exponent => write!(f, "^"),
+        }
+    }
+}
+
+impl Display for StatementList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let stmts = self.statements.iter().map(|s| s.to_string()).filter(|s| s.len() > 0).collect::<Vec<_>>();
+        write!(f, "{}", stmts.join(" "))
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Statement::Assignment(id, value) => write!(f, "{}={}", id, value),
+            Statement::CompoundAssignment(id, op, value) => write!(f, "{}{}={}", id, op, value),
+            Statement::Empty() => Ok(()),
+            Statement::ExpressionWrapper(expr) => write!(f, "{}", expr),
+            Statement::Goto(expr) => write!(f, "goto {}", expr),
+            Statement::GotoLabel(label) => write!(f, "goto {}", label),
+            Statement::If(condition, pass, fail) => {
+                if fail.statements.len() == 0 {
+                    write!(f, "if {} then {} end", condition, pass)
+                } else {
+                    write!(f, "if {} then {} else {} end", condition, pass, fail)
+                }
+            }
+        }
+    }
+}
+
+// Write a symbolic binary operator with no whitespace, unless that would merge with an adjacent `+` or `-` into an increment/decrement
+fn binary(f: &mut Formatter<'_>, x: &Expression, op: &str, y: &Expression) -> Result {
+    let x = x.to_string();
+    let y = y.to_string();
+
+    let sign = |c: Option<char>| c == Some('+') || c == Some('-');
+    let left = if sign(x.chars().last()) && sign(op.chars().next()) { " " } else { "" };
+    let right = if sign(op.chars().last()) && sign(y.chars().next()) { " " } else { "" };
+
+    write!(f, "{}{}{}{}{}", x, left, op, right, y)
+}
+
+// Write a keyword operator, a space is only required if the operand does not start with a bracket
+fn keyword(f: &mut Formatter<'_>, word: &str, x: &Expression) -> Result {
+    let x = x.to_string();
+    if x.starts_with("(") {
+        write!(f, "{}{}", word, x)
+    } else {
+        write!(f, "{} {}", word, x)
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Expression::ConstantNumber(n) => write!(f, "{}", n),
+            Expression::ConstantString(s) => write!(f, "\"{}\"", s),
+            Expression::VariableAccess(id) => write!(f, "{}", id),
+
+            Expression::ACos(x) => keyword(f, "acos", x),
+            Expression::ASin(x) => keyword(f, "asin", x),
+            Expression::ATan(x) => keyword(f, "atan", x),
+            Expression::Sqrt(x) => keyword(f, "sqrt", x),
+            Expression::Cosine(x) => keyword(f, "cos", x),
+            Expression::Sine(x) => keyword(f, "sin", x),
+            Expression::Tangent(x) => keyword(f, "tan", x),
+            Expression::Abs(x) => keyword(f, "abs", x),
+            Expression::Not(x) => keyword(f, "not", x),
+            Expression::Bracket(x) => write!(f, "({})", x),
+            Expression::Negate(x) => {
+                let x = x.to_string();
+                if x.starts_with("-") {
+                    write!(f, "- {}", x)
+                } else {
+                    write!(f, "-{}", x)
+                }
+            },
+
+            Expression::PostDecrement(id) => write!(f, "{}--", id),
+            Expression::PostIncrement(id) => write!(f, "{}++", id),
+            Expression::PreDecrement(id) => write!(f, "--{}", id),
+            Expression::PreIncrement(id) => write!(f, "++{}", id),
+
+            Expression::Add(x, y) => binary(f, x, "+", y),
+            Expression::Subtract(x, y) => binary(f, x, "-", y),
+            Expression::Multiply(x, y) => binary(f, x, "*", y),
+            Expression::Divide(x, y) => binary(f, x, "/", y),
+            Expression::Modulus(x, y) => binary(f, x, "%", y),
+            Expression::Exponent(x, y) => binary(f, x, "^", y),
+            Expression::Equal(x, y) => binary(f, x, "==", y),
+            Expression::NotEqual(x, y) => binary(f, x, "!=", y),
+            Expression::GreaterThan(x, y) => binary(f, x, ">", y),
+            Expression::GreaterThanOrEq(x, y) => binary(f, x, ">=", y),
+            Expression::LessThan(x, y) => binary(f, x, "<", y),
+            Expression::LessThanOrEq(x, y) => binary(f, x, "<=", y),
+            Expression::And(x, y) => binary(f, x, " and ", y),
+            Expression::Or(x, y) => binary(f, x, " or ", y),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use yolol_number::prelude::*;
+
+use crate::compiler::{ BuildConfig };
+use crate::compiler::fields::{ goto_label_field };
+use crate::compiler::stages::yolol_blocks::{ YololBlock };
+use crate::error::{ CompilerError };
+use crate::grammar::ast::{ Program };
+use super::ast::{ Statement, Expression, Identifier, Op };
+pub use super::value::{ Value };
+
+// A minimal Yolol interpreter, used to check that compiled code behaves correctly
+
+// A runtime error stops execution of the current line
+struct RuntimeError;
+
+// Fields are keyed by name, external fields have a `:` prefix
+pub type State = HashMap<String, Value>;
+
+fn key(id: &Identifier) -> String {
+    if id.external {
+        return format!(":{}", id.name);
+    } else {
+        return id.name.clone();
+    }
+}
+
+// Run the lines until execution runs off the end of the last line, goto jumps to a 1 based line number
+pub fn run(lines: &[Vec<Statement>], mut state: State, max_steps: usize) -> State {
+    let mut pc = 0;
+    let mut steps = 0;
+
+    while pc < lines.len() {
+        steps += 1;
+        if steps > max_steps {
+            panic!("Program did not finish within {} lines (a5f0c7e4-1d0b-4f3e-8d6b-2c9f4e7a1b30)", max_steps);
+        }
+
+        pc = match exec_stmts(&lines[pc], &mut state) {
+            Ok(Some(line)) => line.max(1).min(lines.len()) - 1,
+            Ok(None) | Err(RuntimeError) => pc + 1
+        };
+    }
+
+    return state;
+}
+
+// Compile a program with the same stages as the compiler and run it. Each block is run as a line, the goto label field of each
+// block is set to the number of its line. Internal names are minified, so only external fields can be checked in the final state.
+// The `test` config is enabled, so `[cfg("test")]` callables can be used.
+pub fn compile_and_run(program: Program, mut state: State, max_steps: usize) -> Result<State, CompilerError> {
+    let mut config = BuildConfig::for_tests();
+    config.configs.push("test".to_string());
+    let blocks = program
+        .build_blocks(&config)?
+        .inline_macros(&config)?
+        .materialise_structs(&config)?
+        .materialise_arrays()?
+        .covert_yolol_blocks()?
+        .fold_constants()?
+        .peephole_optimise()?
+        .eliminate_dead_code()?
+        .minify_names()?
+        .0
+        .compact_loops(&config)?;
+
+    let mut lines = Vec::new();
+    for block in blocks.blocks.into_iter() {
+        let (label, stmts) = match block {
+            YololBlock::Statements(label, stmts) => (label, stmts),
+            YololBlock::Line(label, stmts) => (label, stmts),
+        };
+        if let Some(label) = label {
+            state.insert(goto_label_field(&label), Value::from(lines.len() as i32 + 1));
+        }
+        lines.push(stmts);
+    }
+
+    return Ok(run(&lines, state, max_steps));
+}
+
+// Execute statements, returning the target line if a goto was executed
+fn exec_stmts(stmts: &[Statement], state: &mut State) -> Result<Option<usize>, RuntimeError> {
+    for stmt in stmts {
+        if let Some(line) = exec_stmt(stmt, state)? {
+            return Ok(Some(line));
+        }
+    }
+    return Ok(None);
+}
+
+fn exec_stmt(stmt: &Statement, state: &mut State) -> Result<Option<usize>, RuntimeError> {
+    match stmt {
+        Statement::Empty() => {},
+        Statement::ExpressionWrapper(expr) => { eval(expr, state)?; },
+        Statement::Assignment(id, value) => {
+            let value = eval(value, state)?;
+            state.insert(key(id), value);
+        },
+        Statement::CompoundAssignment(id, op, value) => {
+            let current = read(id, state);
+            let value = eval(value, state)?;
+            let value = match op {
+                Op::Add => current.add(value),
+                Op::Subtract => current.subtract(value),
+                Op::Multiply => numeric(current, value, |a, b| Ok(a * b))?,
+                Op::Divide => numeric(current, value, divide)?,
+                Op::Modulo => numeric(current, value, modulus)?,
+                Op::Exponent => numeric(current, value, |a, b| Ok(a.pow(b)))?,
+            };
+            state.insert(key(id), value);
+        },
+        Statement::If(condition, pass, fail) => {
+            let branch = if truthy(eval(condition, state)?)? { pass } else { fail };
+            return exec_stmts(&branch.statements, state);
+        },
+        Statement::Goto(expr) => {
+            return match eval(expr, state)? {
+                Value::Num(n) => Ok(Some(n.floor().float_value().max(1.0) as usize)),
+                Value::Str(_) => Err(RuntimeError)
+            };
+        },
+        Statement::GotoLabel(label) => panic!("Cannot run goto label `{}` (3c41e9a2-8b7d-4f06-b5e1-6d2a9f8c0e47)", label),
+    }
+
+    return Ok(None);
+}
+
+fn read(id: &Identifier, state: &State) -> Value {
+    return state.get(&key(id)).cloned().unwrap_or(Value::Num(YololNumber::zero()));
+}
+
+fn truthy(v: Value) -> Result<bool, RuntimeError> {
+    return v.truthy().ok_or(RuntimeError);
+}
+
+fn divide(a: YololNumber, b: YololNumber) -> Result<YololNumber, RuntimeError> {
+    if b == YololNumber::zero() { Err(RuntimeError) } else { Ok(a / b) }
+}
+
+fn modulus(a: YololNumber, b: YololNumber) -> Result<YololNumber, RuntimeError> {
+    if b == YololNumber::zero() { Err(RuntimeError) } else { Ok(a % b) }
+}
+
+fn numeric<F>(a: Value, b: Value, f: F) -> Result<Value, RuntimeError>
+    where F: FnOnce(YololNumber, YololNumber) -> Result<YololNumber, RuntimeError>
+{
+    match (a, b) {
+        (Value::Num(a), Value::Num(b)) => Ok(Value::Num(f(a, b)?)),
+        _ => Err(RuntimeError)
+    }
+}
+
+// Incrementing a string appends a space, decrementing removes the last character
+fn step(id: &Identifier, state: &mut State, increment: bool) -> Result<(Value, Value), RuntimeError> {
+    let old = read(id, state);
+    let new = match (&old, increment) {
+        (Value::Num(n), true) => Value::Num(*n + YololNumber::one()),
+        (Value::Num(n), false) => Value::Num(*n - YololNumber::one()),
+        (Value::Str(s), true) => Value::Str(format!("{} ", s)),
+        (Value::Str(s), false) => {
+            let mut s = s.clone();
+            s.pop().ok_or(RuntimeError)?;
+            Value::Str(s)
+        }
+    };
+    state.insert(key(id), new.clone());
+    return Ok((old, new));
+}
+
+fn unary(x: &Expression, state: &mut State, f: fn(YololNumber) -> YololNumber) -> Result<Value, RuntimeError> {
+    match eval(x, state)? {
+        Value::Num(n) => Ok(Value::Num(f(n))),
+        Value::Str(_) => Err(RuntimeError)
+    }
+}
+
+fn eval(expr: &Expression, state: &mut State) -> Result<Value, RuntimeError> {
+    Ok(match expr {
+        Expression::ConstantNumber(n) => Value::Num(*n),
+        Expression::ConstantString(s) => Value::Str(s.clone()),
+        Expression::VariableAccess(id) => read(id, state),
+        Expression::Bracket(x) => eval(x, state)?,
+
+        Expression::ACos(x) => unary(x, state, |n| n.acos())?,
+        Expression::ASin(x) => unary(x, state, |n| n.asin())?,
+        Expression::ATan(x) => unary(x, state, |n| n.atan())?,
+        Expression::Sqrt(x) => unary(x, state, |n| n.sqrt())?,
+        Expression::Cosine(x) => unary(x, state, |n| n.cos())?,
+        Expression::Sine(x) => unary(x, state, |n| n.sin())?,
+        Expression::Tangent(x) => unary(x, state, |n| n.tan())?,
+        Expression::Abs(x) => unary(x, state, |n| n.abs())?,
+        Expression::Negate(x) => unary(x, state, |n| -n)?,
+        Expression::Not(x) => Value::from_bool(!truthy(eval(x, state)?)?),
+
+        Expression::PostIncrement(id) => step(id, state, true)?.0,
+        Expression::PostDecrement(id) => step(id, state, false)?.0,
+        Expression::PreIncrement(id) => step(id, state, true)?.1,
+        Expression::PreDecrement(id) => step(id, state, false)?.1,
+
+        Expression::Add(x, y) => { let a = eval(x, state)?; a.add(eval(y, state)?) },
+        Expression::Subtract(x, y) => { let a = eval(x, state)?; a.subtract(eval(y, state)?) },
+        Expression::Multiply(x, y) => { let a = eval(x, state)?; numeric(a, eval(y, state)?, |a, b| Ok(a * b))? },
+        Expression::Divide(x, y) => { let a = eval(x, state)?; numeric(a, eval(y, state)?, divide)? },
+        Expression::Modulus(x, y) => { let a = eval(x, state)?; numeric(a, eval(y, state)?, modulus)? },
+        Expression::Exponent(x, y) => { let a = eval(x, state)?; numeric(a, eval(y, state)?, |a, b| Ok(a.pow(b)))? },
+
+        Expression::And(x, y) => { let a = truthy(eval(x, state)?)?; Value::from_bool(truthy(eval(y, state)?)? && a) },
+        Expression::Or(x, y) => { let a = truthy(eval(x, state)?)?; Value::from_bool(truthy(eval(y, state)?)? || a) },
+
+        Expression::Equal(x, y) => { let a = eval(x, state)?; Value::from_bool(a.compare(&eval(y, state)?).is_eq()) },
+        Expression::NotEqual(x, y) => { let a = eval(x, state)?; Value::from_bool(a.compare(&eval(y, state)?).is_ne()) },
+        Expression::GreaterThan(x, y) => { let a = eval(x, state)?; Value::from_bool(a.compare(&eval(y, state)?).is_gt()) },
+        Expression::GreaterThanOrEq(x, y) => { let a = eval(x, state)?; Value::from_bool(a.compare(&eval(y, state)?).is_ge()) },
+        Expression::LessThan(x, y) => { let a = eval(x, state)?; Value::from_bool(a.compare(&eval(y, state)?).is_lt()) },
+        Expression::LessThanOrEq(x, y) => { let a = eval(x, state)?; Value::from_bool(a.compare(&eval(y, state)?).is_le()) },
+    })
+}
\ No newline at end of file
@@ -1 +1,6 @@
-pub mod ast;
\ No newline at end of file
+pub mod ast;
+pub mod visit;
+pub mod display;
+pub mod value;
+#[cfg(test)]
+pub mod interpret;
\ No newline at end of file
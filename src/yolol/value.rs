@@ -0,0 +1,91 @@
+use yolol_number::prelude::*;
+
+use super::ast::{ Expression };
+
+// A Yolol value. Operations on values behave the same in constant folding, compile time evaluation and the interpreter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(YololNumber),
+    Str(String)
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Value {
+        Value::Num(YololNumber::from_value(n))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+}
+
+impl Value {
+    pub fn from_expr(expr: &Expression) -> Option<Value> {
+        match expr {
+            Expression::ConstantNumber(n) => Some(Value::Num(*n)),
+            Expression::ConstantString(s) => Some(Value::Str(s.clone())),
+            _ => None
+        }
+    }
+
+    pub fn to_expr(self) -> Expression {
+        match self {
+            Value::Num(n) => Expression::ConstantNumber(n),
+            Value::Str(s) => Expression::ConstantString(s),
+        }
+    }
+
+    pub fn from_bool(b: bool) -> Value {
+        if b { Value::Num(YololNumber::one()) } else { Value::Num(YololNumber::zero()) }
+    }
+
+    // Any number except zero is true, strings cannot be used as conditions
+    pub fn truthy(&self) -> Option<bool> {
+        match self {
+            Value::Num(n) => Some(*n != YololNumber::zero()),
+            Value::Str(_) => None
+        }
+    }
+
+    // Adding anything to a string concatenates the two as strings
+    pub fn add(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
+            (a, b) => Value::Str(format!("{}{}", a, b))
+        }
+    }
+
+    // Subtracting from a string removes the last occurrence of the right hand side
+    pub fn subtract(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Value::Num(a - b),
+            (a, b) => {
+                let a = a.to_string();
+                let b = b.to_string();
+                Value::Str(match a.rfind(&b) {
+                    Some(i) => format!("{}{}", &a[..i], &a[i + b.len()..]),
+                    None => a
+                })
+            }
+        }
+    }
+
+    // Comparing a string with a number compares the number as a string
+    pub fn compare(&self, other: &Value) -> std::cmp::Ordering {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => a.cmp(b),
+            (a, b) => a.to_string().cmp(&b.to_string())
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,115 @@
+use super::ast::{ Statement, Expression, Identifier };
+
+// Call `f` on every expression node, parents are visited before their children
+pub fn visit_expr<F: FnMut(&Expression)>(expr: &Expression, f: &mut F) {
+    f(expr);
+    match expr {
+        Expression::ConstantNumber(_) => {},
+        Expression::ConstantString(_) => {},
+        Expression::VariableAccess(_) => {},
+        Expression::PostDecrement(_) => {},
+        Expression::PostIncrement(_) => {},
+        Expression::PreDecrement(_) => {},
+        Expression::PreIncrement(_) => {},
+
+        Expression::ACos(x) |
+        Expression::ASin(x) |
+        Expression::ATan(x) |
+        Expression::Sqrt(x) |
+        Expression::Cosine(x) |
+        Expression::Sine(x) |
+        Expression::Tangent(x) |
+        Expression::Bracket(x) |
+        Expression::Abs(x) |
+        Expression::Negate(x) |
+        Expression::Not(x) => visit_expr(x, f),
+
+        Expression::Add(x, y) |
+        Expression::And(x, y) |
+        Expression::Divide(x, y) |
+        Expression::Equal(x, y) |
+        Expression::Exponent(x, y) |
+        Expression::GreaterThan(x, y) |
+        Expression::GreaterThanOrEq(x, y) |
+        Expression::LessThan(x, y) |
+        Expression::LessThanOrEq(x, y) |
+        Expression::Modulus(x, y) |
+        Expression::Multiply(x, y) |
+        Expression::NotEqual(x, y) |
+        Expression::Or(x, y) |
+        Expression::Subtract(x, y) => { visit_expr(x, f); visit_expr(y, f); },
+    }
+}
+
+// Call `f` on every expression directly or indirectly contained in a statement
+pub fn visit_stmt_exprs<F: FnMut(&Expression)>(stmt: &Statement, f: &mut F) {
+    match stmt {
+        Statement::Assignment(_, value) => visit_expr(value, f),
+        Statement::CompoundAssignment(_, _, value) => visit_expr(value, f),
+        Statement::ExpressionWrapper(expr) => visit_expr(expr, f),
+        Statement::Goto(expr) => visit_expr(expr, f),
+        Statement::If(condition, pass, fail) => {
+            visit_expr(condition, f);
+            pass.statements.iter().for_each(|s| visit_stmt_exprs(s, f));
+            fail.statements.iter().for_each(|s| visit_stmt_exprs(s, f));
+        },
+        Statement::Empty() => {},
+        Statement::GotoLabel(_) => {},
+    }
+}
+
+pub fn visit_stmt_identifiers_mut<F: FnMut(&mut Identifier)>(stmt: &mut Statement, f: &mut F) {
+    match stmt {
+        Statement::Assignment(id, value) => { f(id); visit_expr_identifiers_mut(value, f); },
+        Statement::CompoundAssignment(id, _, value) => { f(id); visit_expr_identifiers_mut(value, f); },
+        Statement::ExpressionWrapper(expr) => visit_expr_identifiers_mut(expr, f),
+        Statement::Goto(expr) => visit_expr_identifiers_mut(expr, f),
+        Statement::If(condition, pass, fail) => {
+            visit_expr_identifiers_mut(condition, f);
+            pass.statements.iter_mut().for_each(|s| visit_stmt_identifiers_mut(s, f));
+            fail.statements.iter_mut().for_each(|s| visit_stmt_identifiers_mut(s, f));
+        },
+        Statement::Empty() => {},
+        Statement::GotoLabel(_) => {},
+    }
+}
+
+pub fn visit_expr_identifiers_mut<F: FnMut(&mut Identifier)>(expr: &mut Expression, f: &mut F) {
+    match expr {
+        Expression::ConstantNumber(_) => {},
+        Expression::ConstantString(_) => {},
+
+        Expression::VariableAccess(id) |
+        Expression::PostDecrement(id) |
+        Expression::PostIncrement(id) |
+        Expression::PreDecrement(id) |
+        Expression::PreIncrement(id) => f(id),
+
+        Expression::ACos(x) |
+        Expression::ASin(x) |
+        Expression::ATan(x) |
+        Expression::Sqrt(x) |
+        Expression::Cosine(x) |
+        Expression::Sine(x) |
+        Expression::Tangent(x) |
+        Expression::Bracket(x) |
+        Expression::Abs(x) |
+        Expression::Negate(x) |
+        Expression::Not(x) => visit_expr_identifiers_mut(x, f),
+
+        Expression::Add(x, y) |
+        Expression::And(x, y) |
+        Expression::Divide(x, y) |
+        Expression::Equal(x, y) |
+        Expression::Exponent(x, y) |
+        Expression::GreaterThan(x, y) |
+        Expression::GreaterThanOrEq(x, y) |
+        Expression::LessThan(x, y) |
+        Expression::LessThanOrEq(x, y) |
+        Expression::Modulus(x, y) |
+        Expression::Multiply(x, y) |
+        Expression::NotEqual(x, y) |
+        Expression::Or(x, y) |
+        Expression::Subtract(x, y) => { visit_expr_identifiers_mut(x, f); visit_expr_identifiers_mut(y, f); },
+    }
+}
\ No newline at end of file
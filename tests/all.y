@@ -2,7 +2,7 @@ import "file.y" in namespace;
 // import that thing
 import "x/y/z.y" in ns;
 //import "no/such/thing";
-import "lib/number_parser.y";
+import "std:number_parser";
 
 // Define an enum extending the number type
 type enum<number> struct_name {
@@ -17,15 +17,15 @@ type struct name {
 }
 
 // type range<number> positive => positive > 0;
-type range<number> negative => todo_range_expression;
+type range<number> negative -> todo_range_expression;
 
-def proc foo(foo: bar, bash:baz) { todo_body }
+def proc foo(foo: bar, bash:baz) { todo_body = 1; }
 def macro bar(copy bash:baz)
 {
-    todo_body
+    todo_body = 1;
 }
 def macro bar(bar:   foo, copy x:y) {
-    todo_body
+    todo_body = 1;
 }
 
 /* comment 
@@ -1,9 +0,0 @@
-const true:number = 1;
-const false:number = 0;
-
-const pi:number = 3.141;
-const tau:number = 6.282;
-const e:number = 2.718;
-
-const max_num:number = 9223372036854775.807;
-const min_num:number = -9223372036854775.808;
\ No newline at end of file
@@ -1,47 +0,0 @@
-def macro abs(a: number) -> number {
-    var r:number = 0;
-    emit { "r = abs a" };
-    return r;
-}
-
-def macro sqrt(a: number) -> number {
-    var r:number = 0;
-    emit { "r = sqrt a" };
-    return r;
-}
-
-def macro sin(a: number) -> number {
-    var r:number = 0;
-    emit { "r = sin a" };
-    return r;
-}
-
-def macro cos(a: number) -> number {
-    var r:number = 0;
-    emit { "r = cos a" };
-    return r;
-}
-
-def macro tan(a: number) -> number {
-    var r:number = 0;
-    emit { "r = tan a" };
-    return r;
-}
-
-def macro asin(a: number) -> number {
-    var r:number = 0;
-    emit { "r = asin a" };
-    return r;
-}
-
-def macro acos(a: number) -> number {
-    var r:number = 0;
-    emit { "r = acos a" };
-    return r;
-}
-
-def macro atan(a: number) -> number {
-    var r:number = 0;
-    emit { "r = atan a" };
-    return r;
-}
\ No newline at end of file
@@ -1,4 +1,4 @@
-type struct pid_constants {
+pub type struct pid_constants {
     p: number,
     i: number,
     d: number,
@@ -6,31 +6,33 @@ type struct pid_constants {
     r: number
 }
 
-type struct pid_state {
+pub type struct pid_state {
     previous_error:      number,
     previous_derivative: number,
     integrated_error:    number,
 }
 
-type struct pid {
+pub type struct pid {
     constants: pid_constants,
     state:     pid_state
 }
 
-def macro pid_update(p:number, i:number, d:number, target: number, measurement: number) -> number {
+impl pid {
+    pub def macro update(self, target: number, measurement: number) -> number {
 
-    // Calculate proportional error
-    var error:number = target - measurement;
+        // Calculate proportional error
+        var error:number = target - measurement;
 
-    // Calculate integrated error
-    //pid.integrated_error += error;
+        // Calculate integrated error
+        self.state.integrated_error = self.state.integrated_error + error;
 
-    // Calculate error gradient
-    //var dedt:number = (pid.state.previous_error - error) * (1 - pid.r) + pid.state.previous_derivative * pid.r;
-    //pid.state.previous_derivative = dedt;
-    //pid.state.previous_error = error;
+        // Calculate error gradient
+        var dedt:number = (self.state.previous_error - error) * (1 - self.constants.r) + self.state.previous_derivative * self.constants.r;
+        self.state.previous_derivative = dedt;
+        self.state.previous_error = error;
 
-    //return pid.p * error + pid.i * pid.integrated_error + pid.d * dedt;
+        return self.constants.p * error + self.constants.i * self.state.integrated_error + self.constants.d * dedt;
+    }
 }
 
 main {
@@ -44,6 +46,7 @@ main {
             p: 1,
             i: 0.1,
             d: 0.01,
+            r: 0.5
         },
         state: {
             previous_error: :target - :input,
@@ -53,7 +56,7 @@ main {
     };
 
     line(loop_start) {
-        :output = pid_update(pid, :target, :input);
+        :output = controller.update(:target, :input);
         goto loop_start;
     };
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-type struct name2 {
+pub type struct name2 {
     foo : bar,
     bash: baz,
     buzz: bizz